@@ -1,5 +1,452 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Flash layout with the in-application updater, must match `src/iap.rs`
+const IAP_APP_SIZE_KB: u32 = 31;
+
+/// Tuning constants of `src/config.rs`: name, type, default and description,
+/// see [`tuning`]
+const TUNING: &[(&str, &str, &str, &str)] = &[
+    (
+        "MAX_TEMPERATURE",
+        "f32",
+        "55.0",
+        "Pipe temperature the valve regulates to in °C",
+    ),
+    (
+        "TEMP_HYSTERESIS",
+        "f32",
+        "5.0",
+        "Pipe temperature band below the setpoint in K",
+    ),
+    (
+        "OVERTEMPERATURE_MARGIN",
+        "f32",
+        "15.0",
+        "Margin above the setpoint counting as overtemperature in K",
+    ),
+    (
+        "ROOM_SETPOINT",
+        "f32",
+        "21.0",
+        "Room temperature setpoint of the room sensors in °C",
+    ),
+    (
+        "ROOM_HYSTERESIS",
+        "f32",
+        "0.5",
+        "Room temperature band below the setpoint in K",
+    ),
+    (
+        "MAX_MOVE_TIME",
+        "u64",
+        "13",
+        "Full travel time of the valve actuator in s",
+    ),
+    (
+        "STEP_MOVE_TIME",
+        "u64",
+        "1",
+        "Time of a single regulation step in s",
+    ),
+    (
+        "WAIT_TIME_S",
+        "u64",
+        "120",
+        "Time between regulation steps in s",
+    ),
+    (
+        "STALE_TIMEOUT_S",
+        "u64",
+        "600",
+        "Time without a reading before the failsafe takes over in s",
+    ),
+    (
+        "FAILSAFE_POSITION",
+        "u8",
+        "20",
+        "Valve opening held without readings in %",
+    ),
+    (
+        "LOCKOUT_SETTLE_S",
+        "u64",
+        "900",
+        "Time the valve is closed before overtemperature counts as a failure in s",
+    ),
+    (
+        "MAX_RUNNING",
+        "u8",
+        "1",
+        "Valve actuators running at the same time",
+    ),
+    (
+        "STAGGER_MS",
+        "u64",
+        "2000",
+        "Time between two motor starts in ms",
+    ),
+    ("NTC_BETA", "f32", "5800.0", "B constant of the NTC in K"),
+    ("NTC_R25", "f32", "10000.0", "NTC resistance at 25 °C in Ω"),
+    (
+        "NTC_R_PULL",
+        "f32",
+        "10000.0",
+        "Pull-down resistor of the NTC in Ω",
+    ),
+    (
+        "ACTION",
+        "crate::motor_control::Action",
+        "direct",
+        "How the temperature responds to opening the valve",
+    ),
+    (
+        "DEMAND_MODE",
+        "crate::demand::DemandMode",
+        "setpoint",
+        "What the external demand controls",
+    ),
+    (
+        "LOG_LEVEL",
+        "crate::log::Level",
+        "trace",
+        "Log level of every module after a restart",
+    ),
+];
+
+/// Enum type of tuning constants.
+struct Choice {
+    path: &'static str,
+    /// Feature the type exists with
+    feature: Option<&'static str>,
+    /// Setting values and the variants they select
+    variants: &'static [(&'static str, &'static str)],
+}
+
+const CHOICES: &[Choice] = &[
+    Choice {
+        path: "crate::motor_control::Action",
+        feature: None,
+        variants: &[("direct", "Direct"), ("reverse", "Reverse")],
+    },
+    Choice {
+        path: "crate::demand::DemandMode",
+        feature: Some("demand"),
+        variants: &[
+            ("setpoint", "Setpoint"),
+            ("valve-position", "ValvePosition"),
+        ],
+    },
+    Choice {
+        path: "crate::log::Level",
+        feature: None,
+        variants: &[
+            ("off", "Off"),
+            ("error", "Error"),
+            ("warn", "Warn"),
+            ("info", "Info"),
+            ("debug", "Debug"),
+            ("trace", "Trace"),
+        ],
+    },
+];
+
+// Length and CRC of the image after everything else in flash, the CRC is
+// filled in by `tools/seal_image.py` and checked by `src/image.rs`
+const IMAGE_FOOTER: &str = "
+SECTIONS
+{
+    .image_footer : ALIGN(4)
+    {
+        __image_footer = .;
+        LONG(__image_footer - ORIGIN(FLASH));
+        LONG(0xFFFFFFFF);
+    } > FLASH
+} INSERT AFTER .gnu.sgstubs;
+";
+
+fn git_hash() -> String {
+    // Rebuild when a commit is made or another branch checked out
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Ok(head) = fs::read_to_string(".git/HEAD")
+        && let Some(reference) = head.trim().strip_prefix("ref: ")
+    {
+        println!("cargo:rerun-if-changed=.git/{reference}");
+    }
+
+    Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// Build time as UTC `YYYY-MM-DD HH:MM`, `SOURCE_DATE_EPOCH` makes it reproducible.
+fn build_time() -> String {
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let seconds = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        });
+
+    // Civil date from days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        seconds / 3600 % 24,
+        seconds / 60 % 60
+    )
+}
+
+/// Shared key of the `auth` feature from `AUTH_KEY` (64 hex digits).
+fn auth_key(out: &Path) {
+    println!("cargo:rerun-if-env-changed=AUTH_KEY");
+    let hex = env::var("AUTH_KEY").unwrap_or_default();
+    let key: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .filter_map(|i| hex.get(i..i + 2))
+        .filter_map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect();
+    if hex.len() != 64 || key.len() != 32 {
+        panic!("the `auth` feature needs AUTH_KEY set to a 256-bit key as 64 hex digits");
+    }
+
+    fs::write(out.join("auth_key.rs"), format!("{key:?}")).unwrap();
+}
+
+/// Variant of the enum `kind` named `value` in the settings.
+fn choice(kind: &str, value: &str) -> Option<String> {
+    let choice = CHOICES.iter().find(|choice| choice.path == kind)?;
+    choice
+        .variants
+        .iter()
+        .find(|(name, _)| *name == value)
+        .map(|(_, variant)| format!("{kind}::{variant}"))
+}
+
+/// Tuning constants from `heat_doors.toml` lines `name = value`, overridden
+/// by `HEAT_DOORS_<NAME>` environment variables.
+fn tuning(out: &Path) {
+    println!("cargo:rerun-if-changed=heat_doors.toml");
+    let file = fs::read_to_string("heat_doors.toml").unwrap_or_default();
+    let mut values = HashMap::new();
+    for (number, line) in file.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            panic!("heat_doors.toml:{}: expected `name = value`", number + 1);
+        };
+        let name = name.trim().to_uppercase();
+        if !TUNING.iter().any(|(known, ..)| *known == name) {
+            panic!(
+                "heat_doors.toml:{}: unknown setting `{}`",
+                number + 1,
+                name.to_lowercase()
+            );
+        }
+        values.insert(name, value.trim().to_owned());
+    }
+
+    let mut config = String::new();
+    for (name, kind, default, description) in TUNING {
+        let variable = format!("HEAT_DOORS_{name}");
+        println!("cargo:rerun-if-env-changed={variable}");
+        let value = env::var(&variable)
+            .ok()
+            .or_else(|| values.remove(*name))
+            .unwrap_or_else(|| default.to_string());
+        // Normalized through the parsed value, so any valid number is a valid literal
+        let literal = match *kind {
+            "f32" => value
+                .parse::<f32>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .map(|value| format!("{value:?}")),
+            "u8" | "u64" => value.parse::<u64>().ok().map(|value| value.to_string()),
+            _ => choice(kind, &value),
+        }
+        .unwrap_or_else(|| panic!("{}: `{value}` is not a valid {kind}", name.to_lowercase()));
+        writeln!(config, "/// {description}").unwrap();
+        let feature = CHOICES
+            .iter()
+            .find(|choice| choice.path == *kind)
+            .and_then(|choice| choice.feature);
+        if let Some(feature) = feature {
+            writeln!(config, "#[cfg(feature = \"{feature}\")]").unwrap();
+        }
+        writeln!(config, "pub const {name}: {kind} = {literal};").unwrap();
+    }
+
+    // Every variant can be selected, so none of them is dead code in a build
+    // that selects another one
+    for choice in CHOICES {
+        if let Some(feature) = choice.feature {
+            writeln!(config, "#[cfg(feature = \"{feature}\")]").unwrap();
+        }
+        let variants: Vec<String> = choice
+            .variants
+            .iter()
+            .map(|(_, variant)| format!("{}::{variant}", choice.path))
+            .collect();
+        writeln!(
+            config,
+            "const _: [{}; {}] = [{}];",
+            choice.path,
+            variants.len(),
+            variants.join(", ")
+        )
+        .unwrap();
+    }
+
+    fs::write(out.join("config.rs"), config).unwrap();
+}
+
+/// Enabled cargo features as a space separated list.
+fn features() -> String {
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    features.join(" ")
+}
+
+/// Memory of a supported chip, must match `src/chip.rs`.
+struct Chip {
+    /// Cargo feature selecting it
+    feature: &'static str,
+    /// Family module of `src/chip.rs`, set as the `family` cfg
+    family: &'static str,
+    flash_kb: u32,
+    ram_kb: u32,
+    /// Flash erase unit in bytes
+    page_size: u32,
+}
+
+const CHIPS: &[Chip] = &[
+    Chip {
+        feature: "STM32F103C8",
+        family: "f1",
+        flash_kb: 64,
+        ram_kb: 20,
+        page_size: 1024,
+    },
+    Chip {
+        feature: "STM32F103CB",
+        family: "f1",
+        flash_kb: 128,
+        ram_kb: 20,
+        page_size: 1024,
+    },
+    Chip {
+        feature: "STM32F103RB",
+        family: "f1",
+        flash_kb: 128,
+        ram_kb: 20,
+        page_size: 1024,
+    },
+    Chip {
+        feature: "STM32F030C8",
+        family: "f0",
+        flash_kb: 64,
+        ram_kb: 8,
+        page_size: 1024,
+    },
+    Chip {
+        feature: "STM32G030C8",
+        family: "g0",
+        flash_kb: 64,
+        ram_kb: 8,
+        page_size: 2048,
+    },
+    Chip {
+        feature: "STM32L053C8",
+        family: "l0",
+        flash_kb: 64,
+        ram_kb: 8,
+        page_size: 128,
+    },
+];
+
+/// The chip selected by its cargo feature.
+fn chip() -> &'static Chip {
+    CHIPS
+        .iter()
+        .find(|chip| env::var_os(format!("CARGO_FEATURE_{}", chip.feature)).is_some())
+        .expect("select a chip with its cargo feature, e.g. `stm32f103c8`")
+}
+
 fn main() {
+    let chip = chip();
+    println!("cargo::rustc-check-cfg=cfg(family, values(\"f0\", \"f1\", \"g0\", \"l0\"))");
+    println!("cargo:rustc-cfg=family=\"{}\"", chip.family);
+
+    let flash = if env::var_os("CARGO_FEATURE_IAP").is_some() {
+        IAP_APP_SIZE_KB * 1024
+    } else {
+        // Two pages on top per persistent log, see `flash::log_pages`
+        let logs = ["AUTH", "ENERGY", "FACTORY"]
+            .iter()
+            .filter(|feature| env::var_os(format!("CARGO_FEATURE_{feature}")).is_some())
+            .count() as u32;
+        chip.flash_kb * 1024 - 2 * logs * chip.page_size
+    };
+    let ram_kb = chip.ram_kb;
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(
+        out.join("memory.x"),
+        format!(
+            "MEMORY\n{{\n    FLASH : ORIGIN = 0x08000000, LENGTH = {flash}\n    RAM   : ORIGIN = 0x20000000, LENGTH = {ram_kb}K\n}}\n{IMAGE_FOOTER}"
+        ),
+    )
+    .unwrap();
+
+    if env::var_os("CARGO_FEATURE_AUTH").is_some() {
+        auth_key(&out);
+    }
+    tuning(&out);
+
+    println!("cargo:rustc-env=GIT_HASH={}", git_hash());
+    println!("cargo:rustc-env=BUILD_TIME={}", build_time());
+    println!("cargo:rustc-env=FEATURES={}", features());
+
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
-    println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    if env::var_os("CARGO_FEATURE_DEFMT").is_some() {
+        println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    }
 }
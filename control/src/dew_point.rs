@@ -0,0 +1,25 @@
+//! Dew point of the room air and the condensation risk of a cooled pipe.
+
+// Called as a function, std's own `ln` would shadow it in host builds
+use micromath::F32Ext;
+
+use crate::regulation::Action;
+
+/// Distance to keep from the dew point in °C
+pub const MARGIN: f32 = 2.0;
+// Magnus formula coefficients for water, valid from -45 to 60 °C
+const MAGNUS_B: f32 = 17.62;
+const MAGNUS_C: f32 = 243.12;
+
+/// Dew point of air at `temperature` with relative `humidity` in %.
+pub fn dew_point(temperature: f32, humidity: f32) -> f32 {
+    let gamma =
+        F32Ext::ln(humidity.max(1.0) / 100.0) + MAGNUS_B * temperature / (MAGNUS_C + temperature);
+    MAGNUS_C * gamma / (MAGNUS_B - gamma)
+}
+
+/// Whether more chilled water would cool the pipe towards the dew point.
+pub fn risk(action: Action, pipe_temperature: f32, dew_point: f32) -> bool {
+    // Unknown temperatures compare false and do not block the valve
+    action == Action::Reverse && pipe_temperature < dew_point + MARGIN
+}
@@ -0,0 +1,20 @@
+//! Regulation logic of heat-dooRS without any hardware access.
+//!
+//! Kept in its own crate so it builds for the host as well, where
+//! `cargo test -p heat-control` checks it without flashing a board.
+
+#![no_std]
+
+pub mod dew_point;
+pub mod ntc;
+pub mod regulation;
+pub mod starts;
+pub mod travel;
+
+/// Movement of the valve motor.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum MotorStatus {
+    Off,
+    Opening,
+    Closing,
+}
@@ -0,0 +1,34 @@
+//! NTC divider readings to temperatures.
+
+// Called as a function, std's own `ln` would shadow it in host builds
+use micromath::F32Ext;
+
+const ADC_MAX: f32 = 4095.0;
+const T0: f32 = 298.15; // 25°C v K
+
+/// NTC to VCC with a pull-down to GND, read by a 12-bit ADC.
+pub struct Divider {
+    /// B constant in K
+    pub beta: f32,
+    /// Resistance at 25 °C in Ω
+    pub r25: f32,
+    /// Pull-down resistor in Ω
+    pub r_pull: f32,
+}
+
+impl Divider {
+    /// Temperature in °C, NaN for the codes of an open or shorted NTC.
+    pub fn temperature_c(&self, adc: u16) -> f32 {
+        if adc == 0 || adc as f32 >= ADC_MAX {
+            return f32::NAN;
+        }
+
+        let adc_f = adc as f32;
+
+        let r_ntc = self.r_pull * (ADC_MAX - adc_f) / adc_f;
+
+        let inv_t = (1.0 / T0) + (1.0 / self.beta) * F32Ext::ln(r_ntc / self.r25);
+
+        (1.0 / inv_t) - 273.15
+    }
+}
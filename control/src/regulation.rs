@@ -0,0 +1,132 @@
+//! Hysteresis state machine of the regulation.
+//!
+//! Temperatures and setpoints are passed mirrored for reverse action, so a
+//! value above the setpoint always closes the valve. The valve starts fully
+//! open, closes fully when the temperature passes the setpoint and opens
+//! fully again once it dropped below the hysteresis band. Within the band it
+//! follows the temperature one step at a time.
+
+/// How the temperature responds to opening the valve.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Action {
+    /// Heating, the valve opens on falling temperature
+    Direct,
+    /// Cooling with chilled water, the valve opens on rising temperature
+    Reverse,
+}
+
+impl Action {
+    /// Mirror a temperature for reverse action, so the regulation can treat
+    /// both the same: a value above the mirrored setpoint closes the valve.
+    pub fn regulated(self, temperature: f32) -> f32 {
+        match self {
+            Action::Direct => temperature,
+            Action::Reverse => -temperature,
+        }
+    }
+}
+
+/// Phase of the regulation.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum HeatingStatus {
+    /// Position unknown, the valve has to be opened fully first
+    Off,
+    Heating,
+    /// Closed after passing the setpoint, waiting to drop below the band
+    Cooling,
+}
+
+/// Valve move asked for by a reading.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Step {
+    /// Open fully to start from a known position
+    Calibrate,
+    /// Below the band again after cooling, open fully
+    Resume,
+    /// Above the setpoint, close fully
+    Overheat,
+    /// Still cooling down, the valve stays closed
+    Cooling,
+    /// Below the band while heating, the valve stays open
+    TooLow,
+    /// Rising within the band, close one step
+    Close,
+    /// Falling within the band, open one step
+    Open,
+    /// Unchanged within the band
+    Hold,
+}
+
+pub struct Regulator {
+    status: HeatingStatus,
+    /// Reading of the last step move
+    last_temp: f32,
+}
+
+impl Default for Regulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Regulator {
+    pub const fn new() -> Self {
+        Self {
+            status: HeatingStatus::Off,
+            last_temp: 0.0,
+        }
+    }
+
+    pub fn status(&self) -> HeatingStatus {
+        self.status
+    }
+
+    pub fn last_temp(&self) -> f32 {
+        self.last_temp
+    }
+
+    /// Start over from a fully open valve, after the valve was moved by
+    /// something else.
+    pub fn restart(&mut self) {
+        self.status = HeatingStatus::Off;
+    }
+
+    /// Decide the move for a new reading.
+    pub fn step(&mut self, temp: f32, setpoint: f32, hysteresis: f32) -> Step {
+        match self.status {
+            HeatingStatus::Off => Step::Calibrate,
+            HeatingStatus::Cooling => {
+                self.last_temp = temp;
+                if temp < setpoint - hysteresis {
+                    self.status = HeatingStatus::Heating;
+                    Step::Resume
+                } else {
+                    Step::Cooling
+                }
+            }
+            HeatingStatus::Heating => {
+                if temp > setpoint {
+                    self.status = HeatingStatus::Cooling;
+                    Step::Overheat
+                } else if temp < setpoint - hysteresis {
+                    Step::TooLow
+                } else if temp > self.last_temp {
+                    Step::Close
+                } else if temp < self.last_temp {
+                    Step::Open
+                } else {
+                    Step::Hold
+                }
+            }
+        }
+    }
+
+    /// The move of `step` for the reading `temp` was carried out.
+    pub fn moved(&mut self, step: Step, temp: f32) {
+        match step {
+            Step::Calibrate => self.status = HeatingStatus::Heating,
+            Step::Close | Step::Open => self.last_temp = temp,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Start permits of the motors sharing one supply.
+//!
+//! Starts are spaced by the stagger time so the inrush currents do not add
+//! up, and only a limited number of valve actuators run at once. A motor
+//! not taking a running slot, like the circulation pump, is staggered only.
+//! Waiting motors are not queued, the first to ask once the supply allows
+//! another start gets it.
+
+/// Why a start has to wait.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Wait {
+    /// Every running slot is taken, until one is released
+    Slot,
+    /// Too close to the previous start, until the given time in ms
+    Until(u64),
+}
+
+pub struct Starts {
+    /// Valve actuators running at the same time
+    max_running: usize,
+    /// Time between two starts in ms
+    stagger_ms: u64,
+    running: usize,
+    /// Time of the last start in ms
+    last_start: Option<u64>,
+}
+
+impl Starts {
+    pub const fn new(max_running: usize, stagger_ms: u64) -> Self {
+        Self {
+            max_running,
+            stagger_ms,
+            running: 0,
+            last_start: None,
+        }
+    }
+
+    /// Start a motor at `now_ms`, taking a running slot if `slot` is set.
+    pub fn start(&mut self, now_ms: u64, slot: bool) -> Result<(), Wait> {
+        if slot && self.running >= self.max_running {
+            return Err(Wait::Slot);
+        }
+        if let Some(last) = self.last_start {
+            let next = last + self.stagger_ms;
+            if now_ms < next {
+                return Err(Wait::Until(next));
+            }
+        }
+
+        self.last_start = Some(now_ms);
+        if slot {
+            self.running += 1;
+        }
+        Ok(())
+    }
+
+    /// A motor holding a running slot stopped.
+    pub fn stop(&mut self) {
+        self.running = self.running.saturating_sub(1);
+    }
+
+    /// Valve actuators running.
+    pub fn running(&self) -> usize {
+        self.running
+    }
+}
@@ -0,0 +1,75 @@
+//! Travel accounting of the valve actuator.
+//!
+//! The actuator has no position feedback. The position is estimated from
+//! the running time, and the time run in one direction is limited to the
+//! full travel so the motor does not keep pushing against an end stop. A
+//! move in the other direction starts the count over.
+
+use crate::MotorStatus;
+
+pub struct Travel {
+    /// Full travel time in s
+    full_travel: u64,
+    /// Direction of the last finished move
+    last_move: MotorStatus,
+    /// Time moved in the last direction in s
+    total_movement_time: u64,
+    /// Estimated opening in ms of travel, 0 = fully closed
+    position_ms: u64,
+}
+
+impl Travel {
+    pub const fn new(full_travel: u64) -> Self {
+        Self {
+            full_travel,
+            last_move: MotorStatus::Off,
+            total_movement_time: 0,
+            position_ms: 0,
+        }
+    }
+
+    /// Whether a move in `direction` stays within the full travel.
+    pub fn can_move(&self, direction: MotorStatus) -> bool {
+        let total_time = if direction != self.last_move {
+            0
+        } else {
+            self.total_movement_time
+        };
+
+        total_time < self.full_travel
+    }
+
+    /// A move in `direction` starts.
+    pub fn start(&mut self, direction: MotorStatus) {
+        if self.last_move != direction {
+            self.total_movement_time = 0;
+        }
+    }
+
+    /// The move in `direction` ended after `elapsed_ms`.
+    pub fn stop(&mut self, direction: MotorStatus, elapsed_ms: u64) {
+        if direction == MotorStatus::Off {
+            return;
+        }
+
+        self.last_move = direction;
+        self.total_movement_time += elapsed_ms / 1000;
+
+        let full_travel_ms = self.full_travel * 1000;
+        self.position_ms = match direction {
+            MotorStatus::Opening => (self.position_ms + elapsed_ms).min(full_travel_ms),
+            MotorStatus::Closing => self.position_ms.saturating_sub(elapsed_ms),
+            MotorStatus::Off => self.position_ms,
+        };
+    }
+
+    /// Estimated opening in ms of travel.
+    pub fn position_ms(&self) -> u64 {
+        self.position_ms
+    }
+
+    /// Estimated opening in %.
+    pub fn position(&self) -> u8 {
+        (self.position_ms * 100 / (self.full_travel * 1000)) as u8
+    }
+}
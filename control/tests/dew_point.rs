@@ -0,0 +1,44 @@
+use heat_control::dew_point::{MARGIN, dew_point, risk};
+use heat_control::regulation::Action;
+use proptest::prelude::*;
+
+fn temperature() -> impl Strategy<Value = f32> {
+    (0..400).prop_map(|tenths| tenths as f32 / 10.0)
+}
+
+#[test]
+fn saturated_air_condenses_at_its_temperature() {
+    assert!((dew_point(20.0, 100.0) - 20.0).abs() < 0.01);
+    // Reference value of the Magnus formula
+    assert!((dew_point(25.0, 50.0) - 13.85).abs() < 0.1);
+}
+
+#[test]
+fn unknown_temperatures_do_not_block() {
+    assert!(!risk(Action::Reverse, f32::NAN, 15.0));
+    assert!(!risk(Action::Reverse, 10.0, f32::NAN));
+}
+
+proptest! {
+    #[test]
+    fn dew_point_is_below_the_air(temp in temperature(), humidity in 1.0f32..99.0) {
+        prop_assert!(dew_point(temp, humidity) < temp);
+    }
+
+    #[test]
+    fn heating_is_never_at_risk(pipe in temperature(), dew in temperature()) {
+        prop_assert!(!risk(Action::Direct, pipe, dew));
+    }
+
+    #[test]
+    fn cooling_keeps_the_margin(pipe in temperature(), dew in temperature()) {
+        prop_assert_eq!(risk(Action::Reverse, pipe, dew), pipe < dew + MARGIN);
+    }
+
+    #[test]
+    fn cooling_stops_near_the_dew_point(temp in temperature(), humidity in 30.0f32..90.0) {
+        let dew = dew_point(temp, humidity);
+        prop_assert!(risk(Action::Reverse, dew + MARGIN / 2.0, dew));
+        prop_assert!(!risk(Action::Reverse, dew + MARGIN * 2.0, dew));
+    }
+}
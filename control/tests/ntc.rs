@@ -0,0 +1,39 @@
+use heat_control::ntc::Divider;
+use proptest::prelude::*;
+
+/// The defaults of the firmware
+const DIVIDER: Divider = Divider {
+    beta: 5800.0,
+    r25: 10000.0,
+    r_pull: 10000.0,
+};
+
+#[test]
+fn open_or_shorted_codes_are_nan() {
+    assert!(DIVIDER.temperature_c(0).is_nan());
+    assert!(DIVIDER.temperature_c(4095).is_nan());
+    assert!(DIVIDER.temperature_c(u16::MAX).is_nan());
+}
+
+#[test]
+fn half_scale_is_25_c() {
+    assert!((DIVIDER.temperature_c(2048) - 25.0).abs() < 0.1);
+}
+
+#[test]
+fn codes_next_to_the_rails_are_finite() {
+    assert!(DIVIDER.temperature_c(1).is_finite());
+    assert!(DIVIDER.temperature_c(4094).is_finite());
+}
+
+proptest! {
+    #[test]
+    fn rises_with_the_code(adc in 1u16..4094) {
+        prop_assert!(DIVIDER.temperature_c(adc + 1) > DIVIDER.temperature_c(adc));
+    }
+
+    #[test]
+    fn valid_codes_give_finite_temperatures(adc in 1u16..4095) {
+        prop_assert!(DIVIDER.temperature_c(adc).is_finite());
+    }
+}
@@ -0,0 +1,126 @@
+use heat_control::regulation::{HeatingStatus, Regulator, Step};
+use proptest::prelude::*;
+
+const SETPOINT: f32 = 55.0;
+const HYSTERESIS: f32 = 5.0;
+
+fn temperature() -> impl Strategy<Value = f32> {
+    (0..1000).prop_map(|tenths| tenths as f32 / 10.0)
+}
+
+/// Readings within the hysteresis band
+fn band() -> impl Strategy<Value = f32> {
+    (500..=550).prop_map(|tenths| tenths as f32 / 10.0)
+}
+
+/// Run a reading through the regulator with every move succeeding.
+fn feed(regulator: &mut Regulator, temp: f32) -> Step {
+    let step = regulator.step(temp, SETPOINT, HYSTERESIS);
+    regulator.moved(step, temp);
+    step
+}
+
+/// A regulator heating, with its last step at `last_temp` within the band.
+fn heating(last_temp: f32) -> Regulator {
+    let mut regulator = Regulator::new();
+    feed(&mut regulator, last_temp);
+    feed(&mut regulator, last_temp);
+    assert_eq!(regulator.last_temp(), last_temp);
+    regulator
+}
+
+#[test]
+fn starts_by_opening_fully() {
+    let mut regulator = Regulator::new();
+    assert_eq!(regulator.status(), HeatingStatus::Off);
+    assert_eq!(feed(&mut regulator, 20.0), Step::Calibrate);
+    assert_eq!(regulator.status(), HeatingStatus::Heating);
+}
+
+#[test]
+fn restart_calibrates_again() {
+    let mut regulator = heating(52.0);
+    regulator.restart();
+    assert_eq!(feed(&mut regulator, 50.0), Step::Calibrate);
+}
+
+proptest! {
+    #[test]
+    fn calibrates_until_the_valve_opened(temps in prop::collection::vec(temperature(), 1..10)) {
+        let mut regulator = Regulator::new();
+        for temp in temps {
+            // The opening fails, e.g. the travel limit was reached
+            prop_assert_eq!(regulator.step(temp, SETPOINT, HYSTERESIS), Step::Calibrate);
+            prop_assert_eq!(regulator.status(), HeatingStatus::Off);
+        }
+    }
+
+    #[test]
+    fn closes_fully_above_the_setpoint(last in band(), temp in temperature()) {
+        let mut regulator = heating(last);
+        let step = feed(&mut regulator, temp);
+        if temp > SETPOINT {
+            prop_assert_eq!(step, Step::Overheat);
+            prop_assert_eq!(regulator.status(), HeatingStatus::Cooling);
+        } else {
+            prop_assert_eq!(regulator.status(), HeatingStatus::Heating);
+        }
+    }
+
+    #[test]
+    fn keeps_open_below_the_band(last in band(), temp in temperature()) {
+        prop_assume!(temp < SETPOINT - HYSTERESIS);
+        let mut regulator = heating(last);
+        prop_assert_eq!(feed(&mut regulator, temp), Step::TooLow);
+    }
+
+    #[test]
+    fn steps_against_the_change_within_the_band(last in band(), temp in band()) {
+        let mut regulator = heating(last);
+        let expected = if temp > last {
+            Step::Close
+        } else if temp < last {
+            Step::Open
+        } else {
+            Step::Hold
+        };
+        prop_assert_eq!(feed(&mut regulator, temp), expected);
+    }
+
+    #[test]
+    fn a_failed_step_is_retried(last in band(), temp in band()) {
+        prop_assume!(temp != last);
+        let mut regulator = heating(last);
+        let step = regulator.step(temp, SETPOINT, HYSTERESIS);
+        // Not moved, the reading is compared to the same one again
+        prop_assert_eq!(regulator.step(temp, SETPOINT, HYSTERESIS), step);
+        prop_assert_eq!(regulator.last_temp(), last);
+    }
+
+    #[test]
+    fn cools_down_through_the_whole_band(temps in prop::collection::vec(temperature(), 1..20)) {
+        let mut regulator = heating(SETPOINT);
+        feed(&mut regulator, SETPOINT + 1.0);
+        for temp in temps {
+            let step = feed(&mut regulator, temp);
+            if temp < SETPOINT - HYSTERESIS {
+                prop_assert_eq!(step, Step::Resume);
+                prop_assert_eq!(regulator.status(), HeatingStatus::Heating);
+                break;
+            }
+            prop_assert_eq!(step, Step::Cooling);
+            prop_assert_eq!(regulator.status(), HeatingStatus::Cooling);
+        }
+    }
+
+    #[test]
+    fn never_steps_outside_the_band(temps in prop::collection::vec(temperature(), 1..50)) {
+        let mut regulator = Regulator::new();
+        for temp in temps {
+            let step = feed(&mut regulator, temp);
+            if matches!(step, Step::Open | Step::Close) {
+                prop_assert!((SETPOINT - HYSTERESIS..=SETPOINT).contains(&temp));
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use heat_control::starts::{Starts, Wait};
+use proptest::prelude::*;
+
+const STAGGER_MS: u64 = 2000;
+
+#[test]
+fn staggers_the_actuators_and_waits_for_a_slot() {
+    let mut starts = Starts::new(2, STAGGER_MS);
+    assert_eq!(starts.start(0, true), Ok(()));
+    assert_eq!(starts.start(0, true), Err(Wait::Until(STAGGER_MS)));
+    assert_eq!(starts.start(STAGGER_MS, true), Ok(()));
+
+    // Both slots taken, the third waits past the stagger
+    assert_eq!(starts.start(3 * STAGGER_MS, true), Err(Wait::Slot));
+    starts.stop();
+    assert_eq!(starts.start(3 * STAGGER_MS, true), Ok(()));
+    assert_eq!(starts.running(), 2);
+}
+
+#[test]
+fn pump_is_staggered_without_a_slot() {
+    let mut starts = Starts::new(1, STAGGER_MS);
+    assert_eq!(starts.start(0, true), Ok(()));
+    assert_eq!(starts.start(500, false), Err(Wait::Until(STAGGER_MS)));
+    assert_eq!(starts.start(STAGGER_MS, false), Ok(()));
+    assert_eq!(starts.start(STAGGER_MS, true), Err(Wait::Slot));
+
+    // A released slot still waits for the stagger after the pump
+    starts.stop();
+    assert_eq!(
+        starts.start(STAGGER_MS + 1, true),
+        Err(Wait::Until(2 * STAGGER_MS))
+    );
+    assert_eq!(starts.running(), 0);
+}
+
+proptest! {
+    /// Actuators asking in turn, each running for a while: starts stay
+    /// apart by the stagger and never more than the slots run at once.
+    #[test]
+    fn starts_stay_apart_and_within_the_slots(
+        max_running in 1usize..4,
+        run_ms in prop::collection::vec(0..10 * STAGGER_MS, 1..16),
+    ) {
+        let mut starts = Starts::new(max_running, STAGGER_MS);
+        let mut now = 0;
+        let mut last = None;
+        // Stop times of the running actuators
+        let mut running: Vec<u64> = Vec::new();
+        for duration in run_ms {
+            loop {
+                running.retain(|&stop| {
+                    if stop <= now {
+                        starts.stop();
+                    }
+                    stop > now
+                });
+                match starts.start(now, true) {
+                    Ok(()) => break,
+                    Err(Wait::Until(at)) => {
+                        prop_assert!(at > now);
+                        now = at;
+                    }
+                    Err(Wait::Slot) => {
+                        prop_assert_eq!(running.len(), max_running);
+                        now = *running.iter().min().unwrap();
+                    }
+                }
+            }
+            if let Some(last) = last {
+                prop_assert!(now >= last + STAGGER_MS);
+            }
+            last = Some(now);
+            running.push(now + duration);
+            prop_assert!(running.len() <= max_running);
+            prop_assert_eq!(starts.running(), running.len());
+        }
+    }
+}
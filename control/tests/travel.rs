@@ -0,0 +1,106 @@
+use heat_control::MotorStatus;
+use heat_control::travel::Travel;
+use proptest::prelude::*;
+
+const FULL_TRAVEL: u64 = 13;
+
+fn direction() -> impl Strategy<Value = MotorStatus> {
+    prop_oneof![Just(MotorStatus::Opening), Just(MotorStatus::Closing)]
+}
+
+/// Moves of up to twice the full travel, in ms.
+fn moves() -> impl Strategy<Value = Vec<(MotorStatus, u64)>> {
+    prop::collection::vec((direction(), 0..2 * FULL_TRAVEL * 1000), 0..32)
+}
+
+fn run(travel: &mut Travel, direction: MotorStatus, elapsed_ms: u64) {
+    travel.start(direction);
+    travel.stop(direction, elapsed_ms);
+}
+
+#[test]
+fn starts_closed_and_free_to_move() {
+    let travel = Travel::new(FULL_TRAVEL);
+    assert_eq!(travel.position(), 0);
+    assert!(travel.can_move(MotorStatus::Opening));
+    assert!(travel.can_move(MotorStatus::Closing));
+}
+
+#[test]
+fn stops_at_the_full_travel() {
+    let mut travel = Travel::new(FULL_TRAVEL);
+    run(&mut travel, MotorStatus::Opening, (FULL_TRAVEL - 1) * 1000);
+    assert!(travel.can_move(MotorStatus::Opening));
+    run(&mut travel, MotorStatus::Opening, 1000);
+    assert!(!travel.can_move(MotorStatus::Opening));
+    assert!(travel.can_move(MotorStatus::Closing));
+    assert_eq!(travel.position(), 100);
+}
+
+proptest! {
+    #[test]
+    fn position_stays_within_the_travel(moves in moves()) {
+        let mut travel = Travel::new(FULL_TRAVEL);
+        for (direction, elapsed_ms) in moves {
+            run(&mut travel, direction, elapsed_ms);
+            prop_assert!(travel.position_ms() <= FULL_TRAVEL * 1000);
+            prop_assert!(travel.position() <= 100);
+        }
+    }
+
+    #[test]
+    fn a_full_move_reaches_the_end_stop(moves in moves(), direction in direction()) {
+        let mut travel = Travel::new(FULL_TRAVEL);
+        for (direction, elapsed_ms) in moves {
+            run(&mut travel, direction, elapsed_ms);
+        }
+        run(&mut travel, direction, FULL_TRAVEL * 1000);
+        let end = if direction == MotorStatus::Opening { 100 } else { 0 };
+        prop_assert_eq!(travel.position(), end);
+        prop_assert!(!travel.can_move(direction));
+    }
+
+    #[test]
+    fn changing_direction_frees_the_travel(moves in moves(), direction in direction()) {
+        let mut travel = Travel::new(FULL_TRAVEL);
+        for (direction, elapsed_ms) in moves {
+            run(&mut travel, direction, elapsed_ms);
+        }
+        run(&mut travel, direction, FULL_TRAVEL * 1000);
+        let other = if direction == MotorStatus::Opening {
+            MotorStatus::Closing
+        } else {
+            MotorStatus::Opening
+        };
+        prop_assert!(travel.can_move(other));
+        run(&mut travel, other, 1000);
+        prop_assert!(travel.can_move(direction));
+    }
+
+    #[test]
+    fn moves_in_one_direction_add_up(steps in prop::collection::vec(1000..5000u64, 1..20)) {
+        let mut travel = Travel::new(FULL_TRAVEL);
+        let mut total_s = 0;
+        for elapsed_ms in steps {
+            prop_assert_eq!(travel.can_move(MotorStatus::Closing), total_s < FULL_TRAVEL);
+            run(&mut travel, MotorStatus::Closing, elapsed_ms);
+            total_s += elapsed_ms / 1000;
+        }
+        prop_assert_eq!(travel.can_move(MotorStatus::Closing), total_s < FULL_TRAVEL);
+    }
+
+    #[test]
+    fn stopping_while_off_changes_nothing(moves in moves(), elapsed_ms in 0..100_000u64) {
+        let mut travel = Travel::new(FULL_TRAVEL);
+        for (direction, elapsed_ms) in moves {
+            run(&mut travel, direction, elapsed_ms);
+        }
+        let position = travel.position_ms();
+        let opening = travel.can_move(MotorStatus::Opening);
+        let closing = travel.can_move(MotorStatus::Closing);
+        travel.stop(MotorStatus::Off, elapsed_ms);
+        prop_assert_eq!(travel.position_ms(), position);
+        prop_assert_eq!(travel.can_move(MotorStatus::Opening), opening);
+        prop_assert_eq!(travel.can_move(MotorStatus::Closing), closing);
+    }
+}
@@ -0,0 +1,89 @@
+//! Start coordination of the motors sharing the supply.
+//!
+//! A motor draws several times its running current while it spins up, so
+//! starts are spaced by [`STAGGER_MS`] and at most [`MAX_RUNNING`] valve
+//! actuators run at once, keeping a small supply from dropping out when
+//! several zones move together. The circulation pump takes part in the
+//! staggering only, once started it runs for hours. The permits themselves
+//! are [`heat_control::starts`].
+//!
+//! An actuator waits for [`start`] before it is energized and holds the
+//! returned permit while it runs.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use embassy_time::{Instant, Timer};
+use heat_control::starts::{Starts, Wait};
+
+use crate::config::{MAX_RUNNING, STAGGER_MS};
+
+/// Actuators waiting for a slot that are woken one by one, more are woken
+/// all at once
+const WAITERS: usize = 4;
+
+const _: () = assert!(MAX_RUNNING > 0, "max_running has to be at least 1");
+
+struct Shared {
+    starts: Starts,
+    /// Waiting for a running slot
+    waiting: MultiWakerRegistration<WAITERS>,
+}
+
+static SHARED: Mutex<CriticalSectionRawMutex, RefCell<Shared>> = Mutex::new(RefCell::new(Shared {
+    starts: Starts::new(MAX_RUNNING as usize, STAGGER_MS),
+    waiting: MultiWakerRegistration::new(),
+}));
+
+/// Running slot of an actuator, released when dropped.
+pub struct Running(());
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        SHARED.lock(|shared| {
+            let mut shared = shared.borrow_mut();
+            shared.starts.stop();
+            shared.waiting.wake();
+        });
+    }
+}
+
+/// Wait for the permit to start a motor, taking a running slot if `slot`.
+async fn permit(slot: bool) {
+    loop {
+        let wait = poll_fn(|cx| {
+            SHARED.lock(|shared| {
+                let mut shared = shared.borrow_mut();
+                match shared.starts.start(Instant::now().as_millis(), slot) {
+                    Ok(()) => Poll::Ready(None),
+                    Err(Wait::Until(at)) => Poll::Ready(Some(at)),
+                    Err(Wait::Slot) => {
+                        shared.waiting.register(cx.waker());
+                        Poll::Pending
+                    }
+                }
+            })
+        })
+        .await;
+        match wait {
+            Some(at) => Timer::at(Instant::from_millis(at)).await,
+            None => return,
+        }
+    }
+}
+
+/// Wait until [`STAGGER_MS`] after the previous motor start.
+#[cfg(feature = "pump")]
+pub async fn stagger() {
+    permit(false).await;
+}
+
+/// Wait for a running slot and the stagger before starting an actuator.
+pub async fn start() -> Running {
+    permit(true).await;
+    Running(())
+}
@@ -0,0 +1,96 @@
+//! External 0–10 V control input on PA3 (ADC2).
+//!
+//! The signal comes in through a 20k/10k divider, so 9.9 V reads as the full
+//! ADC range. A signal below [`LOSS_MV`] for [`LOSS_TIME`] counts as a broken
+//! wire or a dead controller and hands control back to the local regulation.
+
+use embassy_executor::task;
+use embassy_stm32::Peri;
+use embassy_stm32::peripherals::ADC2;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::board::AnalogPin;
+use crate::chip;
+use crate::demand::{ExternalDemand, FULL_SCALE};
+use crate::fmt::{info, trace, warn};
+
+const ADC_MAX: u32 = 4095;
+const FULL_SCALE_MV: u32 = 9_900; // Input voltage at ADC_MAX
+const RANGE_MV: u32 = 10_000;
+const LOSS_MV: u32 = 500;
+const RESTORE_MV: u32 = 1_000;
+const LOSS_TIME: Duration = Duration::from_secs(5);
+const SAMPLE_INTERVAL_MS: u64 = 100;
+/// Weight of a new sample in the moving average, as a power of two
+const FILTER_SHIFT: u32 = 3;
+
+fn adc_to_millivolts(adc: u16) -> u32 {
+    u32::from(adc) * FULL_SCALE_MV / ADC_MAX
+}
+
+struct AnalogInput {
+    demand: ExternalDemand,
+    lost: bool,
+    low_since: Option<Instant>,
+}
+
+impl AnalogInput {
+    /// Track loss of signal, returns true while the signal is valid.
+    fn check_signal(&mut self, millivolts: u32) -> bool {
+        if self.lost {
+            if millivolts >= RESTORE_MV {
+                info!("Analog: signal present, {} mV", millivolts);
+                self.lost = false;
+                self.low_since = None;
+            }
+            return !self.lost;
+        }
+
+        if millivolts >= LOSS_MV {
+            self.low_since = None;
+            return true;
+        }
+
+        let low_since = *self.low_since.get_or_insert_with(Instant::now);
+        if low_since.elapsed() >= LOSS_TIME {
+            warn!("Analog: signal lost");
+            self.lost = true;
+            self.demand.release();
+            return false;
+        }
+
+        // Hold the last value until the loss is confirmed
+        true
+    }
+}
+
+#[task]
+pub async fn analog_input(pin: Peri<'static, AnalogPin>, adc: Peri<'static, ADC2>) {
+    let mut adc = chip::Adc::new(adc, chip::SAMPLE_TIME_LONG);
+    let mut pin = pin;
+
+    let mut input = AnalogInput {
+        demand: ExternalDemand::new(),
+        lost: true,
+        low_since: None,
+    };
+    let mut filtered = adc_to_millivolts(adc.read(&mut pin).await) << FILTER_SHIFT;
+
+    info!("Starting 0-10 V input");
+    loop {
+        let millivolts = adc_to_millivolts(adc.read(&mut pin).await);
+
+        // Exponential moving average, kept scaled up by the filter weight
+        filtered = filtered - (filtered >> FILTER_SHIFT) + millivolts;
+        let millivolts = filtered >> FILTER_SHIFT;
+        trace!("Analog: {} mV", millivolts);
+
+        if input.check_signal(millivolts) {
+            input
+                .demand
+                .apply(millivolts.min(RANGE_MV) * FULL_SCALE / RANGE_MV);
+        }
+
+        Timer::after_millis(SAMPLE_INTERVAL_MS).await;
+    }
+}
@@ -0,0 +1,93 @@
+//! HMAC-SHA256 authentication of state-changing remote commands.
+//!
+//! Authenticated commands are followed by a counter and a tag, the first 16
+//! bytes of `HMAC-SHA256(key, counter (u32 LE) || command)`. The key is set
+//! at build time through `AUTH_KEY`. A command is only accepted with a
+//! counter above the last accepted one, which is kept in the data flash pages
+//! so recorded commands cannot be replayed, not even after a restart.
+
+use hmac_sha256::HMAC;
+
+use crate::flash::{DATA_PAGES, Log};
+use crate::fmt::warn;
+
+const KEY: [u8; 32] = include!(concat!(env!("OUT_DIR"), "/auth_key.rs"));
+pub const TAG_LEN: usize = 16;
+/// Counter and tag appended to binary commands
+#[cfg(any(feature = "ble", feature = "lora"))]
+pub const SUFFIX_LEN: usize = 4 + TAG_LEN;
+
+/// Accepted counters, kept in the data pages
+static COUNTERS: Log = Log::new(DATA_PAGES);
+
+/// Check the tag over the counter and `parts`, consuming the counter.
+fn verify(counter: u32, parts: &[&[u8]], tag: &[u8]) -> bool {
+    let last = COUNTERS.last().unwrap_or(0);
+    if counter <= last || counter == Log::ERASED {
+        warn!("Auth: counter {} replayed, last {}", counter, last);
+        return false;
+    }
+
+    let mut hmac = HMAC::new(KEY);
+    hmac.update(counter.to_le_bytes());
+    for part in parts {
+        hmac.update(part);
+    }
+    let expected = hmac.finalize();
+
+    // Compare in constant time
+    let difference = expected[..TAG_LEN]
+        .iter()
+        .zip(tag)
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    if tag.len() != TAG_LEN || difference != 0 {
+        warn!("Auth: invalid tag");
+        return false;
+    }
+
+    COUNTERS.append(counter);
+    true
+}
+
+/// Verify a binary command whose payload ends with the counter (u32 LE) and
+/// tag, returning the payload without them. `header` is authenticated along
+/// with the payload.
+#[cfg(any(feature = "ble", feature = "lora"))]
+pub fn verify_binary<'a>(header: &[u8], payload: &'a [u8]) -> Option<&'a [u8]> {
+    let split = payload.len().checked_sub(SUFFIX_LEN)?;
+    let (payload, suffix) = payload.split_at(split);
+    let (counter, tag) = suffix.split_at(4);
+    let counter = u32::from_le_bytes(counter.try_into().ok()?);
+    verify(counter, &[header, payload], tag).then_some(payload)
+}
+
+/// Verify a text command ending with `<counter> <tag as hex>`, returning the
+/// command without them.
+#[cfg(feature = "shell")]
+pub fn verify_text(line: &str) -> Option<&str> {
+    let (rest, tag_hex) = line.trim_end().rsplit_once(' ')?;
+    let (command, counter) = rest.trim_end().rsplit_once(' ')?;
+    let command = command.trim_end();
+    let counter = counter.parse().ok()?;
+
+    let mut tag = [0u8; TAG_LEN];
+    if tag_hex.len() != 2 * TAG_LEN {
+        return None;
+    }
+    for (byte, hex) in tag.iter_mut().zip(tag_hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(hex).ok()?, 16).ok()?;
+    }
+
+    verify(counter, &[command.as_bytes()], &tag).then_some(command)
+}
+
+/// Six-digit code keyed with the authentication key over `uid`, see
+/// [`identity::pairing_code`](crate::identity::pairing_code).
+#[cfg(feature = "ble")]
+pub fn pairing_code(uid: &[u8]) -> u32 {
+    let mut hmac = HMAC::new(KEY);
+    hmac.update(b"pairing");
+    hmac.update(uid);
+    let mac = hmac.finalize();
+    u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]) % 1_000_000
+}
@@ -0,0 +1,729 @@
+//! Minimal BACnet MS/TP slave node on an RS-485 transceiver.
+//!
+//! Exposes the controller as a device with three objects:
+//! - Analog Input 0: measured temperature
+//! - Analog Value 0: regulation setpoint (writable)
+//! - Analog Output 0: estimated valve position
+//!
+//! Slave nodes never hold the token, so the device must be bound statically
+//! in the BMS front-end (MAC address + device instance).
+
+use embassy_executor::task;
+use embassy_stm32::Peri;
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::peripherals::USART3;
+use embassy_stm32::usart::{BufferedUart, Config};
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::{Read, Write};
+
+use crate::board::{BacnetDePin, Usart3RxPin, Usart3TxPin};
+use crate::fmt::{info, warn};
+use crate::{Irqs, identity, state, version};
+
+const BAUDRATE: u32 = 38_400;
+const MAC_ADDRESS: u8 = 10;
+/// Highest device instance, the one above is the wildcard
+const MAX_DEVICE_INSTANCE: u32 = 0x3F_FFFE;
+const VENDOR_ID: u32 = 0;
+const DEVICE_NAME: &str = "heat-dooRS";
+const MAX_FRAME_DATA: usize = 128;
+const FRAME_TIMEOUT: Duration = Duration::from_millis(100);
+
+// MS/TP frame types
+const FRAME_TEST_REQUEST: u8 = 3;
+const FRAME_TEST_RESPONSE: u8 = 4;
+const FRAME_DATA_EXPECTING_REPLY: u8 = 5;
+const FRAME_DATA_NOT_EXPECTING_REPLY: u8 = 6;
+
+// Object types
+const OBJECT_ANALOG_INPUT: u16 = 0;
+const OBJECT_ANALOG_OUTPUT: u16 = 1;
+const OBJECT_ANALOG_VALUE: u16 = 2;
+const OBJECT_DEVICE: u16 = 8;
+
+// Property identifiers
+const PROP_APPLICATION_SOFTWARE_VERSION: u32 = 12;
+const PROP_EVENT_STATE: u32 = 36;
+const PROP_FIRMWARE_REVISION: u32 = 44;
+const PROP_MAX_APDU: u32 = 62;
+const PROP_OBJECT_IDENTIFIER: u32 = 75;
+const PROP_OBJECT_LIST: u32 = 76;
+const PROP_OBJECT_NAME: u32 = 77;
+const PROP_OBJECT_TYPE: u32 = 79;
+const PROP_OUT_OF_SERVICE: u32 = 81;
+const PROP_PRESENT_VALUE: u32 = 85;
+const PROP_PROTOCOL_VERSION: u32 = 98;
+const PROP_SEGMENTATION_SUPPORTED: u32 = 107;
+const PROP_STATUS_FLAGS: u32 = 111;
+const PROP_SYSTEM_STATUS: u32 = 112;
+const PROP_UNITS: u32 = 117;
+const PROP_VENDOR_IDENTIFIER: u32 = 120;
+const PROP_PROTOCOL_REVISION: u32 = 139;
+
+// Engineering units
+const UNITS_DEGREES_CELSIUS: u32 = 62;
+const UNITS_PERCENT: u32 = 98;
+
+// Confirmed services
+const SERVICE_READ_PROPERTY: u8 = 12;
+const SERVICE_WRITE_PROPERTY: u8 = 15;
+
+// PDU types
+const PDU_CONFIRMED_REQUEST: u8 = 0x00;
+const PDU_SIMPLE_ACK: u8 = 0x20;
+const PDU_COMPLEX_ACK: u8 = 0x30;
+const PDU_ERROR: u8 = 0x50;
+const PDU_REJECT: u8 = 0x60;
+const PDU_ABORT: u8 = 0x70;
+
+const REJECT_UNRECOGNIZED_SERVICE: u8 = 9;
+const REJECT_MISSING_REQUIRED_PARAMETER: u8 = 5;
+const ABORT_SEGMENTATION_NOT_SUPPORTED: u8 = 4;
+
+/// Device instance from the 22 bits of the serial, so units on one network
+/// differ without being configured.
+fn device_instance() -> u32 {
+    (identity::serial() & 0x3F_FFFF).min(MAX_DEVICE_INSTANCE)
+}
+
+fn objects() -> [(u16, u32); 4] {
+    [
+        (OBJECT_DEVICE, device_instance()),
+        (OBJECT_ANALOG_INPUT, 0),
+        (OBJECT_ANALOG_OUTPUT, 0),
+        (OBJECT_ANALOG_VALUE, 0),
+    ]
+}
+
+#[derive(Clone, Copy)]
+enum BacnetError {
+    UnknownObject,
+    UnknownProperty,
+    WriteAccessDenied,
+    InvalidDataType,
+    ValueOutOfRange,
+    PropertyIsNotAnArray,
+    InvalidArrayIndex,
+}
+
+impl BacnetError {
+    // Error class and error code enumerations
+    fn encode(self) -> (u8, u8) {
+        match self {
+            BacnetError::UnknownObject => (1, 31),
+            BacnetError::UnknownProperty => (2, 32),
+            BacnetError::WriteAccessDenied => (2, 40),
+            BacnetError::InvalidDataType => (2, 9),
+            BacnetError::ValueOutOfRange => (2, 37),
+            BacnetError::PropertyIsNotAnArray => (2, 50),
+            BacnetError::InvalidArrayIndex => (2, 42),
+        }
+    }
+}
+
+struct Frame {
+    frame_type: u8,
+    destination: u8,
+    source: u8,
+    len: usize,
+    data: [u8; MAX_FRAME_DATA],
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    fn byte(&mut self, value: u8) {
+        if let Some(slot) = self.buf.get_mut(self.len) {
+            *slot = value;
+            self.len += 1;
+        }
+    }
+
+    fn bytes(&mut self, values: &[u8]) {
+        for value in values {
+            self.byte(*value);
+        }
+    }
+
+    fn tag(&mut self, number: u8, context: bool, len: usize) {
+        let class = if context { 0x08 } else { 0x00 };
+        if len < 5 {
+            self.byte((number << 4) | class | len as u8);
+        } else {
+            self.byte((number << 4) | class | 5);
+            self.byte(len as u8);
+        }
+    }
+
+    fn unsigned_bytes(value: u32) -> usize {
+        match value {
+            0..=0xFF => 1,
+            0x100..=0xFFFF => 2,
+            0x1_0000..=0xFF_FFFF => 3,
+            _ => 4,
+        }
+    }
+
+    fn unsigned_value(&mut self, value: u32, len: usize) {
+        self.bytes(&value.to_be_bytes()[4 - len..]);
+    }
+
+    fn app_unsigned(&mut self, value: u32) {
+        let len = Self::unsigned_bytes(value);
+        self.tag(2, false, len);
+        self.unsigned_value(value, len);
+    }
+
+    fn app_enumerated(&mut self, value: u32) {
+        let len = Self::unsigned_bytes(value);
+        self.tag(9, false, len);
+        self.unsigned_value(value, len);
+    }
+
+    fn app_boolean(&mut self, value: bool) {
+        self.tag(1, false, value as usize);
+    }
+
+    fn app_real(&mut self, value: f32) {
+        self.tag(4, false, 4);
+        self.bytes(&value.to_be_bytes());
+    }
+
+    fn app_object_id(&mut self, object_type: u16, instance: u32) {
+        self.tag(12, false, 4);
+        self.bytes(&object_id(object_type, instance).to_be_bytes());
+    }
+
+    fn app_string(&mut self, value: &str) {
+        self.tag(7, false, value.len() + 1);
+        self.byte(0); // ANSI X3.4 / UTF-8
+        self.bytes(value.as_bytes());
+    }
+
+    fn app_status_flags(&mut self) {
+        // in-alarm, fault, overridden, out-of-service all cleared
+        self.tag(8, false, 2);
+        self.bytes(&[4, 0]);
+    }
+
+    fn context_unsigned(&mut self, number: u8, value: u32) {
+        let len = Self::unsigned_bytes(value);
+        self.tag(number, true, len);
+        self.unsigned_value(value, len);
+    }
+
+    fn context_object_id(&mut self, number: u8, object_type: u16, instance: u32) {
+        self.tag(number, true, 4);
+        self.bytes(&object_id(object_type, instance).to_be_bytes());
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.buf.get(self.pos).copied()
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let value = self.peek()?;
+        self.pos += 1;
+        Some(value)
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let slice = self.buf.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(slice)
+    }
+
+    fn is_context_tag(&self, number: u8) -> bool {
+        self.peek()
+            .is_some_and(|tag| tag >> 4 == number && tag & 0x08 != 0 && tag & 0x07 < 6)
+    }
+
+    fn is_opening_tag(&self, number: u8) -> bool {
+        self.peek() == Some((number << 4) | 0x0E)
+    }
+
+    fn is_closing_tag(&self, number: u8) -> bool {
+        self.peek() == Some((number << 4) | 0x0F)
+    }
+
+    // Returns the length of a context tag with the given number
+    fn context_tag(&mut self, number: u8) -> Option<usize> {
+        if !self.is_context_tag(number) {
+            return None;
+        }
+        let len = (self.byte()? & 0x07) as usize;
+        if len == 5 {
+            Some(self.byte()? as usize)
+        } else {
+            Some(len)
+        }
+    }
+
+    fn context_unsigned(&mut self, number: u8) -> Option<u32> {
+        let len = self.context_tag(number)?;
+        if len == 0 || len > 4 {
+            return None;
+        }
+        Some(
+            self.take(len)?
+                .iter()
+                .fold(0, |acc, b| (acc << 8) | *b as u32),
+        )
+    }
+}
+
+fn object_id(object_type: u16, instance: u32) -> u32 {
+    ((object_type as u32) << 22) | (instance & 0x3F_FFFF)
+}
+
+fn header_crc(crc: u8, data: u8) -> u8 {
+    let mut crc = (crc ^ data) as u16;
+    crc = crc
+        ^ (crc << 1)
+        ^ (crc << 2)
+        ^ (crc << 3)
+        ^ (crc << 4)
+        ^ (crc << 5)
+        ^ (crc << 6)
+        ^ (crc << 7);
+    ((crc & 0xFE) ^ ((crc >> 8) & 1)) as u8
+}
+
+fn data_crc(crc: u16, data: u8) -> u16 {
+    let low = (crc & 0xFF) ^ data as u16;
+    (crc >> 8)
+        ^ (low << 8)
+        ^ (low << 3)
+        ^ (low << 12)
+        ^ (low >> 4)
+        ^ (low & 0x0F)
+        ^ ((low & 0x0F) << 7)
+}
+
+async fn read_frame(uart: &mut BufferedUart<'_>, frame: &mut Frame) -> bool {
+    // Wait for the 0x55 0xFF preamble
+    let mut byte = [0u8; 1];
+    let mut previous = 0u8;
+    loop {
+        if uart.read_exact(&mut byte).await.is_err() {
+            return false;
+        }
+        if previous == 0x55 && byte[0] == 0xFF {
+            break;
+        }
+        previous = byte[0];
+    }
+
+    let mut header = [0u8; 6];
+    match with_timeout(FRAME_TIMEOUT, uart.read_exact(&mut header)).await {
+        Ok(Ok(())) => {}
+        _ => return false,
+    }
+
+    if header.iter().fold(0xFF, |crc, b| header_crc(crc, *b)) != 0x55 {
+        warn!("BACnet: header CRC error");
+        return false;
+    }
+
+    frame.frame_type = header[0];
+    frame.destination = header[1];
+    frame.source = header[2];
+    frame.len = u16::from_be_bytes([header[3], header[4]]) as usize;
+    if frame.len == 0 {
+        return true;
+    }
+
+    if frame.len > MAX_FRAME_DATA {
+        warn!("BACnet: frame too long ({} bytes)", frame.len);
+        return false;
+    }
+
+    let mut crc = [0u8; 2];
+    let received = with_timeout(FRAME_TIMEOUT, async {
+        uart.read_exact(&mut frame.data[..frame.len]).await?;
+        uart.read_exact(&mut crc).await
+    })
+    .await;
+    if !matches!(received, Ok(Ok(()))) {
+        return false;
+    }
+
+    let crc = frame.data[..frame.len]
+        .iter()
+        .chain(crc.iter())
+        .fold(0xFFFF, |crc, b| data_crc(crc, *b));
+    if crc != 0xF0B8 {
+        warn!("BACnet: data CRC error");
+        return false;
+    }
+
+    true
+}
+
+async fn send_frame(
+    uart: &mut BufferedUart<'_>,
+    de_pin: &mut Output<'static>,
+    frame_type: u8,
+    destination: u8,
+    data: &[u8],
+) {
+    let len = (data.len() as u16).to_be_bytes();
+    let header = [frame_type, destination, MAC_ADDRESS, len[0], len[1]];
+    let header_crc = !header.iter().fold(0xFF, |crc, b| header_crc(crc, *b));
+    let data_crc = !data.iter().fold(0xFFFF, |crc, b| data_crc(crc, *b));
+
+    de_pin.set_high();
+    let result = async {
+        uart.write_all(&[0x55, 0xFF]).await?;
+        uart.write_all(&header).await?;
+        uart.write_all(&[header_crc]).await?;
+        if !data.is_empty() {
+            uart.write_all(data).await?;
+            uart.write_all(&data_crc.to_le_bytes()).await?;
+        }
+        uart.flush().await
+    }
+    .await;
+    de_pin.set_low();
+
+    if result.is_err() {
+        warn!("BACnet: transmit error");
+    }
+}
+
+// Handle an NPDU expecting a reply, returns the length of the reply NPDU
+fn handle_npdu(request: &[u8], reply: &mut [u8]) -> Option<usize> {
+    let mut reader = Reader::new(request);
+    if reader.byte()? != 0x01 {
+        return None;
+    }
+
+    let control = reader.byte()?;
+    if control & 0x80 != 0 {
+        // Network layer messages are not supported
+        return None;
+    }
+
+    if control & 0x20 != 0 {
+        reader.take(2)?;
+        let len = reader.byte()? as usize;
+        reader.take(len)?;
+    }
+
+    let mut source: Option<(&[u8], &[u8])> = None;
+    if control & 0x08 != 0 {
+        let net = reader.take(2)?;
+        let len = reader.byte()? as usize;
+        source = Some((net, reader.take(len)?));
+    }
+
+    if control & 0x20 != 0 {
+        reader.byte()?; // hop count
+    }
+
+    let mut writer = Writer::new(reply);
+    writer.byte(0x01);
+    match source {
+        Some((net, address)) => {
+            writer.byte(0x20);
+            writer.bytes(net);
+            writer.byte(address.len() as u8);
+            writer.bytes(address);
+            writer.byte(0xFF);
+        }
+        None => writer.byte(0x00),
+    }
+
+    let header_len = writer.len;
+    let apdu_len = handle_apdu(&request[reader.pos..], &mut reply[header_len..])?;
+    Some(header_len + apdu_len)
+}
+
+fn handle_apdu(request: &[u8], reply: &mut [u8]) -> Option<usize> {
+    let mut reader = Reader::new(request);
+    let pdu = reader.byte()?;
+    if pdu & 0xF0 != PDU_CONFIRMED_REQUEST {
+        return None;
+    }
+
+    reader.byte()?; // max segments / max APDU
+    let invoke_id = reader.byte()?;
+
+    let mut writer = Writer::new(reply);
+    if pdu & 0x08 != 0 {
+        writer.bytes(&[
+            PDU_ABORT | 0x01,
+            invoke_id,
+            ABORT_SEGMENTATION_NOT_SUPPORTED,
+        ]);
+        return Some(writer.len);
+    }
+
+    let service = reader.byte()?;
+    let result = match service {
+        SERVICE_READ_PROPERTY => read_property(&mut reader, invoke_id, &mut writer),
+        SERVICE_WRITE_PROPERTY => write_property(&mut reader, invoke_id, &mut writer),
+        _ => {
+            writer.bytes(&[PDU_REJECT, invoke_id, REJECT_UNRECOGNIZED_SERVICE]);
+            return Some(writer.len);
+        }
+    };
+
+    match result {
+        Some(Ok(())) => {}
+        Some(Err(error)) => {
+            let (class, code) = error.encode();
+            writer.len = 0;
+            writer.bytes(&[PDU_ERROR, invoke_id, service]);
+            writer.app_enumerated(class as u32);
+            writer.app_enumerated(code as u32);
+        }
+        None => {
+            writer.len = 0;
+            writer.bytes(&[PDU_REJECT, invoke_id, REJECT_MISSING_REQUIRED_PARAMETER]);
+        }
+    }
+
+    Some(writer.len)
+}
+
+fn decode_object(reader: &mut Reader) -> Option<(u16, u32)> {
+    if reader.context_tag(0)? != 4 {
+        return None;
+    }
+    let raw = u32::from_be_bytes(reader.take(4)?.try_into().ok()?);
+    Some(((raw >> 22) as u16, raw & 0x3F_FFFF))
+}
+
+fn read_property(
+    reader: &mut Reader,
+    invoke_id: u8,
+    writer: &mut Writer,
+) -> Option<Result<(), BacnetError>> {
+    let (object_type, instance) = decode_object(reader)?;
+    let property = reader.context_unsigned(1)?;
+    let index = if reader.is_context_tag(2) {
+        Some(reader.context_unsigned(2)?)
+    } else {
+        None
+    };
+
+    if !objects().contains(&(object_type, instance)) {
+        return Some(Err(BacnetError::UnknownObject));
+    }
+
+    writer.bytes(&[PDU_COMPLEX_ACK, invoke_id, SERVICE_READ_PROPERTY]);
+    writer.context_object_id(0, object_type, instance);
+    writer.context_unsigned(1, property);
+    if let Some(index) = index {
+        writer.context_unsigned(2, index);
+    }
+    writer.byte(0x3E);
+    if let Err(error) = encode_property(writer, object_type, property, index) {
+        return Some(Err(error));
+    }
+    writer.byte(0x3F);
+
+    Some(Ok(()))
+}
+
+fn encode_property(
+    writer: &mut Writer,
+    object_type: u16,
+    property: u32,
+    index: Option<u32>,
+) -> Result<(), BacnetError> {
+    if index.is_some() && !(object_type == OBJECT_DEVICE && property == PROP_OBJECT_LIST) {
+        return Err(BacnetError::PropertyIsNotAnArray);
+    }
+
+    let state = state::get();
+    let instance = if object_type == OBJECT_DEVICE {
+        device_instance()
+    } else {
+        0
+    };
+
+    match property {
+        PROP_OBJECT_IDENTIFIER => writer.app_object_id(object_type, instance),
+        PROP_OBJECT_TYPE => writer.app_enumerated(object_type as u32),
+        PROP_OBJECT_NAME => writer.app_string(match object_type {
+            OBJECT_DEVICE => DEVICE_NAME,
+            OBJECT_ANALOG_INPUT => "Temperature",
+            OBJECT_ANALOG_OUTPUT => "Valve position",
+            _ => "Setpoint",
+        }),
+        _ if object_type == OBJECT_DEVICE => match property {
+            PROP_SYSTEM_STATUS => writer.app_enumerated(0), // operational
+            PROP_VENDOR_IDENTIFIER => writer.app_unsigned(VENDOR_ID),
+            PROP_MAX_APDU => writer.app_unsigned(MAX_FRAME_DATA as u32 - 8),
+            PROP_SEGMENTATION_SUPPORTED => writer.app_enumerated(3), // no segmentation
+            PROP_PROTOCOL_VERSION => writer.app_unsigned(1),
+            PROP_PROTOCOL_REVISION => writer.app_unsigned(14),
+            PROP_FIRMWARE_REVISION => writer.app_string(version::VERSION),
+            PROP_APPLICATION_SOFTWARE_VERSION => writer.app_string(version::GIT_HASH),
+            PROP_OBJECT_LIST => match index {
+                None => {
+                    for (object_type, instance) in objects() {
+                        writer.app_object_id(object_type, instance);
+                    }
+                }
+                Some(0) => writer.app_unsigned(objects().len() as u32),
+                Some(index) => match objects().get(index as usize - 1) {
+                    Some((object_type, instance)) => writer.app_object_id(*object_type, *instance),
+                    None => return Err(BacnetError::InvalidArrayIndex),
+                },
+            },
+            _ => return Err(BacnetError::UnknownProperty),
+        },
+        PROP_PRESENT_VALUE => writer.app_real(match object_type {
+            OBJECT_ANALOG_INPUT => state.temperature,
+            OBJECT_ANALOG_OUTPUT => state.valve_position as f32,
+            _ => state.setpoint,
+        }),
+        PROP_UNITS => writer.app_enumerated(match object_type {
+            OBJECT_ANALOG_OUTPUT => UNITS_PERCENT,
+            _ => UNITS_DEGREES_CELSIUS,
+        }),
+        PROP_STATUS_FLAGS => writer.app_status_flags(),
+        PROP_EVENT_STATE => writer.app_enumerated(0), // normal
+        PROP_OUT_OF_SERVICE => writer.app_boolean(false),
+        _ => return Err(BacnetError::UnknownProperty),
+    }
+
+    Ok(())
+}
+
+fn write_property(
+    reader: &mut Reader,
+    invoke_id: u8,
+    writer: &mut Writer,
+) -> Option<Result<(), BacnetError>> {
+    let (object_type, instance) = decode_object(reader)?;
+    let property = reader.context_unsigned(1)?;
+    if reader.is_context_tag(2) {
+        reader.context_unsigned(2)?;
+    }
+
+    if !reader.is_opening_tag(3) {
+        return None;
+    }
+    reader.byte()?;
+    let tag = reader.byte()?;
+    let value = reader.take((tag & 0x07) as usize)?;
+    if !reader.is_closing_tag(3) {
+        return None;
+    }
+
+    if !objects().contains(&(object_type, instance)) {
+        return Some(Err(BacnetError::UnknownObject));
+    }
+
+    if object_type != OBJECT_ANALOG_VALUE || property != PROP_PRESENT_VALUE {
+        return Some(Err(BacnetError::WriteAccessDenied));
+    }
+
+    if tag != 0x44 {
+        return Some(Err(BacnetError::InvalidDataType));
+    }
+
+    let setpoint = f32::from_be_bytes(value.try_into().ok()?);
+    if !state::set_setpoint(setpoint) {
+        return Some(Err(BacnetError::ValueOutOfRange));
+    }
+
+    info!("BACnet: setpoint changed to {}", setpoint);
+    writer.bytes(&[PDU_SIMPLE_ACK, invoke_id, SERVICE_WRITE_PROPERTY]);
+    Some(Ok(()))
+}
+
+#[task]
+pub async fn bacnet(
+    usart: Peri<'static, USART3>,
+    tx_pin: Peri<'static, Usart3TxPin>,
+    rx_pin: Peri<'static, Usart3RxPin>,
+    de_pin: Peri<'static, BacnetDePin>,
+) {
+    let mut tx_buffer = [0u8; MAX_FRAME_DATA + 16];
+    let mut rx_buffer = [0u8; MAX_FRAME_DATA + 16];
+    let mut config = Config::default();
+    config.baudrate = BAUDRATE;
+
+    let mut uart = BufferedUart::new(
+        usart,
+        rx_pin,
+        tx_pin,
+        &mut tx_buffer,
+        &mut rx_buffer,
+        Irqs,
+        config,
+    )
+    .unwrap();
+    let mut de_pin = Output::new(de_pin, Level::Low, Speed::Medium);
+
+    info!(
+        "Starting BACnet MS/TP slave, MAC {}, device {}",
+        MAC_ADDRESS,
+        device_instance()
+    );
+
+    let mut frame = Frame {
+        frame_type: 0,
+        destination: 0,
+        source: 0,
+        len: 0,
+        data: [0; MAX_FRAME_DATA],
+    };
+    let mut reply = [0u8; MAX_FRAME_DATA];
+
+    loop {
+        if !read_frame(&mut uart, &mut frame).await || frame.destination != MAC_ADDRESS {
+            continue;
+        }
+
+        match frame.frame_type {
+            FRAME_TEST_REQUEST => {
+                let data = &frame.data[..frame.len];
+                send_frame(
+                    &mut uart,
+                    &mut de_pin,
+                    FRAME_TEST_RESPONSE,
+                    frame.source,
+                    data,
+                )
+                .await;
+            }
+            FRAME_DATA_EXPECTING_REPLY => {
+                if let Some(len) = handle_npdu(&frame.data[..frame.len], &mut reply) {
+                    send_frame(
+                        &mut uart,
+                        &mut de_pin,
+                        FRAME_DATA_NOT_EXPECTING_REPLY,
+                        frame.source,
+                        &reply[..len],
+                    )
+                    .await;
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Smartphone control through an HM-10/JDY-08 BLE UART bridge.
+//!
+//! Requests are `command, payload...` sent in `link` frames. Every request is
+//! answered with a frame carrying the command with the high bit set.
+//!
+//! Commands:
+//! - `0x01` status: no payload, returns temperature and setpoint (0.1 °C, i16 LE),
+//!   valve position (%), motor status and pairing state
+//! - `0x02` pair: pairing code of the unit (u32 LE, see
+//!   `identity::pairing_code`), returns a result code
+//! - `0x03` set setpoint: setpoint (0.1 °C, i16 LE), returns a result code
+//! - `0x04` bootloader: no payload, returns a result code and restarts into
+//!   the system bootloader
+//! - `0x05` set time (with `rtc`): UTC seconds since the epoch (u32 LE),
+//!   returns a result code
+//!
+//! Write commands are only accepted after a successful pairing, which expires
+//! after a period without traffic. With the `auth` feature their payload is
+//! also followed by a counter (u32 LE) and tag, see `auth`.
+
+use embassy_executor::task;
+use embassy_stm32::Peri;
+use embassy_stm32::peripherals::USART1;
+use embassy_stm32::usart::{BufferedUart, Config};
+use embassy_time::{Duration, Instant};
+use embedded_io_async::Write;
+
+#[cfg(feature = "auth")]
+use crate::auth;
+use crate::board::{Usart1RxPin, Usart1TxPin};
+use crate::fmt::{info, warn};
+use crate::link::{self, Decoder};
+#[cfg(feature = "rtc")]
+use crate::rtc;
+use crate::{Irqs, bootloader, identity, state};
+
+const BAUDRATE: u32 = 9600;
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(300);
+const MAX_PAIRING_ATTEMPTS: u8 = 3;
+const PAIRING_LOCKOUT: Duration = Duration::from_secs(30);
+#[cfg(not(feature = "auth"))]
+const MAX_PAYLOAD: usize = 8;
+#[cfg(feature = "auth")]
+const MAX_PAYLOAD: usize = 8 + auth::SUFFIX_LEN;
+
+const RESPONSE: u8 = 0x80;
+
+const CMD_STATUS: u8 = 0x01;
+const CMD_PAIR: u8 = 0x02;
+const CMD_SET_SETPOINT: u8 = 0x03;
+const CMD_BOOTLOADER: u8 = 0x04;
+#[cfg(feature = "rtc")]
+const CMD_SET_TIME: u8 = 0x05;
+
+const RESULT_OK: u8 = 0;
+const RESULT_INVALID: u8 = 1;
+const RESULT_NOT_PAIRED: u8 = 2;
+const RESULT_OUT_OF_RANGE: u8 = 3;
+const RESULT_LOCKED: u8 = 4;
+#[cfg(feature = "auth")]
+const RESULT_NOT_AUTHENTICATED: u8 = 5;
+
+struct Session {
+    paired_until: Option<Instant>,
+    failed_attempts: u8,
+    locked_until: Option<Instant>,
+}
+
+impl Session {
+    fn is_paired(&self) -> bool {
+        self.paired_until.is_some_and(|t| Instant::now() < t)
+    }
+
+    fn pair(&mut self, code: u32) -> u8 {
+        if self.locked_until.is_some_and(|t| Instant::now() < t) {
+            return RESULT_LOCKED;
+        }
+
+        if code != identity::pairing_code() {
+            self.failed_attempts += 1;
+            if self.failed_attempts >= MAX_PAIRING_ATTEMPTS {
+                warn!("BLE: too many pairing attempts, locking");
+                self.failed_attempts = 0;
+                self.locked_until = Some(Instant::now() + PAIRING_LOCKOUT);
+            }
+            return RESULT_INVALID;
+        }
+
+        info!("BLE: paired");
+        self.failed_attempts = 0;
+        self.paired_until = Some(Instant::now() + PAIRING_TIMEOUT);
+        RESULT_OK
+    }
+}
+
+fn handle_command(
+    session: &mut Session,
+    command: u8,
+    payload: &[u8],
+    response: &mut [u8; MAX_PAYLOAD],
+) -> usize {
+    #[cfg(feature = "auth")]
+    let payload = {
+        let changes_state = matches!(command, CMD_SET_SETPOINT | CMD_BOOTLOADER);
+        #[cfg(feature = "rtc")]
+        let changes_state = changes_state || command == CMD_SET_TIME;
+        if !changes_state {
+            payload
+        } else if let Some(payload) = auth::verify_binary(&[command], payload) {
+            payload
+        } else {
+            response[0] = RESULT_NOT_AUTHENTICATED;
+            return 1;
+        }
+    };
+
+    match command {
+        CMD_STATUS => {
+            let state = state::get();
+            response[..2].copy_from_slice(&state::deci_degrees(state.temperature).to_le_bytes());
+            response[2..4].copy_from_slice(&state::deci_degrees(state.setpoint).to_le_bytes());
+            response[4] = state.valve_position;
+            response[5] = state.motor_status as u8;
+            response[6] = session.is_paired() as u8;
+            7
+        }
+        CMD_PAIR => {
+            response[0] = match payload.try_into() {
+                Ok(code) => session.pair(u32::from_le_bytes(code)),
+                Err(_) => RESULT_INVALID,
+            };
+            1
+        }
+        CMD_SET_SETPOINT => {
+            response[0] = if !session.is_paired() {
+                RESULT_NOT_PAIRED
+            } else {
+                match payload.try_into() {
+                    Ok(value) => {
+                        let setpoint = i16::from_le_bytes(value) as f32 / 10.0;
+                        if state::set_setpoint(setpoint) {
+                            info!("BLE: setpoint changed to {}", setpoint);
+                            RESULT_OK
+                        } else {
+                            RESULT_OUT_OF_RANGE
+                        }
+                    }
+                    Err(_) => RESULT_INVALID,
+                }
+            };
+            1
+        }
+        #[cfg(feature = "rtc")]
+        CMD_SET_TIME => {
+            response[0] = if !session.is_paired() {
+                RESULT_NOT_PAIRED
+            } else {
+                match payload.try_into() {
+                    Ok(time) => {
+                        rtc::sync(u32::from_le_bytes(time));
+                        RESULT_OK
+                    }
+                    Err(_) => RESULT_INVALID,
+                }
+            };
+            1
+        }
+        CMD_BOOTLOADER => {
+            response[0] = if session.is_paired() {
+                RESULT_OK
+            } else {
+                RESULT_NOT_PAIRED
+            };
+            1
+        }
+        _ => {
+            response[0] = RESULT_INVALID;
+            1
+        }
+    }
+}
+
+#[task]
+pub async fn ble(
+    usart: Peri<'static, USART1>,
+    tx_pin: Peri<'static, Usart1TxPin>,
+    rx_pin: Peri<'static, Usart1RxPin>,
+) {
+    let mut tx_buffer = [0u8; 32];
+    let mut rx_buffer = [0u8; 32];
+    let mut config = Config::default();
+    config.baudrate = BAUDRATE;
+
+    let mut uart = BufferedUart::new(
+        usart,
+        rx_pin,
+        tx_pin,
+        &mut tx_buffer,
+        &mut rx_buffer,
+        Irqs,
+        config,
+    )
+    .unwrap();
+
+    info!("Starting BLE control");
+
+    let mut session = Session {
+        paired_until: None,
+        failed_attempts: 0,
+        locked_until: None,
+    };
+    let mut decoder = Decoder::<{ MAX_PAYLOAD + 1 + link::OVERHEAD }>::new();
+    let mut response = [0u8; MAX_PAYLOAD + 1];
+
+    loop {
+        if link::read_frame(&mut uart, &mut decoder).await.is_err() {
+            continue;
+        }
+        let Some((&command, payload)) = decoder.payload().split_first() else {
+            continue;
+        };
+
+        // Any traffic from a paired phone keeps the session alive
+        if session.is_paired() {
+            session.paired_until = Some(Instant::now() + PAIRING_TIMEOUT);
+        }
+
+        response[0] = command | RESPONSE;
+        let (_, data) = response.split_first_mut().unwrap();
+        let response_len = handle_command(&mut session, command, payload, data.try_into().unwrap());
+        let response = &response[..response_len + 1];
+
+        if link::write_frame(&mut uart, response).await.is_err() {
+            warn!("BLE: transmit error");
+        }
+
+        if command == CMD_BOOTLOADER && response[1] == RESULT_OK {
+            let _ = uart.flush().await;
+            bootloader::enter().await;
+        }
+    }
+}
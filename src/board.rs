@@ -0,0 +1,833 @@
+//! Pin assignment and clock source of the supported boards.
+//!
+//! The valve controller board is the default, a `board-*` feature selects
+//! another one. A board assigns the pins of the core functions and of the
+//! optional interfaces; the interface pins listed in the README are the ones
+//! of the controller board, another board only moves those it needs for
+//! itself.
+//!
+//! Adding a board means a module with the same items as the ones below.
+//!
+//! The indicators besides the status LED are built here as well, on the
+//! same pins on every board.
+
+/// The valve controller board with a Blue Pill style STM32F103C8.
+#[cfg(not(feature = "board-nucleo"))]
+mod variant {
+    use embassy_stm32::pac::{self, gpio::Gpio};
+    #[allow(unused_imports)]
+    use embassy_stm32::peripherals::{self, PA0, PA1, PA2, PC13};
+    #[cfg(feature = "hse")]
+    use embassy_stm32::rcc::HseMode;
+    #[cfg(feature = "hse")]
+    use embassy_stm32::time::Hertz;
+
+    /// NTC divider, an ADC1 channel
+    pub type NtcPin = PA0;
+    pub type MotorEnablePin = PA1;
+    pub type MotorDirectionPin = PA2;
+    pub type StatusLedPin = PC13;
+
+    // Interfaces
+    /// Demand input divider, an ADC2 channel
+    #[cfg(feature = "analog")]
+    pub type AnalogPin = peripherals::PA3;
+    /// TIM3 CH1
+    #[cfg(feature = "pwm-input")]
+    pub type PwmInputPin = peripherals::PA6;
+    #[cfg(any(feature = "ble", feature = "iap"))]
+    pub type Usart1TxPin = peripherals::PA9;
+    #[cfg(any(feature = "ble", feature = "iap"))]
+    pub type Usart1RxPin = peripherals::PA10;
+    #[cfg(any(feature = "bacnet", feature = "mbus"))]
+    pub type Usart3TxPin = peripherals::PB10;
+    #[cfg(any(feature = "bacnet", feature = "mbus"))]
+    pub type Usart3RxPin = peripherals::PB11;
+    /// RS-485 driver enable
+    #[cfg(feature = "bacnet")]
+    pub type BacnetDePin = peripherals::PB12;
+    #[cfg(feature = "lora")]
+    pub type LoraSpi = peripherals::SPI1;
+    #[cfg(feature = "lora")]
+    pub type LoraSckPin = peripherals::PA5;
+    #[cfg(feature = "lora")]
+    pub type LoraMosiPin = peripherals::PA7;
+    #[cfg(feature = "lora")]
+    pub type LoraMisoPin = peripherals::PA6;
+    #[cfg(feature = "lora")]
+    pub type LoraNssPin = peripherals::PA4;
+    #[cfg(feature = "lora")]
+    pub type LoraResetPin = peripherals::PB0;
+    #[cfg(feature = "lora")]
+    pub type LoraDio0Pin = peripherals::PB1;
+    #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+    pub type Spi2SckPin = peripherals::PB13;
+    #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+    pub type Spi2MosiPin = peripherals::PB15;
+    #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+    pub type Spi2MisoPin = peripherals::PB14;
+    #[cfg(feature = "nrf24")]
+    pub type Nrf24CsnPin = peripherals::PB9;
+    #[cfg(feature = "nrf24")]
+    pub type Nrf24CePin = peripherals::PB8;
+    #[cfg(feature = "nrf24")]
+    pub type Nrf24IrqPin = peripherals::PA8;
+    #[cfg(feature = "i2c-sensor")]
+    pub type I2c1SclPin = peripherals::PB6;
+    #[cfg(feature = "i2c-sensor")]
+    pub type I2c1SdaPin = peripherals::PB7;
+    #[cfg(feature = "spi-sensor")]
+    pub type SpiSensorCsPin = peripherals::PB12;
+    #[cfg(feature = "sg-ready")]
+    pub type SgReady1Pin = peripherals::PB3;
+    #[cfg(feature = "sg-ready")]
+    pub type SgReady2Pin = peripherals::PB4;
+    /// TIM3 CH3
+    #[cfg(feature = "fan")]
+    pub type FanPin = peripherals::PB0;
+    #[cfg(feature = "fan-tach")]
+    pub type FanTachPin = peripherals::PB1;
+    #[cfg(feature = "flow")]
+    pub type FlowPin = peripherals::PB9;
+    /// Return NTC divider, an ADC2 channel
+    #[cfg(feature = "energy")]
+    pub type EnergyPin = peripherals::PA5;
+    #[cfg(feature = "boiler")]
+    pub type BoilerPin = peripherals::PB8;
+    #[cfg(feature = "pump")]
+    pub type PumpPin = peripherals::PA4;
+    #[cfg(feature = "stages")]
+    pub type Stage1Pin = peripherals::PB11;
+    #[cfg(feature = "stages")]
+    pub type Stage2Pin = peripherals::PB12;
+    #[cfg(feature = "window")]
+    pub type WindowPin = peripherals::PB5;
+    #[cfg(feature = "buttons")]
+    pub type ButtonUpPin = peripherals::PA15;
+    #[cfg(feature = "buttons")]
+    pub type ButtonDownPin = peripherals::PB3;
+    #[cfg(feature = "buttons")]
+    pub type ButtonModePin = peripherals::PB4;
+    /// TIM3 CH1
+    #[cfg(feature = "encoder")]
+    pub type EncoderAPin = peripherals::PA6;
+    /// TIM3 CH2
+    #[cfg(feature = "encoder")]
+    pub type EncoderBPin = peripherals::PA7;
+    #[cfg(feature = "encoder")]
+    pub type EncoderButtonPin = peripherals::PA15;
+    #[cfg(feature = "usb")]
+    pub type UsbDpPin = peripherals::PA12;
+    #[cfg(feature = "usb")]
+    pub type UsbDmPin = peripherals::PA11;
+
+    pub const MOTOR_ENABLE: (Gpio, usize) = (pac::GPIOA, 1);
+    #[cfg(feature = "factory")]
+    pub const MOTOR_DIRECTION: (Gpio, usize) = (pac::GPIOA, 2);
+    pub const STATUS_LED: (Gpio, usize) = (pac::GPIOC, 13);
+    pub const STATUS_LED_ACTIVE_LOW: bool = true;
+
+    /// 8 MHz crystal on OSC_IN/OSC_OUT (PD0, PD1)
+    #[cfg(feature = "hse")]
+    pub const HSE: (Hertz, HseMode) = (Hertz(8_000_000), HseMode::Oscillator);
+
+    macro_rules! pins {
+        ($p:ident) => {
+            $crate::board::Pins {
+                ntc: $p.PA0,
+                motor_enable: $p.PA1,
+                motor_direction: $p.PA2,
+                status_led: $p.PC13,
+                #[cfg(feature = "analog")]
+                analog: $p.PA3,
+                #[cfg(feature = "pwm-input")]
+                pwm_input: $p.PA6,
+                #[cfg(any(feature = "ble", feature = "iap"))]
+                usart1_tx: $p.PA9,
+                #[cfg(any(feature = "ble", feature = "iap"))]
+                usart1_rx: $p.PA10,
+                #[cfg(any(feature = "bacnet", feature = "mbus"))]
+                usart3_tx: $p.PB10,
+                #[cfg(any(feature = "bacnet", feature = "mbus"))]
+                usart3_rx: $p.PB11,
+                #[cfg(feature = "bacnet")]
+                bacnet_de: $p.PB12,
+                #[cfg(feature = "lora")]
+                lora_spi: $p.SPI1,
+                #[cfg(feature = "lora")]
+                lora_sck: $p.PA5,
+                #[cfg(feature = "lora")]
+                lora_mosi: $p.PA7,
+                #[cfg(feature = "lora")]
+                lora_miso: $p.PA6,
+                #[cfg(feature = "lora")]
+                lora_nss: $p.PA4,
+                #[cfg(feature = "lora")]
+                lora_reset: $p.PB0,
+                #[cfg(feature = "lora")]
+                lora_dio0: ($p.PB1, $p.EXTI1),
+                #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+                spi2_sck: $p.PB13,
+                #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+                spi2_mosi: $p.PB15,
+                #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+                spi2_miso: $p.PB14,
+                #[cfg(feature = "nrf24")]
+                nrf24_csn: $p.PB9,
+                #[cfg(feature = "nrf24")]
+                nrf24_ce: $p.PB8,
+                #[cfg(feature = "nrf24")]
+                nrf24_irq: ($p.PA8, $p.EXTI8),
+                #[cfg(feature = "i2c-sensor")]
+                i2c1_scl: $p.PB6,
+                #[cfg(feature = "i2c-sensor")]
+                i2c1_sda: $p.PB7,
+                #[cfg(feature = "spi-sensor")]
+                spi_sensor_cs: $p.PB12,
+                #[cfg(feature = "sg-ready")]
+                sg_ready1: $p.PB3,
+                #[cfg(feature = "sg-ready")]
+                sg_ready2: $p.PB4,
+                #[cfg(feature = "fan")]
+                fan: $p.PB0,
+                #[cfg(feature = "fan-tach")]
+                fan_tach: ($p.PB1, $p.EXTI1),
+                #[cfg(feature = "flow")]
+                flow: ($p.PB9, $p.EXTI9),
+                #[cfg(feature = "energy")]
+                energy: $p.PA5,
+                #[cfg(feature = "boiler")]
+                boiler: $p.PB8,
+                #[cfg(feature = "pump")]
+                pump: $p.PA4,
+                #[cfg(feature = "stages")]
+                stage1: $p.PB11,
+                #[cfg(feature = "stages")]
+                stage2: $p.PB12,
+                #[cfg(feature = "window")]
+                window: ($p.PB5, $p.EXTI5),
+                #[cfg(feature = "buttons")]
+                button_up: ($p.PA15, $p.EXTI15),
+                #[cfg(feature = "buttons")]
+                button_down: ($p.PB3, $p.EXTI3),
+                #[cfg(feature = "buttons")]
+                button_mode: ($p.PB4, $p.EXTI4),
+                #[cfg(feature = "encoder")]
+                encoder_a: $p.PA6,
+                #[cfg(feature = "encoder")]
+                encoder_b: $p.PA7,
+                #[cfg(feature = "encoder")]
+                encoder_button: ($p.PA15, $p.EXTI15),
+                #[cfg(feature = "usb")]
+                usb_dp: $p.PA12,
+                #[cfg(feature = "usb")]
+                usb_dm: $p.PA11,
+            }
+        };
+    }
+    pub(crate) use pins;
+}
+
+/// NUCLEO-F103RB with the valve driver on the Arduino header: NTC on A0,
+/// motor enable on A1, direction on A2 (PA4), as PA2 and PA3 carry the
+/// ST-LINK virtual COM port. The interfaces on PA3, PA4 and PA5 move to the
+/// port C pins of the 64-pin package, LoRa to SPI2.
+#[cfg(feature = "board-nucleo")]
+mod variant {
+    use embassy_stm32::pac::{self, gpio::Gpio};
+    #[allow(unused_imports)]
+    use embassy_stm32::peripherals::{self, PA0, PA1, PA4, PA5};
+    #[cfg(feature = "hse")]
+    use embassy_stm32::rcc::HseMode;
+    #[cfg(feature = "hse")]
+    use embassy_stm32::time::Hertz;
+
+    /// NTC divider, an ADC1 channel
+    pub type NtcPin = PA0;
+    pub type MotorEnablePin = PA1;
+    pub type MotorDirectionPin = PA4;
+    /// LD2
+    pub type StatusLedPin = PA5;
+
+    // Interfaces, PA3 is the ST-LINK virtual COM port as well
+    /// Demand input divider, an ADC2 channel
+    #[cfg(feature = "analog")]
+    pub type AnalogPin = peripherals::PC2;
+    /// TIM3 CH1
+    #[cfg(feature = "pwm-input")]
+    pub type PwmInputPin = peripherals::PA6;
+    #[cfg(any(feature = "ble", feature = "iap"))]
+    pub type Usart1TxPin = peripherals::PA9;
+    #[cfg(any(feature = "ble", feature = "iap"))]
+    pub type Usart1RxPin = peripherals::PA10;
+    #[cfg(any(feature = "bacnet", feature = "mbus"))]
+    pub type Usart3TxPin = peripherals::PB10;
+    #[cfg(any(feature = "bacnet", feature = "mbus"))]
+    pub type Usart3RxPin = peripherals::PB11;
+    /// RS-485 driver enable
+    #[cfg(feature = "bacnet")]
+    pub type BacnetDePin = peripherals::PB12;
+    #[cfg(feature = "lora")]
+    pub type LoraSpi = peripherals::SPI2;
+    #[cfg(feature = "lora")]
+    pub type LoraSckPin = peripherals::PB13;
+    #[cfg(feature = "lora")]
+    pub type LoraMosiPin = peripherals::PB15;
+    #[cfg(feature = "lora")]
+    pub type LoraMisoPin = peripherals::PB14;
+    #[cfg(feature = "lora")]
+    pub type LoraNssPin = peripherals::PC4;
+    #[cfg(feature = "lora")]
+    pub type LoraResetPin = peripherals::PB0;
+    #[cfg(feature = "lora")]
+    pub type LoraDio0Pin = peripherals::PB1;
+    #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+    pub type Spi2SckPin = peripherals::PB13;
+    #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+    pub type Spi2MosiPin = peripherals::PB15;
+    #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+    pub type Spi2MisoPin = peripherals::PB14;
+    #[cfg(feature = "nrf24")]
+    pub type Nrf24CsnPin = peripherals::PB9;
+    #[cfg(feature = "nrf24")]
+    pub type Nrf24CePin = peripherals::PB8;
+    #[cfg(feature = "nrf24")]
+    pub type Nrf24IrqPin = peripherals::PA8;
+    #[cfg(feature = "i2c-sensor")]
+    pub type I2c1SclPin = peripherals::PB6;
+    #[cfg(feature = "i2c-sensor")]
+    pub type I2c1SdaPin = peripherals::PB7;
+    #[cfg(feature = "spi-sensor")]
+    pub type SpiSensorCsPin = peripherals::PB12;
+    #[cfg(feature = "sg-ready")]
+    pub type SgReady1Pin = peripherals::PB3;
+    #[cfg(feature = "sg-ready")]
+    pub type SgReady2Pin = peripherals::PB4;
+    /// TIM3 CH3
+    #[cfg(feature = "fan")]
+    pub type FanPin = peripherals::PB0;
+    #[cfg(feature = "fan-tach")]
+    pub type FanTachPin = peripherals::PB1;
+    #[cfg(feature = "flow")]
+    pub type FlowPin = peripherals::PB9;
+    /// Return NTC divider, an ADC2 channel
+    #[cfg(feature = "energy")]
+    pub type EnergyPin = peripherals::PC1;
+    #[cfg(feature = "boiler")]
+    pub type BoilerPin = peripherals::PB8;
+    #[cfg(feature = "pump")]
+    pub type PumpPin = peripherals::PC0;
+    #[cfg(feature = "stages")]
+    pub type Stage1Pin = peripherals::PB11;
+    #[cfg(feature = "stages")]
+    pub type Stage2Pin = peripherals::PB12;
+    #[cfg(feature = "window")]
+    pub type WindowPin = peripherals::PB5;
+    #[cfg(feature = "buttons")]
+    pub type ButtonUpPin = peripherals::PA15;
+    #[cfg(feature = "buttons")]
+    pub type ButtonDownPin = peripherals::PB3;
+    #[cfg(feature = "buttons")]
+    pub type ButtonModePin = peripherals::PB4;
+    /// TIM3 CH1
+    #[cfg(feature = "encoder")]
+    pub type EncoderAPin = peripherals::PA6;
+    /// TIM3 CH2
+    #[cfg(feature = "encoder")]
+    pub type EncoderBPin = peripherals::PA7;
+    #[cfg(feature = "encoder")]
+    pub type EncoderButtonPin = peripherals::PA15;
+    #[cfg(feature = "usb")]
+    pub type UsbDpPin = peripherals::PA12;
+    #[cfg(feature = "usb")]
+    pub type UsbDmPin = peripherals::PA11;
+
+    pub const MOTOR_ENABLE: (Gpio, usize) = (pac::GPIOA, 1);
+    #[cfg(feature = "factory")]
+    pub const MOTOR_DIRECTION: (Gpio, usize) = (pac::GPIOA, 4);
+    pub const STATUS_LED: (Gpio, usize) = (pac::GPIOA, 5);
+    pub const STATUS_LED_ACTIVE_LOW: bool = false;
+
+    /// 8 MHz from the MCO of the ST-LINK, no crystal fitted
+    #[cfg(feature = "hse")]
+    pub const HSE: (Hertz, HseMode) = (Hertz(8_000_000), HseMode::Bypass);
+
+    macro_rules! pins {
+        ($p:ident) => {
+            $crate::board::Pins {
+                ntc: $p.PA0,
+                motor_enable: $p.PA1,
+                motor_direction: $p.PA4,
+                status_led: $p.PA5,
+                #[cfg(feature = "analog")]
+                analog: $p.PC2,
+                #[cfg(feature = "pwm-input")]
+                pwm_input: $p.PA6,
+                #[cfg(any(feature = "ble", feature = "iap"))]
+                usart1_tx: $p.PA9,
+                #[cfg(any(feature = "ble", feature = "iap"))]
+                usart1_rx: $p.PA10,
+                #[cfg(any(feature = "bacnet", feature = "mbus"))]
+                usart3_tx: $p.PB10,
+                #[cfg(any(feature = "bacnet", feature = "mbus"))]
+                usart3_rx: $p.PB11,
+                #[cfg(feature = "bacnet")]
+                bacnet_de: $p.PB12,
+                #[cfg(feature = "lora")]
+                lora_spi: $p.SPI2,
+                #[cfg(feature = "lora")]
+                lora_sck: $p.PB13,
+                #[cfg(feature = "lora")]
+                lora_mosi: $p.PB15,
+                #[cfg(feature = "lora")]
+                lora_miso: $p.PB14,
+                #[cfg(feature = "lora")]
+                lora_nss: $p.PC4,
+                #[cfg(feature = "lora")]
+                lora_reset: $p.PB0,
+                #[cfg(feature = "lora")]
+                lora_dio0: ($p.PB1, $p.EXTI1),
+                #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+                spi2_sck: $p.PB13,
+                #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+                spi2_mosi: $p.PB15,
+                #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+                spi2_miso: $p.PB14,
+                #[cfg(feature = "nrf24")]
+                nrf24_csn: $p.PB9,
+                #[cfg(feature = "nrf24")]
+                nrf24_ce: $p.PB8,
+                #[cfg(feature = "nrf24")]
+                nrf24_irq: ($p.PA8, $p.EXTI8),
+                #[cfg(feature = "i2c-sensor")]
+                i2c1_scl: $p.PB6,
+                #[cfg(feature = "i2c-sensor")]
+                i2c1_sda: $p.PB7,
+                #[cfg(feature = "spi-sensor")]
+                spi_sensor_cs: $p.PB12,
+                #[cfg(feature = "sg-ready")]
+                sg_ready1: $p.PB3,
+                #[cfg(feature = "sg-ready")]
+                sg_ready2: $p.PB4,
+                #[cfg(feature = "fan")]
+                fan: $p.PB0,
+                #[cfg(feature = "fan-tach")]
+                fan_tach: ($p.PB1, $p.EXTI1),
+                #[cfg(feature = "flow")]
+                flow: ($p.PB9, $p.EXTI9),
+                #[cfg(feature = "energy")]
+                energy: $p.PC1,
+                #[cfg(feature = "boiler")]
+                boiler: $p.PB8,
+                #[cfg(feature = "pump")]
+                pump: $p.PC0,
+                #[cfg(feature = "stages")]
+                stage1: $p.PB11,
+                #[cfg(feature = "stages")]
+                stage2: $p.PB12,
+                #[cfg(feature = "window")]
+                window: ($p.PB5, $p.EXTI5),
+                #[cfg(feature = "buttons")]
+                button_up: ($p.PA15, $p.EXTI15),
+                #[cfg(feature = "buttons")]
+                button_down: ($p.PB3, $p.EXTI3),
+                #[cfg(feature = "buttons")]
+                button_mode: ($p.PB4, $p.EXTI4),
+                #[cfg(feature = "encoder")]
+                encoder_a: $p.PA6,
+                #[cfg(feature = "encoder")]
+                encoder_b: $p.PA7,
+                #[cfg(feature = "encoder")]
+                encoder_button: ($p.PA15, $p.EXTI15),
+                #[cfg(feature = "usb")]
+                usb_dp: $p.PA12,
+                #[cfg(feature = "usb")]
+                usb_dm: $p.PA11,
+            }
+        };
+    }
+    pub(crate) use pins;
+}
+
+// Interfaces colliding on one board only, main.rs refuses the collisions
+// common to all of them
+#[cfg(all(
+    not(feature = "board-nucleo"),
+    feature = "lora",
+    any(
+        feature = "pwm-input",
+        feature = "encoder",
+        feature = "energy",
+        feature = "pump"
+    )
+))]
+compile_error!("feature `lora` uses SPI1 on PA4-PA7");
+#[cfg(all(feature = "board-nucleo", not(feature = "stm32f103rb")))]
+compile_error!("feature `board-nucleo` needs `stm32f103rb`, the chip of the NUCLEO-F103RB");
+#[cfg(all(
+    feature = "board-nucleo",
+    feature = "lora",
+    any(feature = "nrf24", feature = "spi-sensor", feature = "hd44780-gpio")
+))]
+compile_error!("feature `lora` uses SPI2 on PB13-PB15 on the NUCLEO-F103RB");
+
+use embassy_stm32::Peri;
+#[cfg(any(
+    feature = "lora",
+    feature = "nrf24",
+    feature = "fan-tach",
+    feature = "flow",
+    feature = "window",
+    feature = "buttons",
+    feature = "encoder"
+))]
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Level;
+#[cfg(feature = "hd44780-gpio")]
+use embassy_stm32::gpio::Output;
+#[cfg(feature = "tm1637")]
+use embassy_stm32::gpio::OutputOpenDrain;
+#[cfg(any(feature = "rgb-led", feature = "buzzer"))]
+use embassy_stm32::gpio::OutputType;
+#[cfg(any(feature = "tm1637", feature = "hd44780-gpio"))]
+use embassy_stm32::gpio::Speed;
+#[cfg(any(
+    feature = "lora",
+    feature = "nrf24",
+    feature = "fan-tach",
+    feature = "flow",
+    feature = "window",
+    feature = "buttons",
+    feature = "encoder"
+))]
+use embassy_stm32::gpio::{Pin, Pull};
+#[cfg(any(
+    feature = "ssd1306",
+    all(feature = "hd44780", not(feature = "hd44780-gpio"))
+))]
+use embassy_stm32::i2c::{self, I2c};
+#[cfg(any(
+    feature = "ssd1306",
+    all(feature = "hd44780", not(feature = "hd44780-gpio"))
+))]
+use embassy_stm32::peripherals::I2C1;
+#[cfg(any(feature = "rgb-led", feature = "hd44780-gpio"))]
+use embassy_stm32::peripherals::PA8;
+#[cfg(feature = "rgb-led")]
+use embassy_stm32::peripherals::{PA9, PA10, TIM1};
+#[cfg(any(
+    feature = "tm1637",
+    all(feature = "display", not(feature = "hd44780-gpio"))
+))]
+use embassy_stm32::peripherals::{PB6, PB7};
+#[cfg(feature = "hd44780-gpio")]
+use embassy_stm32::peripherals::{PB9, PB12, PB13, PB14, PB15};
+#[cfg(feature = "buzzer")]
+use embassy_stm32::peripherals::{PB10, TIM2};
+#[cfg(any(feature = "ssd1306", feature = "buzzer"))]
+use embassy_stm32::time::Hertz;
+#[cfg(feature = "rgb-led")]
+use embassy_stm32::time::khz;
+#[cfg(any(feature = "rgb-led", feature = "buzzer"))]
+use embassy_stm32::timer::low_level::CountingMode;
+#[cfg(any(feature = "rgb-led", feature = "buzzer"))]
+use embassy_stm32::timer::simple_pwm::{PwmPin, SimplePwm};
+use embassy_time::Instant;
+
+#[cfg(feature = "display")]
+use crate::display::Display;
+use crate::indicator::{Status, StatusIndicator};
+
+pub use variant::*;
+
+/// A pin with the EXTI line it is wired to.
+#[cfg(any(
+    feature = "lora",
+    feature = "nrf24",
+    feature = "fan-tach",
+    feature = "flow",
+    feature = "window",
+    feature = "buttons",
+    feature = "encoder"
+))]
+pub type Exti<P> = (Peri<'static, P>, Peri<'static, <P as Pin>::ExtiChannel>);
+
+/// The pins of the core functions and the enabled interfaces, taken from the
+/// peripherals with [`pins!`].
+pub struct Pins {
+    pub ntc: Peri<'static, NtcPin>,
+    pub motor_enable: Peri<'static, MotorEnablePin>,
+    pub motor_direction: Peri<'static, MotorDirectionPin>,
+    pub status_led: Peri<'static, StatusLedPin>,
+    #[cfg(feature = "analog")]
+    pub analog: Peri<'static, AnalogPin>,
+    #[cfg(feature = "pwm-input")]
+    pub pwm_input: Peri<'static, PwmInputPin>,
+    #[cfg(any(feature = "ble", feature = "iap"))]
+    pub usart1_tx: Peri<'static, Usart1TxPin>,
+    #[cfg(any(feature = "ble", feature = "iap"))]
+    pub usart1_rx: Peri<'static, Usart1RxPin>,
+    #[cfg(any(feature = "bacnet", feature = "mbus"))]
+    pub usart3_tx: Peri<'static, Usart3TxPin>,
+    #[cfg(any(feature = "bacnet", feature = "mbus"))]
+    pub usart3_rx: Peri<'static, Usart3RxPin>,
+    #[cfg(feature = "bacnet")]
+    pub bacnet_de: Peri<'static, BacnetDePin>,
+    #[cfg(feature = "lora")]
+    pub lora_spi: Peri<'static, LoraSpi>,
+    #[cfg(feature = "lora")]
+    pub lora_sck: Peri<'static, LoraSckPin>,
+    #[cfg(feature = "lora")]
+    pub lora_mosi: Peri<'static, LoraMosiPin>,
+    #[cfg(feature = "lora")]
+    pub lora_miso: Peri<'static, LoraMisoPin>,
+    #[cfg(feature = "lora")]
+    pub lora_nss: Peri<'static, LoraNssPin>,
+    #[cfg(feature = "lora")]
+    pub lora_reset: Peri<'static, LoraResetPin>,
+    #[cfg(feature = "lora")]
+    pub lora_dio0: Exti<LoraDio0Pin>,
+    #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+    pub spi2_sck: Peri<'static, Spi2SckPin>,
+    #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+    pub spi2_mosi: Peri<'static, Spi2MosiPin>,
+    #[cfg(any(feature = "nrf24", feature = "spi-sensor"))]
+    pub spi2_miso: Peri<'static, Spi2MisoPin>,
+    #[cfg(feature = "nrf24")]
+    pub nrf24_csn: Peri<'static, Nrf24CsnPin>,
+    #[cfg(feature = "nrf24")]
+    pub nrf24_ce: Peri<'static, Nrf24CePin>,
+    #[cfg(feature = "nrf24")]
+    pub nrf24_irq: Exti<Nrf24IrqPin>,
+    #[cfg(feature = "i2c-sensor")]
+    pub i2c1_scl: Peri<'static, I2c1SclPin>,
+    #[cfg(feature = "i2c-sensor")]
+    pub i2c1_sda: Peri<'static, I2c1SdaPin>,
+    #[cfg(feature = "spi-sensor")]
+    pub spi_sensor_cs: Peri<'static, SpiSensorCsPin>,
+    #[cfg(feature = "sg-ready")]
+    pub sg_ready1: Peri<'static, SgReady1Pin>,
+    #[cfg(feature = "sg-ready")]
+    pub sg_ready2: Peri<'static, SgReady2Pin>,
+    #[cfg(feature = "fan")]
+    pub fan: Peri<'static, FanPin>,
+    #[cfg(feature = "fan-tach")]
+    pub fan_tach: Exti<FanTachPin>,
+    #[cfg(feature = "flow")]
+    pub flow: Exti<FlowPin>,
+    #[cfg(feature = "energy")]
+    pub energy: Peri<'static, EnergyPin>,
+    #[cfg(feature = "boiler")]
+    pub boiler: Peri<'static, BoilerPin>,
+    #[cfg(feature = "pump")]
+    pub pump: Peri<'static, PumpPin>,
+    #[cfg(feature = "stages")]
+    pub stage1: Peri<'static, Stage1Pin>,
+    #[cfg(feature = "stages")]
+    pub stage2: Peri<'static, Stage2Pin>,
+    #[cfg(feature = "window")]
+    pub window: Exti<WindowPin>,
+    #[cfg(feature = "buttons")]
+    pub button_up: Exti<ButtonUpPin>,
+    #[cfg(feature = "buttons")]
+    pub button_down: Exti<ButtonDownPin>,
+    #[cfg(feature = "buttons")]
+    pub button_mode: Exti<ButtonModePin>,
+    #[cfg(feature = "encoder")]
+    pub encoder_a: Peri<'static, EncoderAPin>,
+    #[cfg(feature = "encoder")]
+    pub encoder_b: Peri<'static, EncoderBPin>,
+    #[cfg(feature = "encoder")]
+    pub encoder_button: Exti<EncoderButtonPin>,
+    #[cfg(feature = "usb")]
+    pub usb_dp: Peri<'static, UsbDpPin>,
+    #[cfg(feature = "usb")]
+    pub usb_dm: Peri<'static, UsbDmPin>,
+}
+
+/// An input interrupting on the EXTI line of its pin.
+#[cfg(any(
+    feature = "lora",
+    feature = "nrf24",
+    feature = "fan-tach",
+    feature = "flow",
+    feature = "window",
+    feature = "buttons",
+    feature = "encoder"
+))]
+pub fn exti_input<P: Pin>((pin, line): Exti<P>, pull: Pull) -> ExtiInput<'static> {
+    ExtiInput::new(pin, line, pull)
+}
+
+/// Output level driving the status LED `on`.
+pub fn status_led_level(on: bool) -> Level {
+    Level::from(on != STATUS_LED_ACTIVE_LOW)
+}
+
+/// The indicators of the enabled features besides the status LED, built
+/// from the peripherals with [`indicators!`] and driven as one.
+pub struct Indicators {
+    #[cfg(feature = "rgb-led")]
+    pub rgb_led: crate::rgb_led::RgbLed,
+    #[cfg(feature = "buzzer")]
+    pub buzzer: crate::buzzer::Buzzer,
+    #[cfg(feature = "ssd1306")]
+    pub ssd1306: Display<crate::ssd1306::Ssd1306>,
+    #[cfg(feature = "hd44780")]
+    pub hd44780: Display<crate::hd44780::Lcd>,
+    #[cfg(feature = "tm1637")]
+    pub tm1637: crate::tm1637::Readout,
+}
+
+impl StatusIndicator for Indicators {
+    const NAME: &'static str = "feature";
+
+    #[allow(unused_variables)]
+    fn update(&mut self, status: Status) {
+        #[cfg(feature = "rgb-led")]
+        self.rgb_led.update(status);
+        #[cfg(feature = "buzzer")]
+        self.buzzer.update(status);
+        #[cfg(feature = "ssd1306")]
+        self.ssd1306.update(status);
+        #[cfg(feature = "hd44780")]
+        self.hd44780.update(status);
+        #[cfg(feature = "tm1637")]
+        self.tm1637.update(status);
+    }
+
+    fn tick(&mut self) -> Instant {
+        let next = Instant::MAX;
+        #[cfg(feature = "rgb-led")]
+        let next = next.min(self.rgb_led.tick());
+        #[cfg(feature = "buzzer")]
+        let next = next.min(self.buzzer.tick());
+        #[cfg(feature = "ssd1306")]
+        let next = next.min(self.ssd1306.tick());
+        #[cfg(feature = "hd44780")]
+        let next = next.min(self.hd44780.tick());
+        #[cfg(feature = "tm1637")]
+        let next = next.min(self.tm1637.tick());
+        next
+    }
+}
+
+/// Take the indicator peripherals and build the [`Indicators`].
+///
+/// Has to run before the JTAG pins are freed, the buzzer rewrites the remap
+/// register, which would enable them again.
+macro_rules! indicators {
+    ($p:ident) => {
+        $crate::board::Indicators {
+            #[cfg(feature = "rgb-led")]
+            rgb_led: $crate::board::rgb_led($p.TIM1, $p.PA8, $p.PA9, $p.PA10),
+            #[cfg(feature = "buzzer")]
+            buzzer: $crate::board::buzzer($p.TIM2, $p.PB10),
+            #[cfg(feature = "ssd1306")]
+            ssd1306: $crate::board::ssd1306($p.I2C1, $p.PB6, $p.PB7),
+            #[cfg(all(feature = "hd44780", not(feature = "hd44780-gpio")))]
+            hd44780: $crate::board::hd44780($p.I2C1, $p.PB6, $p.PB7),
+            #[cfg(feature = "hd44780-gpio")]
+            hd44780: $crate::board::hd44780($p.PA8, $p.PB9, $p.PB12, $p.PB13, $p.PB14, $p.PB15),
+            #[cfg(feature = "tm1637")]
+            tm1637: $crate::board::tm1637($p.PB6, $p.PB7),
+        }
+    };
+}
+pub(crate) use indicators;
+
+/// Red, green and blue on TIM1 CH1-CH3 (PA8-PA10).
+#[cfg(feature = "rgb-led")]
+pub fn rgb_led(
+    tim: Peri<'static, TIM1>,
+    red: Peri<'static, PA8>,
+    green: Peri<'static, PA9>,
+    blue: Peri<'static, PA10>,
+) -> crate::rgb_led::RgbLed {
+    crate::rgb_led::RgbLed::new(SimplePwm::new(
+        tim,
+        Some(PwmPin::new(red, OutputType::PushPull)),
+        Some(PwmPin::new(green, OutputType::PushPull)),
+        Some(PwmPin::new(blue, OutputType::PushPull)),
+        None,
+        khz(1),
+        CountingMode::EdgeAlignedUp,
+    ))
+}
+
+/// Piezo buzzer on TIM2 CH3, remapped to PB10.
+#[cfg(feature = "buzzer")]
+pub fn buzzer(tim: Peri<'static, TIM2>, pin: Peri<'static, PB10>) -> crate::buzzer::Buzzer {
+    // Partial remap 2 moves TIM2 CH3 to PB10
+    embassy_stm32::pac::AFIO
+        .mapr()
+        .modify(|w| w.set_tim2_remap(2));
+    crate::buzzer::Buzzer::new(SimplePwm::new(
+        tim,
+        None,
+        None,
+        Some(PwmPin::new(pin, OutputType::PushPull)),
+        None,
+        Hertz(crate::buzzer::TONE_HZ),
+        CountingMode::EdgeAlignedUp,
+    ))
+}
+
+/// OLED on I2C1 (PB6 SCL, PB7 SDA) at 400 kHz.
+#[cfg(feature = "ssd1306")]
+pub fn ssd1306(
+    i2c: Peri<'static, I2C1>,
+    scl: Peri<'static, PB6>,
+    sda: Peri<'static, PB7>,
+) -> Display<crate::ssd1306::Ssd1306> {
+    let mut config = i2c::Config::default();
+    config.frequency = Hertz::khz(400);
+    Display::new(crate::ssd1306::Ssd1306::new(I2c::new_blocking(
+        i2c, scl, sda, config,
+    )))
+}
+
+/// Character LCD through a PCF8574 backpack on I2C1 (PB6 SCL, PB7 SDA).
+#[cfg(all(feature = "hd44780", not(feature = "hd44780-gpio")))]
+pub fn hd44780(
+    i2c: Peri<'static, I2C1>,
+    scl: Peri<'static, PB6>,
+    sda: Peri<'static, PB7>,
+) -> Display<crate::hd44780::Lcd> {
+    let i2c = I2c::new_blocking(i2c, scl, sda, i2c::Config::default());
+    Display::new(crate::hd44780::Hd44780::new(crate::hd44780::Pcf8574::new(
+        i2c,
+    )))
+}
+
+/// Character LCD in 4-bit mode: PA8 RS, PB9 E, PB12-PB15 D4-D7.
+#[cfg(feature = "hd44780-gpio")]
+pub fn hd44780(
+    rs: Peri<'static, PA8>,
+    e: Peri<'static, PB9>,
+    d4: Peri<'static, PB12>,
+    d5: Peri<'static, PB13>,
+    d6: Peri<'static, PB14>,
+    d7: Peri<'static, PB15>,
+) -> Display<crate::hd44780::Lcd> {
+    let rs = Output::new(rs, Level::Low, Speed::Low);
+    let e = Output::new(e, Level::Low, Speed::Low);
+    let data = [
+        Output::new(d4, Level::Low, Speed::Low),
+        Output::new(d5, Level::Low, Speed::Low),
+        Output::new(d6, Level::Low, Speed::Low),
+        Output::new(d7, Level::Low, Speed::Low),
+    ];
+    Display::new(crate::hd44780::Hd44780::new(crate::hd44780::Gpio::new(
+        rs, e, data,
+    )))
+}
+
+/// LED display on PB6 CLK and PB7 DIO, open drain with the module's pull-ups.
+#[cfg(feature = "tm1637")]
+pub fn tm1637(clk: Peri<'static, PB6>, dio: Peri<'static, PB7>) -> crate::tm1637::Readout {
+    crate::tm1637::Readout::new(crate::tm1637::Tm1637::new(
+        OutputOpenDrain::new(clk, Level::High, Speed::Low),
+        OutputOpenDrain::new(dio, Level::High, Speed::Low),
+    ))
+}
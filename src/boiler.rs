@@ -0,0 +1,44 @@
+//! Boiler heat request output on PB8, active high.
+//!
+//! Heat is only requested once the valve has been open at least
+//! [`MIN_OPENING`] for [`OPEN_DELAY`], so the boiler never fires into a
+//! closed or barely open valve. The request drops as soon as the valve
+//! closes below the threshold.
+
+use embassy_executor::task;
+use embassy_stm32::gpio::Output;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::fmt::info;
+use crate::state;
+
+/// Valve opening in % counting as open
+const MIN_OPENING: u8 = 20;
+const OPEN_DELAY: Duration = Duration::from_secs(60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[task]
+pub async fn boiler(mut output: Output<'static>) {
+    info!("Starting boiler interlock");
+    let mut open_since: Option<Instant> = None;
+    let mut requested = false;
+
+    loop {
+        let open = state::get().valve_position >= MIN_OPENING;
+        if !open {
+            open_since = None;
+        } else if open_since.is_none() {
+            open_since = Some(Instant::now());
+        }
+
+        let request = open_since.is_some_and(|since| since.elapsed() >= OPEN_DELAY);
+        if request != requested {
+            info!("Boiler request {}", if request { "on" } else { "off" });
+            output.set_level(request.into());
+            requested = request;
+            state::update(|s| s.boiler_request = request);
+        }
+
+        Timer::after(CHECK_INTERVAL).await;
+    }
+}
@@ -0,0 +1,69 @@
+//! Entry into the STM32 system bootloader for in-field reflashing.
+//!
+//! The request is stored in RAM that survives a reset, the MCU is reset and
+//! the jump happens at the very start of `main`, before any peripheral or
+//! clock is configured. The system memory is entered directly through its
+//! vector table, only the F0 maps it to address 0 first. The ROM bootloader
+//! talks over USART1 (PA9/PA10).
+
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of_mut, read_volatile, write_volatile};
+
+use embassy_time::{Duration, Timer, with_timeout};
+
+use crate::chip::{self, SYSTEM_MEMORY};
+use crate::fmt::{info, warn};
+use crate::motor_control::MotorCommand;
+use crate::{MOTOR_COMMANDS, SIGNAL_SAFE_STATE};
+
+const BOOTLOADER_MAGIC: u32 = 0xDF0B_007A;
+const SAFE_STATE_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[unsafe(link_section = ".uninit.BOOTLOADER_REQUEST")]
+static mut BOOTLOADER_REQUEST: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// Jump to the system bootloader if it was requested before the last reset.
+pub fn check() {
+    // SAFETY: called once at startup before anything else runs, the .uninit
+    // word is only accessed through volatile reads and writes
+    unsafe {
+        let request = addr_of_mut!(BOOTLOADER_REQUEST).cast::<u32>();
+        if read_volatile(request) == BOOTLOADER_MAGIC {
+            write_volatile(request, 0);
+            chip::map_system_memory();
+            cortex_m::asm::bootload(SYSTEM_MEMORY as *const u32);
+        }
+    }
+}
+
+/// Stop the motor before a restart, the motor task stays parked afterwards.
+pub async fn safe_state() {
+    info!("Driving motor to the safe state");
+    MOTOR_COMMANDS.send(MotorCommand::SafeState).await;
+    if with_timeout(SAFE_STATE_TIMEOUT, SIGNAL_SAFE_STATE.wait())
+        .await
+        .is_err()
+    {
+        warn!("Motor did not confirm the safe state");
+    }
+
+    // Let the transports flush their last output
+    Timer::after_millis(100).await;
+}
+
+/// Drive the motor to the safe state and restart into the system bootloader.
+#[cfg(any(feature = "ble", feature = "shell"))]
+pub async fn enter() -> ! {
+    info!("Entering system bootloader");
+
+    safe_state().await;
+
+    // SAFETY: single volatile write of a plain word in .uninit
+    unsafe {
+        write_volatile(
+            addr_of_mut!(BOOTLOADER_REQUEST).cast::<u32>(),
+            BOOTLOADER_MAGIC,
+        );
+    }
+    cortex_m::peripheral::SCB::sys_reset();
+}
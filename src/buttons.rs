@@ -0,0 +1,44 @@
+//! Up, down and mode push buttons on PA15, PB3 and PB4, closing to ground.
+//!
+//! Without a display mode toggles the manual mode, up and down change the
+//! setpoint or move the valve in manual mode.
+
+use embassy_executor::task;
+use embassy_futures::select::{Either3, select3};
+use embassy_stm32::exti::ExtiInput;
+use embassy_time::{Duration, Timer};
+
+use crate::fmt::info;
+use crate::manual::{self, InputEvent};
+
+/// A press has to stay low this long to count
+const DEBOUNCE: Duration = Duration::from_millis(30);
+
+#[task]
+pub async fn buttons(
+    mut up: ExtiInput<'static>,
+    mut down: ExtiInput<'static>,
+    mut mode: ExtiInput<'static>,
+) {
+    info!("Starting buttons");
+    loop {
+        let (event, input) = match select3(
+            up.wait_for_falling_edge(),
+            down.wait_for_falling_edge(),
+            mode.wait_for_falling_edge(),
+        )
+        .await
+        {
+            Either3::First(()) => (InputEvent::Turn(1), &mut up),
+            Either3::Second(()) => (InputEvent::Turn(-1), &mut down),
+            Either3::Third(()) => (InputEvent::Push, &mut mode),
+        };
+
+        Timer::after(DEBOUNCE).await;
+        if input.is_low() {
+            manual::input(event);
+            input.wait_for_high().await;
+            Timer::after(DEBOUNCE).await;
+        }
+    }
+}
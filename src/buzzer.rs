@@ -0,0 +1,154 @@
+//! Passive buzzer on PB10 (TIM2 CH3, partial remap) sounding the alarms.
+//!
+//! Each alarm has its own beep pattern and only sounds after it stayed
+//! active for [`ALARM_DELAY`], so the missing reading right after startup
+//! stays quiet. [`mute`] silences the alarms sounding at that moment until
+//! they clear; alarms raised later sound again.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_stm32::peripherals::TIM2;
+use embassy_stm32::timer::Channel;
+use embassy_stm32::timer::simple_pwm::SimplePwm;
+use embassy_time::{Duration, Instant};
+
+use crate::fmt::info;
+use crate::indicator::{Condition, Status, StatusIndicator};
+
+pub const TONE_HZ: u32 = 2_700;
+const ALARM_DELAY: Duration = Duration::from_secs(60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Alarm {
+    condition: Condition,
+    beeps: u8,
+    beep_ms: u64,
+    pause_ms: u64,
+}
+
+/// Alarms in order of priority
+const ALARMS: [Alarm; 2] = [
+    // Continuous fast beeping
+    Alarm {
+        condition: Condition::Overtemperature,
+        beeps: 10,
+        beep_ms: 100,
+        pause_ms: 100,
+    },
+    // Two long beeps every 10 s
+    Alarm {
+        condition: Condition::SensorFault,
+        beeps: 2,
+        beep_ms: 300,
+        pause_ms: 10_000,
+    },
+];
+
+static MUTE: AtomicBool = AtomicBool::new(false);
+
+/// Silence the alarms sounding now.
+#[cfg(feature = "shell")]
+pub fn mute() {
+    MUTE.store(true, Ordering::Relaxed);
+}
+
+pub struct Buzzer {
+    pwm: SimplePwm<'static, TIM2>,
+    status: Status,
+    active_since: [Option<Instant>; ALARMS.len()],
+    muted: [bool; ALARMS.len()],
+    /// Alarm being played and the step of its pattern
+    playing: Option<usize>,
+    step: usize,
+    next: Instant,
+}
+
+impl Buzzer {
+    pub fn new(mut pwm: SimplePwm<'static, TIM2>) -> Self {
+        pwm.channel(Channel::Ch3).enable();
+        let mut buzzer = Self {
+            pwm,
+            status: Status::default(),
+            active_since: [None; ALARMS.len()],
+            muted: [false; ALARMS.len()],
+            playing: None,
+            step: 0,
+            next: Instant::now(),
+        };
+        buzzer.tone(false);
+        buzzer
+    }
+
+    fn tone(&mut self, on: bool) {
+        let mut channel = self.pwm.channel(Channel::Ch3);
+        if on {
+            channel.set_duty_cycle_fraction(1, 2);
+        } else {
+            channel.set_duty_cycle_fully_off();
+        }
+    }
+
+    /// Alarm to sound, the first one active long enough and not muted.
+    fn sounding(&mut self) -> Option<usize> {
+        let mut sounding = None;
+        for (index, alarm) in ALARMS.iter().enumerate() {
+            if !self.status.is(alarm.condition) {
+                self.active_since[index] = None;
+                self.muted[index] = false;
+                continue;
+            }
+
+            let since = *self.active_since[index].get_or_insert_with(Instant::now);
+            if sounding.is_none() && !self.muted[index] && since.elapsed() >= ALARM_DELAY {
+                sounding = Some(index);
+            }
+        }
+        sounding
+    }
+}
+
+impl StatusIndicator for Buzzer {
+    const NAME: &'static str = "buzzer";
+
+    fn update(&mut self, status: Status) {
+        self.status = status;
+    }
+
+    fn tick(&mut self) -> Instant {
+        let now = Instant::now();
+        if MUTE.swap(false, Ordering::Relaxed) && self.playing.is_some() {
+            info!("Buzzer: muted");
+            for (index, since) in self.active_since.iter().enumerate() {
+                self.muted[index] |= since.is_some();
+            }
+            self.tone(false);
+            self.playing = None;
+            self.step = 0;
+            self.next = now;
+        }
+        if now < self.next {
+            return self.next;
+        }
+
+        // Pick the alarm again at the start of each round of beeps
+        if self.step == 0 {
+            self.playing = self.sounding();
+        }
+        let Some(alarm) = self.playing.map(|index| &ALARMS[index]) else {
+            self.next = now + CHECK_INTERVAL;
+            return self.next;
+        };
+
+        let steps = 2 * usize::from(alarm.beeps);
+        let on = self.step.is_multiple_of(2);
+        let ms = if self.step + 1 == steps {
+            alarm.pause_ms
+        } else {
+            alarm.beep_ms
+        };
+        self.tone(on);
+        self.step = (self.step + 1) % steps;
+        self.next = now + Duration::from_millis(ms);
+        self.next
+    }
+}
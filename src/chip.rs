@@ -0,0 +1,461 @@
+//! Properties of the selected microcontroller.
+//!
+//! The chip is picked with its cargo feature, `stm32f103c8` by default.
+//! build.rs derives the `family` cfg from it, along with the memory layout.
+//! Within a family the parts share the peripherals and registers the
+//! firmware programs directly and differ in the memory sizes only.
+//!
+//! Everything that differs between the families sits in a module per
+//! family below, adding a family means a module with the same items:
+//! constants, the ADC and CRC set up the same way and the raw GPIO access of
+//! the panic handler. The flash programming of `flash` and the clock tree of
+//! `clock` are split the same way.
+
+/// STM32F103, the medium-density parts of the Blue Pill class.
+#[cfg(family = "f1")]
+mod family {
+    use embassy_stm32::Peri;
+    use embassy_stm32::adc::{self, AdcChannel, SampleTime};
+    use embassy_stm32::crc::Crc;
+    use embassy_stm32::pac::{self, gpio::Gpio, gpio::vals::CnfOut, gpio::vals::Mode};
+    use embassy_stm32::peripherals::CRC;
+
+    #[cfg(feature = "stm32f103c8")]
+    pub const FLASH_SIZE: u32 = 64 * 1024;
+    #[cfg(any(feature = "stm32f103cb", feature = "stm32f103rb"))]
+    pub const FLASH_SIZE: u32 = 128 * 1024;
+
+    /// Erase unit of the medium-density parts
+    #[cfg(any(
+        feature = "iap",
+        feature = "auth",
+        feature = "energy",
+        feature = "factory"
+    ))]
+    pub const PAGE_SIZE: u32 = 1024;
+
+    /// Typical internal reference voltage, from
+    /// http://www.st.com/resource/en/datasheet/CD00161566.pdf
+    /// 5.3.4 Embedded reference voltage
+    pub const VREFINT_MV: u32 = 1200;
+
+    /// Vector table of the ROM bootloader, entered without remapping
+    #[cfg(feature = "bootloader")]
+    pub const SYSTEM_MEMORY: u32 = 0x1FFF_F000;
+
+    /// The ROM bootloader sets the vector table offset itself.
+    #[cfg(feature = "bootloader")]
+    pub fn map_system_memory() {}
+
+    /// ADC sample time for the NTC dividers
+    pub const SAMPLE_TIME: SampleTime = SampleTime::CYCLES13_5;
+    /// ADC sample time for sources with a high impedance
+    #[cfg(feature = "analog")]
+    pub const SAMPLE_TIME_LONG: SampleTime = SampleTime::CYCLES239_5;
+
+    /// An ADC converting on interrupt.
+    pub struct Adc<'d, T: adc::Instance>(adc::Adc<'d, T>);
+
+    impl<'d, T: adc::Instance> Adc<'d, T> {
+        pub fn new(adc: Peri<'d, T>, sample_time: SampleTime) -> Self {
+            let mut adc = adc::Adc::new(adc);
+            adc.set_sample_time(sample_time);
+            Self(adc)
+        }
+
+        pub async fn read(&mut self, channel: &mut impl AdcChannel<T>) -> u16 {
+            // The ADC clock stops in STOP mode
+            #[cfg(feature = "low-power")]
+            let _awake = crate::low_power::Awake::new();
+            self.0.read(channel).await
+        }
+    }
+
+    impl<T: adc::Instance> Adc<'_, T> {
+        /// Conversion of the internal reference, ADC1 only.
+        pub async fn read_vrefint(&mut self) -> u16
+        where
+            adc::Vref: AdcChannel<T>,
+        {
+            #[cfg(feature = "low-power")]
+            let _awake = crate::low_power::Awake::new();
+            let mut vrefint = self.0.enable_vref();
+            self.0.read(&mut vrefint).await
+        }
+    }
+
+    /// The CRC unit, fixed to the CRC-32 of `tools/seal_image.py`.
+    pub fn crc(crc: Peri<'_, CRC>) -> Crc<'_> {
+        Crc::new(crc)
+    }
+
+    /// Clock the GPIO ports of the core pins.
+    pub fn enable_gpio() {
+        pac::RCC.apb2enr().modify(|w| {
+            w.set_gpioaen(true);
+            w.set_gpioben(true);
+            w.set_gpiocen(true);
+        });
+    }
+
+    /// Configure a pin as a slow push-pull output.
+    pub fn push_pull((port, pin): (Gpio, usize)) {
+        port.cr(pin / 8).modify(|w| {
+            w.set_mode(pin % 8, Mode::OUTPUT2MHZ);
+            w.set_cnf_out(pin % 8, CnfOut::PUSH_PULL);
+        });
+    }
+
+    /// Release PA15, PB3 and PB4 from the JTAG port, SWD keeps working.
+    #[cfg(any(feature = "buttons", feature = "encoder", feature = "sg-ready"))]
+    pub fn free_jtag_pins() {
+        use embassy_stm32::pac::afio::vals::SwjCfg;
+
+        pac::AFIO
+            .mapr()
+            .modify(|w| w.set_swj_cfg(SwjCfg::JTAG_DISABLE));
+    }
+}
+
+/// STM32F030, the Cortex-M0 value line with 8 KB of RAM and 1 KB pages.
+#[cfg(family = "f0")]
+mod family {
+    use embassy_stm32::Peri;
+    use embassy_stm32::adc::{self, AdcChannel, SampleTime};
+    use embassy_stm32::crc::{Config, Crc, InputReverseConfig};
+    use embassy_stm32::interrupt::typelevel::Binding;
+    use embassy_stm32::pac::{self, gpio::Gpio, gpio::vals::Moder, gpio::vals::Ot};
+    use embassy_stm32::peripherals::CRC;
+
+    pub const FLASH_SIZE: u32 = 64 * 1024;
+
+    /// Erase unit of the STM32F030x8
+    #[cfg(any(
+        feature = "iap",
+        feature = "auth",
+        feature = "energy",
+        feature = "factory"
+    ))]
+    pub const PAGE_SIZE: u32 = 1024;
+
+    /// Typical internal reference voltage, from
+    /// https://www.st.com/resource/en/datasheet/stm32f030c8.pdf
+    /// 6.3.4 Embedded reference voltage
+    pub const VREFINT_MV: u32 = 1230;
+
+    /// Vector table of the ROM bootloader
+    #[cfg(feature = "bootloader")]
+    pub const SYSTEM_MEMORY: u32 = 0x1FFF_EC00;
+
+    /// Map the system memory to address 0, where the Cortex-M0 without a
+    /// vector table offset takes the interrupts of the ROM bootloader from.
+    #[cfg(feature = "bootloader")]
+    pub fn map_system_memory() {
+        use embassy_stm32::pac::syscfg::vals::MemMode;
+
+        pac::RCC.apb2enr().modify(|w| w.set_syscfgen(true));
+        pac::SYSCFG
+            .cfgr1()
+            .modify(|w| w.set_mem_mode(MemMode::SYSTEM_FLASH));
+    }
+
+    /// ADC sample time for the NTC dividers
+    pub const SAMPLE_TIME: SampleTime = SampleTime::CYCLES13_5;
+
+    /// An ADC converting on interrupt.
+    pub struct Adc<'d, T: adc::Instance>(adc::Adc<'d, T>);
+
+    impl<'d, T: adc::Instance> Adc<'d, T> {
+        pub fn new(adc: Peri<'d, T>, sample_time: SampleTime) -> Self
+        where
+            crate::Irqs: Binding<T::Interrupt, adc::InterruptHandler<T>>,
+        {
+            // The ADC runs from the dedicated 14 MHz RC oscillator, which the
+            // HAL leaves off
+            pac::RCC.cr2().modify(|w| w.set_hsi14on(true));
+            while !pac::RCC.cr2().read().hsi14rdy() {}
+
+            let mut adc = adc::Adc::new(adc, crate::Irqs);
+            adc.set_sample_time(sample_time);
+            Self(adc)
+        }
+
+        pub async fn read(&mut self, channel: &mut impl AdcChannel<T>) -> u16 {
+            self.0.read(channel).await
+        }
+    }
+
+    impl<T: adc::Instance> Adc<'_, T> {
+        /// Conversion of the internal reference.
+        pub async fn read_vrefint(&mut self) -> u16
+        where
+            adc::Vref: AdcChannel<T>,
+        {
+            let mut vrefint = self.0.enable_vref();
+            self.0.read(&mut vrefint).await
+        }
+    }
+
+    /// The CRC unit, set up for the CRC-32 of `tools/seal_image.py`. The
+    /// polynomial is fixed like on the F1, the initial value is not.
+    pub fn crc(crc: Peri<'_, CRC>) -> Crc<'_> {
+        let config = Config::new(InputReverseConfig::None, false, 0xFFFF_FFFF);
+        // Only a polynomial is ever rejected
+        Crc::new(crc, config.unwrap())
+    }
+
+    /// Clock the GPIO ports of the core pins.
+    pub fn enable_gpio() {
+        pac::RCC.ahbenr().modify(|w| {
+            w.set_gpioaen(true);
+            w.set_gpioben(true);
+            w.set_gpiocen(true);
+        });
+    }
+
+    /// Configure a pin as a push-pull output, slow after reset.
+    pub fn push_pull((port, pin): (Gpio, usize)) {
+        port.otyper().modify(|w| w.set_ot(pin, Ot::PUSH_PULL));
+        port.moder().modify(|w| w.set_moder(pin, Moder::OUTPUT));
+    }
+
+    /// PA15, PB3 and PB4 are plain GPIOs already, the F0 has no JTAG.
+    #[cfg(any(feature = "buttons", feature = "encoder", feature = "sg-ready"))]
+    pub fn free_jtag_pins() {}
+}
+
+/// STM32G030, a Cortex-M0+ replacement with 8 KB of RAM and 2 KB pages.
+#[cfg(family = "g0")]
+mod family {
+    use embassy_stm32::Peri;
+    use embassy_stm32::adc::{self, AdcChannel, SampleTime};
+    use embassy_stm32::crc::{Config, Crc, InputReverseConfig, PolySize};
+    use embassy_stm32::pac::{self, gpio::Gpio, gpio::vals::Moder, gpio::vals::Ot};
+    use embassy_stm32::peripherals::CRC;
+
+    pub const FLASH_SIZE: u32 = 64 * 1024;
+
+    /// Erase unit of the G0
+    #[cfg(any(
+        feature = "iap",
+        feature = "auth",
+        feature = "energy",
+        feature = "factory"
+    ))]
+    pub const PAGE_SIZE: u32 = 2048;
+
+    /// Typical internal reference voltage, from
+    /// https://www.st.com/resource/en/datasheet/stm32g030c6.pdf
+    /// 5.3.5 Embedded reference voltage
+    pub const VREFINT_MV: u32 = 1212;
+
+    /// Vector table of the ROM bootloader, entered without remapping
+    #[cfg(feature = "bootloader")]
+    pub const SYSTEM_MEMORY: u32 = 0x1FFF_0000;
+
+    /// The ROM bootloader sets the vector table offset itself.
+    #[cfg(feature = "bootloader")]
+    pub fn map_system_memory() {}
+
+    /// ADC sample time for the NTC dividers
+    pub const SAMPLE_TIME: SampleTime = SampleTime::CYCLES12_5;
+
+    /// An ADC converting by polling, the G0 driver has no interrupt mode.
+    pub struct Adc<'d, T: adc::Instance>(adc::Adc<'d, T>);
+
+    impl<'d, T: adc::Instance> Adc<'d, T> {
+        pub fn new(adc: Peri<'d, T>, sample_time: SampleTime) -> Self {
+            let mut adc = adc::Adc::new(adc);
+            adc.set_sample_time(sample_time);
+            Self(adc)
+        }
+
+        pub async fn read(&mut self, channel: &mut impl AdcChannel<T>) -> u16 {
+            self.0.blocking_read(channel)
+        }
+    }
+
+    impl<T: adc::Instance> Adc<'_, T> {
+        /// Conversion of the internal reference.
+        pub async fn read_vrefint(&mut self) -> u16
+        where
+            adc::VrefInt: AdcChannel<T>,
+        {
+            let mut vrefint = self.0.enable_vrefint();
+            self.0.blocking_read(&mut vrefint)
+        }
+    }
+
+    /// The CRC unit, set up for the CRC-32 of `tools/seal_image.py` like the
+    /// fixed one of the F1.
+    pub fn crc(crc: Peri<'_, CRC>) -> Crc<'_> {
+        let config = Config::new(
+            InputReverseConfig::None,
+            false,
+            PolySize::Width32,
+            0xFFFF_FFFF,
+            0x04C1_1DB7,
+        );
+        // The polynomial is a valid 32-bit one
+        Crc::new(crc, config.unwrap())
+    }
+
+    /// Clock the GPIO ports of the core pins.
+    pub fn enable_gpio() {
+        pac::RCC.gpioenr().modify(|w| {
+            w.set_gpioaen(true);
+            w.set_gpioben(true);
+            w.set_gpiocen(true);
+        });
+    }
+
+    /// Configure a pin as a push-pull output, slow after reset.
+    pub fn push_pull((port, pin): (Gpio, usize)) {
+        port.otyper().modify(|w| w.set_ot(pin, Ot::PUSH_PULL));
+        port.moder().modify(|w| w.set_moder(pin, Moder::OUTPUT));
+    }
+
+    /// PA15, PB3 and PB4 are plain GPIOs already, the G0 has no JTAG.
+    #[cfg(any(feature = "buttons", feature = "sg-ready"))]
+    pub fn free_jtag_pins() {}
+}
+
+/// STM32L053, an ultra-low-power Cortex-M0+ with 8 KB of RAM.
+#[cfg(family = "l0")]
+mod family {
+    use embassy_stm32::Peri;
+    use embassy_stm32::adc::{self, AdcChannel, SampleTime};
+    use embassy_stm32::crc::{Config, Crc, InputReverseConfig, PolySize};
+    use embassy_stm32::interrupt::typelevel::Binding;
+    use embassy_stm32::pac::{self, gpio::Gpio, gpio::vals::Moder, gpio::vals::Ot};
+    use embassy_stm32::peripherals::CRC;
+
+    pub const FLASH_SIZE: u32 = 64 * 1024;
+
+    /// Typical internal reference voltage, from
+    /// https://www.st.com/resource/en/datasheet/stm32l053c8.pdf
+    /// 6.3.3 Embedded internal reference voltage
+    pub const VREFINT_MV: u32 = 1224;
+
+    /// Vector table of the ROM bootloader, entered without remapping
+    #[cfg(feature = "bootloader")]
+    pub const SYSTEM_MEMORY: u32 = 0x1FF0_0000;
+
+    /// The ROM bootloader sets the vector table offset itself.
+    #[cfg(feature = "bootloader")]
+    pub fn map_system_memory() {}
+
+    /// ADC sample time for the NTC dividers
+    pub const SAMPLE_TIME: SampleTime = SampleTime::CYCLES12_5;
+
+    /// An ADC converting on interrupt, clocked from APB2.
+    pub struct Adc<'d, T: adc::Instance>(adc::Adc<'d, T>);
+
+    impl<'d, T: adc::Instance> Adc<'d, T> {
+        pub fn new(adc: Peri<'d, T>, sample_time: SampleTime) -> Self
+        where
+            crate::Irqs: Binding<T::Interrupt, adc::InterruptHandler<T>>,
+        {
+            let mut adc = adc::Adc::new(adc, crate::Irqs);
+            adc.set_sample_time(sample_time);
+            Self(adc)
+        }
+
+        pub async fn read(&mut self, channel: &mut impl AdcChannel<T>) -> u16 {
+            self.0.read(channel).await
+        }
+    }
+
+    impl<T: adc::Instance> Adc<'_, T> {
+        /// Conversion of the internal reference.
+        pub async fn read_vrefint(&mut self) -> u16
+        where
+            adc::Vref: AdcChannel<T>,
+        {
+            let mut vrefint = self.0.enable_vref();
+            self.0.read(&mut vrefint).await
+        }
+    }
+
+    /// The CRC unit, set up for the CRC-32 of `tools/seal_image.py` like the
+    /// fixed one of the F1.
+    pub fn crc(crc: Peri<'_, CRC>) -> Crc<'_> {
+        let config = Config::new(
+            InputReverseConfig::None,
+            false,
+            PolySize::Width32,
+            0xFFFF_FFFF,
+            0x04C1_1DB7,
+        );
+        // The polynomial is a valid 32-bit one
+        Crc::new(crc, config.unwrap())
+    }
+
+    /// Clock the GPIO ports of the core pins.
+    pub fn enable_gpio() {
+        pac::RCC.gpioenr().modify(|w| {
+            w.set_gpioaen(true);
+            w.set_gpioben(true);
+            w.set_gpiocen(true);
+        });
+    }
+
+    /// Configure a pin as a push-pull output, slow after reset.
+    pub fn push_pull((port, pin): (Gpio, usize)) {
+        port.otyper().modify(|w| w.set_ot(pin, Ot::PUSH_PULL));
+        port.moder().modify(|w| w.set_moder(pin, Moder::OUTPUT));
+    }
+
+    /// PA15, PB3 and PB4 are plain GPIOs already, the L0 has no JTAG.
+    #[cfg(any(feature = "buttons", feature = "sg-ready"))]
+    pub fn free_jtag_pins() {}
+}
+
+pub use family::*;
+
+// Peripherals the smaller parts lack
+#[cfg(all(not(family = "f1"), any(feature = "analog", feature = "energy")))]
+compile_error!("features `analog` and `energy` use ADC2, only the F1 has a second ADC");
+#[cfg(all(any(family = "f0", family = "g0"), feature = "usb"))]
+compile_error!("feature `usb` needs the USB peripheral, the STM32F030 and STM32G030 have none");
+#[cfg(all(not(family = "f1"), any(feature = "bacnet", feature = "mbus")))]
+compile_error!("features `bacnet` and `mbus` use USART3, the F0, G0 and L0 parts have two USARTs");
+#[cfg(all(any(family = "f0", family = "g0"), feature = "buzzer"))]
+compile_error!("feature `buzzer` uses TIM2, the STM32F030 and STM32G030 have none");
+#[cfg(all(
+    family = "g0",
+    any(feature = "fan", feature = "encoder", feature = "pwm-input")
+))]
+compile_error!(
+    "features `fan`, `encoder` and `pwm-input` use TIM3, the time driver of the STM32G030"
+);
+#[cfg(all(
+    family = "l0",
+    any(feature = "fan", feature = "encoder", feature = "pwm-input")
+))]
+compile_error!("features `fan`, `encoder` and `pwm-input` use TIM3, the STM32L053 has none");
+#[cfg(all(family = "l0", feature = "rgb-led"))]
+compile_error!("feature `rgb-led` uses TIM1, the STM32L053 has none");
+// F1 registers programmed directly, not ported
+#[cfg(all(not(family = "f1"), feature = "rtc"))]
+compile_error!("feature `rtc` drives the counter RTC of the F1, the others have a calendar RTC");
+#[cfg(all(not(family = "f1"), feature = "power"))]
+compile_error!("feature `power` gates the F1 peripheral clocks");
+#[cfg(all(family = "l0", feature = "buzzer"))]
+compile_error!("feature `buzzer` remaps TIM2 through the AFIO of the F1");
+#[cfg(all(family = "l0", feature = "usb"))]
+compile_error!("feature `usb` clocks the USB peripheral from the PLL of the F1");
+#[cfg(all(family = "g0", feature = "iap"))]
+compile_error!("feature `iap` copies the image by half-word, the G0 programs double-words");
+#[cfg(all(
+    family = "l0",
+    any(
+        feature = "iap",
+        feature = "auth",
+        feature = "energy",
+        feature = "factory"
+    )
+))]
+compile_error!(
+    "features `iap`, `auth`, `energy` and `factory` expect erased flash to read as ones, the L0 erases to zeros"
+);
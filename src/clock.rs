@@ -0,0 +1,208 @@
+//! Clock tree setup.
+//!
+//! Without further features the controller runs from its internal RC
+//! oscillator (HSI), which is within ±1 % at room temperature but drifts
+//! with it. `hse` switches to the 8 MHz external clock of the board
+//! multiplied by the PLL, `low-clock` runs at 4 MHz from the HSI instead.
+//!
+//! The F1 runs from the 8 MHz HSI, or at its maximum of 72 MHz with `hse`.
+//! The ADC clock is kept at the fastest setting within its 14 MHz limit
+//! either way.
+//!
+//! The F0 runs from the 8 MHz HSI, or at its maximum of 48 MHz with `hse`.
+//! Its ADC has an RC oscillator of its own, see `chip`.
+//!
+//! The G0 runs from the 16 MHz HSI, or at its maximum of 64 MHz with `hse`.
+//! Its ADC is clocked from the system clock.
+//!
+//! The L0 runs from the 16 MHz HSI, or at its maximum of 32 MHz with `hse`.
+//! `low-clock` keeps the 4 MHz MSI it starts from. Its ADC is clocked from
+//! APB2.
+
+use embassy_stm32::Config;
+
+#[cfg(family = "f1")]
+mod family {
+    use embassy_stm32::rcc::{ADCPrescaler, AHBPrescaler, APBPrescaler, Config};
+    #[cfg(feature = "hse")]
+    use embassy_stm32::rcc::{Hse, Pll, PllMul, PllPreDiv, PllSource, Sysclk};
+
+    #[cfg(feature = "hse")]
+    use crate::board;
+
+    pub fn rcc(rcc: &mut Config) {
+        // 72 MHz, the 48 MHz for USB is the PLL output divided by 1.5
+        #[cfg(feature = "hse")]
+        {
+            let (freq, mode) = board::HSE;
+            rcc.hse = Some(Hse { freq, mode });
+            rcc.pll = Some(Pll {
+                src: PllSource::HSE,
+                prediv: PllPreDiv::DIV1,
+                mul: PllMul::MUL9,
+            });
+            rcc.sys = Sysclk::PLL1_P;
+            rcc.ahb_pre = AHBPrescaler::DIV1;
+            // APB1 is limited to 36 MHz
+            rcc.apb1_pre = APBPrescaler::DIV2;
+            rcc.apb2_pre = APBPrescaler::DIV1;
+            rcc.adc_pre = ADCPrescaler::DIV6;
+        }
+
+        // 4 MHz, still enough for the I2C peripheral and 115200 baud
+        #[cfg(feature = "low-clock")]
+        {
+            rcc.ahb_pre = AHBPrescaler::DIV2;
+            rcc.apb1_pre = APBPrescaler::DIV1;
+            rcc.apb2_pre = APBPrescaler::DIV1;
+            rcc.adc_pre = ADCPrescaler::DIV2;
+        }
+
+        // 8 MHz
+        #[cfg(not(any(feature = "hse", feature = "low-clock")))]
+        {
+            rcc.ahb_pre = AHBPrescaler::DIV1;
+            rcc.apb1_pre = APBPrescaler::DIV1;
+            rcc.apb2_pre = APBPrescaler::DIV1;
+            rcc.adc_pre = ADCPrescaler::DIV2;
+        }
+    }
+}
+
+#[cfg(family = "f0")]
+mod family {
+    use embassy_stm32::rcc::{AHBPrescaler, APBPrescaler, Config};
+    #[cfg(feature = "hse")]
+    use embassy_stm32::rcc::{Hse, Pll, PllMul, PllPreDiv, PllSource, Sysclk};
+
+    #[cfg(feature = "hse")]
+    use crate::board;
+
+    pub fn rcc(rcc: &mut Config) {
+        // 48 MHz
+        #[cfg(feature = "hse")]
+        {
+            let (freq, mode) = board::HSE;
+            rcc.hse = Some(Hse { freq, mode });
+            rcc.pll = Some(Pll {
+                src: PllSource::HSE,
+                prediv: PllPreDiv::DIV1,
+                mul: PllMul::MUL6,
+            });
+            rcc.sys = Sysclk::PLL1_P;
+        }
+
+        // 4 MHz, still enough for the I2C peripheral and 115200 baud
+        #[cfg(feature = "low-clock")]
+        {
+            rcc.ahb_pre = AHBPrescaler::DIV2;
+        }
+
+        // 8 MHz
+        #[cfg(not(feature = "low-clock"))]
+        {
+            rcc.ahb_pre = AHBPrescaler::DIV1;
+        }
+
+        rcc.apb1_pre = APBPrescaler::DIV1;
+    }
+}
+
+#[cfg(family = "g0")]
+mod family {
+    use embassy_stm32::rcc::{AHBPrescaler, APBPrescaler, Config};
+    #[cfg(feature = "hse")]
+    use embassy_stm32::rcc::{Hse, Pll, PllMul, PllPreDiv, PllRDiv, PllSource, Sysclk};
+    #[cfg(feature = "low-clock")]
+    use embassy_stm32::rcc::{Hsi, HsiSysDiv};
+
+    #[cfg(feature = "hse")]
+    use crate::board;
+
+    pub fn rcc(rcc: &mut Config) {
+        // 64 MHz, the PLL at 128 MHz divided by 2
+        #[cfg(feature = "hse")]
+        {
+            let (freq, mode) = board::HSE;
+            rcc.hse = Some(Hse { freq, mode });
+            rcc.pll = Some(Pll {
+                source: PllSource::HSE,
+                prediv: PllPreDiv::DIV1,
+                mul: PllMul::MUL16,
+                divp: None,
+                divq: None,
+                divr: Some(PllRDiv::DIV2),
+            });
+            rcc.sys = Sysclk::PLL1_R;
+        }
+
+        // 4 MHz, still enough for the I2C peripheral and 115200 baud
+        #[cfg(feature = "low-clock")]
+        {
+            rcc.hsi = Some(Hsi {
+                sys_div: HsiSysDiv::DIV4,
+            });
+        }
+
+        rcc.ahb_pre = AHBPrescaler::DIV1;
+        rcc.apb1_pre = APBPrescaler::DIV1;
+    }
+}
+
+#[cfg(family = "l0")]
+mod family {
+    #[cfg(not(feature = "low-clock"))]
+    use embassy_stm32::rcc::Sysclk;
+    use embassy_stm32::rcc::{AHBPrescaler, APBPrescaler, Config};
+    #[cfg(feature = "hse")]
+    use embassy_stm32::rcc::{Hse, Pll, PllDiv, PllMul, PllSource};
+
+    #[cfg(feature = "hse")]
+    use crate::board;
+
+    pub fn rcc(rcc: &mut Config) {
+        // 32 MHz, the PLL at 64 MHz divided by 2
+        #[cfg(feature = "hse")]
+        {
+            let (freq, mode) = board::HSE;
+            rcc.hse = Some(Hse { freq, mode });
+            rcc.pll = Some(Pll {
+                source: PllSource::HSE,
+                mul: PllMul::MUL8,
+                div: PllDiv::DIV2,
+            });
+            rcc.sys = Sysclk::PLL1_R;
+        }
+
+        // 16 MHz
+        #[cfg(not(any(feature = "hse", feature = "low-clock")))]
+        {
+            rcc.hsi = true;
+            rcc.sys = Sysclk::HSI;
+        }
+
+        rcc.ahb_pre = AHBPrescaler::DIV1;
+        rcc.apb1_pre = APBPrescaler::DIV1;
+        rcc.apb2_pre = APBPrescaler::DIV1;
+    }
+}
+
+/// HAL configuration with the clock tree selected by the features.
+pub fn config() -> Config {
+    let mut config = Config::default();
+    family::rcc(&mut config.rcc);
+
+    // Waits for the crystal to start, which never happens without one
+    #[cfg(feature = "rtc")]
+    {
+        config.rcc.ls = embassy_stm32::rcc::LsConfig::default_lse();
+    }
+
+    // Keeping the debug port clocked in STOP mode costs more than the rest
+    #[cfg(all(feature = "low-power", not(feature = "defmt")))]
+    {
+        config.enable_debug_during_sleep = false;
+    }
+
+    config
+}
@@ -0,0 +1,7 @@
+//! Tuning constants generated by build.rs from `heat_doors.toml` and the
+//! environment, see the README for the names and defaults.
+
+// Not every feature set uses all of them
+#![allow(dead_code)]
+
+include!(concat!(env!("OUT_DIR"), "/config.rs"));
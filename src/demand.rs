@@ -0,0 +1,85 @@
+//! Demand from an external controller, overriding the local regulation.
+//!
+//! The interfaces pass the signal as a fraction in per mille. Depending on
+//! `DEMAND_MODE` it sets the regulation setpoint or drives the valve to a position
+//! directly. Once the interface reports the signal lost, the local setpoint
+//! from before the takeover is restored.
+
+use crate::config::DEMAND_MODE;
+use crate::fmt::info;
+use crate::state;
+
+/// What the external signal controls.
+pub enum DemandMode {
+    /// Setpoint between `SETPOINT_LOW` at 0 % and `SETPOINT_HIGH` at 100 %
+    Setpoint,
+    /// Valve opening in %
+    ValvePosition,
+}
+
+pub const FULL_SCALE: u32 = 1000;
+const SETPOINT_LOW: f32 = 10.0;
+const SETPOINT_HIGH: f32 = 80.0;
+/// Setpoint changes smaller than this are ignored
+const SETPOINT_DEADBAND: f32 = 0.5;
+
+pub struct ExternalDemand {
+    active: bool,
+    local_setpoint: f32,
+    applied_setpoint: f32,
+}
+
+impl ExternalDemand {
+    pub const fn new() -> Self {
+        Self {
+            active: false,
+            local_setpoint: f32::NAN,
+            applied_setpoint: f32::NAN,
+        }
+    }
+
+    /// Apply a demand between 0 and `FULL_SCALE`, taking over on the first one.
+    pub fn apply(&mut self, demand: u32) {
+        if !self.active {
+            info!("Demand: external control active");
+            self.active = true;
+            self.local_setpoint = state::get().setpoint;
+            self.applied_setpoint = f32::NAN;
+        }
+
+        let demand = demand.min(FULL_SCALE);
+        match DEMAND_MODE {
+            DemandMode::Setpoint => {
+                let setpoint = SETPOINT_LOW
+                    + (SETPOINT_HIGH - SETPOINT_LOW) * demand as f32 / FULL_SCALE as f32;
+                if (setpoint - self.applied_setpoint).abs() >= SETPOINT_DEADBAND
+                    || self.applied_setpoint.is_nan()
+                {
+                    self.applied_setpoint = setpoint;
+                    state::update(|s| s.setpoint = setpoint);
+                }
+            }
+            DemandMode::ValvePosition => {
+                let position = (demand * 100 / FULL_SCALE) as u8;
+                state::update(|s| s.valve_demand = Some(position));
+            }
+        }
+    }
+
+    /// Hand control back to the local regulation.
+    pub fn release(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        info!("Demand: back to local control");
+        self.active = false;
+        match DEMAND_MODE {
+            DemandMode::Setpoint => {
+                let setpoint = self.local_setpoint;
+                state::update(|s| s.setpoint = setpoint);
+            }
+            DemandMode::ValvePosition => state::update(|s| s.valve_demand = None),
+        }
+    }
+}
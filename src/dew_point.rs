@@ -0,0 +1,16 @@
+//! Condensation protection for cooling with a humidity sensor.
+//!
+//! With a reverse-acting (chilled-water) valve the on-board NTC measures the
+//! supply pipe, the coldest surface of the emitter. The valve is held from
+//! opening, and closed step by step, while that pipe is less than
+//! [`heat_control::dew_point::MARGIN`] above the dew point of the room air.
+
+pub use heat_control::dew_point::dew_point;
+
+use crate::config::ACTION;
+use crate::state::SystemState;
+
+/// Whether more chilled water would cool the pipe towards the dew point.
+pub fn risk(state: &SystemState) -> bool {
+    heat_control::dew_point::risk(ACTION, state.pipe_temperature, state.dew_point)
+}
@@ -0,0 +1,116 @@
+//! Refresh and status pages shared by the display backends.
+
+use embassy_time::{Duration, Instant};
+
+use crate::fmt::warn;
+use crate::indicator::{Status, StatusIndicator};
+#[cfg(feature = "input")]
+use crate::manual::INPUT_EVENTS;
+#[cfg(feature = "input")]
+use crate::menu::{Menu, Screen};
+use crate::state;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+#[cfg(feature = "input")]
+const INPUT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A display able to render the status page and the menu.
+pub trait Backend {
+    const NAME: &'static str;
+
+    /// Initialise the controller, false if it does not respond.
+    fn init(&mut self) -> bool;
+    fn draw_status(&mut self, status: Status);
+    #[cfg(feature = "input")]
+    fn draw_menu(&mut self, selected: usize);
+    /// Send the drawn frame, false if the display does not respond.
+    fn flush(&mut self) -> bool;
+}
+
+/// Operating mode shown on the status page.
+pub fn mode() -> &'static str {
+    if state::heating_paused() {
+        "PAUSED"
+    } else if state::valve_demand().is_some() {
+        "EXTERNAL"
+    } else {
+        #[cfg(feature = "manual")]
+        if state::get().manual {
+            return "MANUAL";
+        }
+        "AUTO"
+    }
+}
+
+/// Status indicator on a display backend, running the menu on local input.
+pub struct Display<B: Backend> {
+    backend: B,
+    status: Status,
+    ready: bool,
+    next_refresh: Instant,
+    #[cfg(feature = "input")]
+    menu: Menu,
+}
+
+impl<B: Backend> Display<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            status: Status::default(),
+            ready: false,
+            next_refresh: Instant::now(),
+            #[cfg(feature = "input")]
+            menu: Menu::new(),
+        }
+    }
+
+    fn refresh(&mut self) {
+        if !self.ready {
+            self.ready = self.backend.init();
+            if !self.ready {
+                warn!("{}: no response", B::NAME);
+                return;
+            }
+        }
+
+        #[cfg(feature = "input")]
+        match self.menu.screen() {
+            Screen::Status => self.backend.draw_status(self.status),
+            Screen::Menu(selected) => self.backend.draw_menu(selected),
+        }
+        #[cfg(not(feature = "input"))]
+        self.backend.draw_status(self.status);
+
+        // Initialise the display again after it lost power
+        self.ready = self.backend.flush();
+    }
+}
+
+impl<B: Backend> StatusIndicator for Display<B> {
+    const NAME: &'static str = B::NAME;
+
+    fn update(&mut self, status: Status) {
+        self.status = status;
+        self.next_refresh = Instant::now();
+    }
+
+    fn tick(&mut self) -> Instant {
+        #[cfg(feature = "input")]
+        while let Ok(event) = INPUT_EVENTS.try_receive() {
+            self.menu.handle(event);
+            self.next_refresh = Instant::now();
+        }
+
+        let now = Instant::now();
+        if now >= self.next_refresh {
+            self.refresh();
+            self.next_refresh = now + REFRESH_INTERVAL;
+        }
+
+        // Look for input often enough for the menu to feel responsive
+        #[cfg(feature = "input")]
+        return self.next_refresh.min(now + INPUT_INTERVAL);
+        #[cfg(not(feature = "input"))]
+        self.next_refresh
+    }
+}
@@ -0,0 +1,59 @@
+//! Rotary encoder on PA6/PA7 (TIM3 in encoder mode) with its push button on
+//! PA15, closing to ground.
+//!
+//! It works like the buttons, turning for up and down and pushing for mode.
+//! Detents following each other within [`FAST_INTERVAL`] count
+//! [`FAST_FACTOR`] times.
+
+use embassy_executor::task;
+use embassy_futures::select::{Either, select};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::peripherals::TIM3;
+use embassy_stm32::timer::qei::Qei;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::fmt::info;
+use crate::manual::{self, InputEvent};
+
+/// Encoder mode counts both edges of both channels
+const COUNTS_PER_DETENT: i16 = 4;
+const FAST_INTERVAL: Duration = Duration::from_millis(60);
+const FAST_FACTOR: i32 = 4;
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const DEBOUNCE: Duration = Duration::from_millis(30);
+
+#[task]
+pub async fn encoder(qei: Qei<'static, TIM3>, mut button: ExtiInput<'static>) {
+    let mut last_count = qei.count();
+    let mut last_turn = Instant::MIN;
+
+    info!("Starting rotary encoder");
+    loop {
+        match select(Timer::after(POLL_INTERVAL), button.wait_for_falling_edge()).await {
+            Either::First(()) => {
+                let delta = qei.count().wrapping_sub(last_count) as i16;
+                let detents = delta / COUNTS_PER_DETENT;
+                if detents == 0 {
+                    continue;
+                }
+                // Keep the partial detent for the next poll
+                last_count = last_count.wrapping_add((detents * COUNTS_PER_DETENT) as u16);
+
+                let mut steps = i32::from(detents);
+                if last_turn.elapsed() < FAST_INTERVAL {
+                    steps *= FAST_FACTOR;
+                }
+                last_turn = Instant::now();
+                manual::input(InputEvent::Turn(steps));
+            }
+            Either::Second(()) => {
+                Timer::after(DEBOUNCE).await;
+                if button.is_low() {
+                    manual::input(InputEvent::Push);
+                    button.wait_for_high().await;
+                    Timer::after(DEBOUNCE).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Heat meter from the flow and the supply/return temperature difference.
+//!
+//! The return temperature is measured by a second NTC on PA5 (ADC2), wired
+//! like the on-board one, the supply temperature is the regulation reading.
+//! The delivered power is integrated into a running energy total, which is
+//! saved to flash every [`SAVE_INTERVAL`] so a restart loses at most that
+//! much of it.
+
+use embassy_executor::task;
+use embassy_stm32::Peri;
+use embassy_stm32::peripherals::ADC2;
+use embassy_time::{Duration, Instant, Ticker};
+
+use crate::board::EnergyPin;
+use crate::chip;
+use crate::flash::{ENERGY_PAGES, Log};
+use crate::fmt::info;
+use crate::log::log;
+use crate::ntc::adc_to_temperature_c;
+use crate::state;
+
+/// Specific heat capacity of water in J/(kg K), one litre taken as one kg
+const SPECIFIC_HEAT: f32 = 4186.0;
+const MEASURE_INTERVAL: Duration = Duration::from_secs(1);
+const SAVE_INTERVAL: Duration = Duration::from_secs(3600);
+const JOULES_PER_WH: u32 = 3600;
+
+static TOTAL: Log = Log::new(ENERGY_PAGES);
+
+/// Heat power in W carried by `flow` ml/min cooling from `supply` to `back`.
+fn power(flow: u32, supply: f32, back: f32) -> u32 {
+    let difference = supply - back;
+    // Unknown temperatures and heat flowing back count as no power
+    if difference > 0.0 {
+        (flow as f32 / 60_000.0 * SPECIFIC_HEAT * difference) as u32
+    } else {
+        0
+    }
+}
+
+#[task]
+pub async fn energy(pin: Peri<'static, EnergyPin>, adc: Peri<'static, ADC2>) {
+    let mut adc = chip::Adc::new(adc, chip::SAMPLE_TIME);
+    let mut pin = pin;
+
+    let mut energy = TOTAL.last().unwrap_or(0);
+    let mut saved = energy;
+    let mut joules = 0;
+    let mut last_save = Instant::now();
+    state::update(|s| s.heat_energy = energy);
+    info!("Starting heat meter, {} Wh", energy);
+
+    let seconds = MEASURE_INTERVAL.as_secs() as u32;
+    let mut ticker = Ticker::every(MEASURE_INTERVAL);
+    loop {
+        ticker.next().await;
+
+        let back = adc_to_temperature_c(adc.read(&mut pin).await);
+        let state = state::get();
+        let power = power(state.flow, state.temperature, back);
+
+        joules += power * seconds;
+        energy = energy.saturating_add(joules / JOULES_PER_WH);
+        joules %= JOULES_PER_WH;
+        log!(Sensors, trace, "Heat: {} W, {} Wh", power, energy);
+        state::update(|s| {
+            s.return_temperature = back;
+            s.heat_power = power;
+            s.heat_energy = energy;
+        });
+
+        if energy != saved && last_save.elapsed() >= SAVE_INTERVAL {
+            TOTAL.append(energy.min(Log::ERASED - 1));
+            saved = energy;
+            last_save = Instant::now();
+        }
+    }
+}
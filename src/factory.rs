@@ -0,0 +1,118 @@
+//! Hidden test mode for the production test fixture.
+//!
+//! The `factory` shell command, not listed in the help, drives the motor to
+//! the safe state and parks its task, then the outputs can be switched one at
+//! a time by their pin level, the raw ADC counts read and the calibration
+//! written. The status LED pattern stops, tasks switching the optional
+//! outputs still do so on a change of their input. A reset leaves the mode.
+//!
+//! The calibration is kept in its own flash page and applied from then on,
+//! also in normal operation.
+
+use core::sync::atomic::{AtomicBool, AtomicI16, AtomicU16, Ordering};
+
+#[cfg(any(feature = "pump", feature = "boiler", feature = "stages"))]
+use embassy_stm32::pac;
+use embassy_stm32::pac::gpio::Gpio;
+use micromath::F32Ext;
+
+use crate::board;
+use crate::flash::{CALIBRATION_PAGES, Log};
+use crate::fmt::info;
+
+/// Outputs by name, switched directly through the port registers
+pub const OUTPUTS: &[(&str, (Gpio, usize))] = &[
+    ("motor-enable", board::MOTOR_ENABLE),
+    ("motor-direction", board::MOTOR_DIRECTION),
+    ("led", board::STATUS_LED),
+    #[cfg(feature = "pump")]
+    ("pump", (pac::GPIOA, 4)),
+    #[cfg(feature = "boiler")]
+    ("boiler", (pac::GPIOB, 8)),
+    #[cfg(feature = "stages")]
+    ("stage1", (pac::GPIOB, 11)),
+    #[cfg(feature = "stages")]
+    ("stage2", (pac::GPIOB, 12)),
+];
+
+/// Largest NTC offset accepted in K
+pub const NTC_OFFSET_MAX: f32 = 10.0;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static STREAM: AtomicBool = AtomicBool::new(false);
+static NTC_RAW: AtomicU16 = AtomicU16::new(0);
+static VREFINT_RAW: AtomicU16 = AtomicU16::new(0);
+/// NTC offset in tenths of K
+static NTC_OFFSET: AtomicI16 = AtomicI16::new(0);
+static CALIBRATION: Log = Log::new(CALIBRATION_PAGES);
+
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Enter the test mode, once the motor is in the safe state.
+pub fn activate() {
+    info!("Entering factory test mode");
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Set an output by name to a high or low level, `false` if there is none.
+pub fn set_output(name: &str, high: bool) -> bool {
+    let Some((_, (port, pin))) = OUTPUTS.iter().find(|(output, _)| *output == name) else {
+        return false;
+    };
+    port.bsrr().write(|w| {
+        w.set_bs(*pin, high);
+        w.set_br(*pin, !high);
+    });
+    true
+}
+
+/// Keep the latest raw conversions of the NTC task.
+pub fn record_adc(ntc: u16, vrefint: u16) {
+    NTC_RAW.store(ntc, Ordering::Relaxed);
+    VREFINT_RAW.store(vrefint, Ordering::Relaxed);
+}
+
+/// Latest raw NTC and internal reference conversions.
+pub fn adc() -> (u16, u16) {
+    (
+        NTC_RAW.load(Ordering::Relaxed),
+        VREFINT_RAW.load(Ordering::Relaxed),
+    )
+}
+
+/// Raw counts written at the telemetry interval.
+pub fn streaming() -> bool {
+    STREAM.load(Ordering::Relaxed)
+}
+
+pub fn set_streaming(on: bool) {
+    STREAM.store(on, Ordering::Relaxed);
+}
+
+/// Read the stored calibration at boot.
+pub fn load() {
+    if let Some(value) = CALIBRATION.last() {
+        // The offset is stored in the lower half word, never all ones in total
+        NTC_OFFSET.store(value as u16 as i16, Ordering::Relaxed);
+        info!("NTC calibration offset: {} K", ntc_offset());
+    }
+}
+
+/// Correction added to the NTC temperature in K.
+pub fn ntc_offset() -> f32 {
+    f32::from(NTC_OFFSET.load(Ordering::Relaxed)) / 10.0
+}
+
+/// Store a new NTC offset in K, `false` if out of range.
+pub fn set_ntc_offset(offset: f32) -> bool {
+    if !(-NTC_OFFSET_MAX..=NTC_OFFSET_MAX).contains(&offset) {
+        return false;
+    }
+    let tenths = (offset * 10.0).round() as i16;
+    CALIBRATION.append(u32::from(tenths as u16));
+    NTC_OFFSET.store(tenths, Ordering::Relaxed);
+    info!("NTC calibration offset set to {} K", offset);
+    true
+}
@@ -0,0 +1,94 @@
+//! PWM fan on PB0 (TIM3 CH3, 25 kHz) following a temperature curve, with an
+//! optional open-collector tach signal on PB1.
+//!
+//! The speed is interpolated between the points of [`CURVE`] from the
+//! regulation temperature, so it works for fan coils as well as for cooling
+//! an enclosure. Without a valid reading the fan runs at full speed.
+
+use embassy_executor::task;
+#[cfg(feature = "fan-tach")]
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::peripherals::TIM3;
+use embassy_stm32::timer::Channel;
+use embassy_stm32::timer::simple_pwm::SimplePwm;
+use embassy_time::{Duration, Timer};
+#[cfg(feature = "fan-tach")]
+use embassy_time::{Instant, with_deadline};
+
+#[cfg(feature = "fan-tach")]
+use crate::fmt::warn;
+use crate::fmt::{info, trace};
+use crate::state;
+
+pub const PWM_HZ: u32 = 25_000;
+/// Temperature and duty cycle in %, by rising temperature
+const CURVE: [(f32, u8); 3] = [(25.0, 0), (30.0, 30), (40.0, 100)];
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+#[cfg(feature = "fan-tach")]
+const PULSES_PER_REVOLUTION: u32 = 2;
+
+/// Duty cycle in % for `temperature`.
+fn duty(temperature: f32) -> u8 {
+    if temperature.is_nan() {
+        return 100;
+    }
+
+    let mut previous = CURVE[0];
+    if temperature <= previous.0 {
+        return previous.1;
+    }
+    for point in &CURVE[1..] {
+        if temperature < point.0 {
+            let fraction = (temperature - previous.0) / (point.0 - previous.0);
+            let duty =
+                f32::from(previous.1) + fraction * (f32::from(point.1) - f32::from(previous.1));
+            return duty as u8;
+        }
+        previous = *point;
+    }
+    previous.1
+}
+
+#[task]
+pub async fn fan(mut pwm: SimplePwm<'static, TIM3>) {
+    info!("Starting fan control");
+    pwm.channel(Channel::Ch3).enable();
+
+    loop {
+        let duty = duty(state::get().temperature);
+        pwm.channel(Channel::Ch3).set_duty_cycle_percent(duty);
+        trace!("Fan: {}%", duty);
+        state::update(|s| s.fan_duty = duty);
+        Timer::after(UPDATE_INTERVAL).await;
+    }
+}
+
+/// Measure the fan speed, counting the tach pulses over each interval.
+#[cfg(feature = "fan-tach")]
+#[task]
+pub async fn tach(mut tach: ExtiInput<'static>) {
+    let per_minute = (Duration::from_secs(60).as_ticks() / UPDATE_INTERVAL.as_ticks()) as u32;
+    let mut stalled = false;
+
+    loop {
+        // The duty cycle set before the interval, giving the fan time to
+        // spin up
+        let duty = state::get().fan_duty;
+        let end = Instant::now() + UPDATE_INTERVAL;
+        let mut pulses = 0u32;
+        while with_deadline(end, tach.wait_for_falling_edge())
+            .await
+            .is_ok()
+        {
+            pulses += 1;
+        }
+
+        let rpm = (pulses * per_minute / PULSES_PER_REVOLUTION) as u16;
+        let stall = duty > 0 && rpm == 0;
+        if stall && !stalled {
+            warn!("Fan: no tach signal at {}%", duty);
+        }
+        stalled = stall;
+        state::update(|s| s.fan_rpm = rpm);
+    }
+}
@@ -0,0 +1,347 @@
+//! Raw flash programming through the FLASH registers.
+//!
+//! Usable before the HAL is initialized and, as every helper is inlined and
+//! accesses memory with inline assembly only, from routines running in RAM
+//! while the application flash is erased.
+//!
+//! The registers and the programming unit differ between the families, the
+//! F0 and F1 program half-words and the G0 double-words. A [`Log`] keeps one
+//! value per programming unit either way. The L0 flash erases to zeros
+//! instead of ones, the features using it are refused there.
+
+use core::arch::asm;
+#[cfg(any(feature = "auth", feature = "energy", feature = "factory"))]
+use core::ptr::read_volatile;
+
+pub use crate::chip::PAGE_SIZE;
+
+pub const FLASH_BASE: u32 = 0x0800_0000;
+const FLASH_KEY1: u32 = 0x4567_0123;
+const FLASH_KEY2: u32 = 0xCDEF_89AB;
+/// Last two flash pages, keeping the auth counters
+#[cfg(feature = "auth")]
+pub const DATA_PAGES: [u32; 2] = log_pages(0);
+/// Pages below the data pages keeping the heat energy total
+#[cfg(feature = "energy")]
+pub const ENERGY_PAGES: [u32; 2] = log_pages(cfg!(feature = "auth") as u32);
+/// Pages below the energy pages keeping the factory calibration
+#[cfg(feature = "factory")]
+pub const CALIBRATION_PAGES: [u32; 2] =
+    log_pages(cfg!(feature = "auth") as u32 + cfg!(feature = "energy") as u32);
+
+/// Page pair of the `index`-th log from the top of the flash, only the logs
+/// of enabled features count. build.rs keeps them out of the application.
+#[cfg(any(feature = "auth", feature = "energy", feature = "factory"))]
+const fn log_pages(index: u32) -> [u32; 2] {
+    let first = FLASH_BASE + crate::chip::FLASH_SIZE - (index + 1) * 2 * PAGE_SIZE;
+    [first, first + PAGE_SIZE]
+}
+
+#[cfg(feature = "iap")]
+#[inline(always)]
+pub unsafe fn read16(address: u32) -> u16 {
+    let value: u32;
+    unsafe {
+        asm!(
+            "ldrh {v}, [{a}]",
+            a = in(reg) address,
+            v = out(reg) value,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+    value as u16
+}
+
+#[inline(always)]
+unsafe fn read32(address: u32) -> u32 {
+    let value: u32;
+    unsafe {
+        asm!(
+            "ldr {v}, [{a}]",
+            a = in(reg) address,
+            v = out(reg) value,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+    value
+}
+
+#[inline(always)]
+pub unsafe fn write32(address: u32, value: u32) {
+    unsafe {
+        asm!(
+            "str {v}, [{a}]",
+            a = in(reg) address,
+            v = in(reg) value,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+#[inline(always)]
+pub unsafe fn unlock() {
+    unsafe {
+        write32(FLASH_KEYR, FLASH_KEY1);
+        write32(FLASH_KEYR, FLASH_KEY2);
+    }
+}
+
+#[inline(always)]
+pub unsafe fn lock() {
+    unsafe { write32(FLASH_CR, CR_LOCK) }
+}
+
+#[inline(always)]
+unsafe fn wait_ready() {
+    unsafe { while read32(FLASH_SR) & SR_BSY != 0 {} }
+}
+
+#[cfg(any(family = "f0", family = "f1"))]
+#[inline(always)]
+pub unsafe fn write16(address: u32, value: u16) {
+    unsafe {
+        asm!(
+            "strh {v}, [{a}]",
+            a = in(reg) address,
+            v = in(reg) value as u32,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Programming and erasing of the STM32F1, by half-word. The F0 has the same
+/// flash interface.
+#[cfg(any(family = "f0", family = "f1"))]
+mod family {
+    use super::*;
+
+    pub(super) const FLASH_KEYR: u32 = 0x4002_2004;
+    pub(super) const FLASH_SR: u32 = 0x4002_200C;
+    pub(super) const FLASH_CR: u32 = 0x4002_2010;
+    const FLASH_AR: u32 = 0x4002_2014;
+    pub(super) const SR_BSY: u32 = 1 << 0;
+    const CR_PG: u32 = 1 << 0;
+    const CR_PER: u32 = 1 << 1;
+    const CR_STRT: u32 = 1 << 6;
+    pub(super) const CR_LOCK: u32 = 1 << 7;
+
+    /// Smallest unit programmed at once, in bytes
+    #[cfg(any(feature = "auth", feature = "energy", feature = "factory"))]
+    pub const PROGRAM_SIZE: u32 = 4;
+
+    #[inline(always)]
+    pub unsafe fn erase_page(address: u32) {
+        unsafe {
+            write32(FLASH_CR, CR_PER);
+            write32(FLASH_AR, address);
+            write32(FLASH_CR, CR_PER | CR_STRT);
+            wait_ready();
+        }
+    }
+
+    /// Program a half-word of erased flash.
+    #[inline(always)]
+    pub unsafe fn program(address: u32, value: u16) {
+        unsafe {
+            write32(FLASH_CR, CR_PG);
+            write16(address, value);
+            wait_ready();
+        }
+    }
+
+    /// Program a word of erased flash.
+    #[inline(always)]
+    pub unsafe fn program_word(address: u32, value: u32) {
+        unsafe {
+            program(address, value as u16);
+            program(address + 2, (value >> 16) as u16);
+        }
+    }
+
+    /// Program `PAGE_SIZE` bytes from `source` to the erased page at `destination`.
+    #[cfg(feature = "iap")]
+    #[inline(always)]
+    pub unsafe fn program_page(destination: u32, source: u32) {
+        unsafe {
+            let mut offset = 0;
+            while offset < PAGE_SIZE {
+                program(destination + offset, read16(source + offset));
+                offset += 2;
+            }
+        }
+    }
+}
+
+/// Programming and erasing of the STM32G0, by double-word with ECC.
+#[cfg(family = "g0")]
+mod family {
+    use super::*;
+
+    pub(super) const FLASH_KEYR: u32 = 0x4002_2008;
+    pub(super) const FLASH_SR: u32 = 0x4002_2010;
+    pub(super) const FLASH_CR: u32 = 0x4002_2014;
+    /// BSY1
+    pub(super) const SR_BSY: u32 = 1 << 16;
+    /// Error flags, cleared by writing them back
+    const SR_ERRORS: u32 = 0x0000_C3FA;
+    const CR_PG: u32 = 1 << 0;
+    const CR_PER: u32 = 1 << 1;
+    const CR_PNB_SHIFT: u32 = 3;
+    const CR_STRT: u32 = 1 << 16;
+    pub(super) const CR_LOCK: u32 = 1 << 31;
+
+    /// Smallest unit programmed at once, in bytes
+    #[cfg(any(feature = "auth", feature = "energy", feature = "factory"))]
+    pub const PROGRAM_SIZE: u32 = 8;
+
+    #[inline(always)]
+    unsafe fn clear_errors() {
+        unsafe { write32(FLASH_SR, SR_ERRORS) }
+    }
+
+    #[inline(always)]
+    pub unsafe fn erase_page(address: u32) {
+        let page = (address - FLASH_BASE) / PAGE_SIZE;
+        unsafe {
+            clear_errors();
+            write32(FLASH_CR, CR_PER | page << CR_PNB_SHIFT);
+            write32(FLASH_CR, CR_PER | page << CR_PNB_SHIFT | CR_STRT);
+            wait_ready();
+        }
+    }
+
+    /// Program a word of erased flash, as the lower half of the double-word
+    /// at `address`. The upper half stays erased but can not be programmed
+    /// any more, the ECC covers the double-word.
+    #[inline(always)]
+    pub unsafe fn program_word(address: u32, value: u32) {
+        unsafe {
+            clear_errors();
+            write32(FLASH_CR, CR_PG);
+            write32(address, value);
+            write32(address + 4, u32::MAX);
+            wait_ready();
+            write32(FLASH_CR, 0);
+        }
+    }
+}
+
+pub use family::*;
+use family::{CR_LOCK, FLASH_CR, FLASH_KEYR, FLASH_SR, SR_BSY};
+
+/// Values appended word by word to two alternating flash pages, the last one
+/// is current.
+///
+/// A page starts with its generation, followed by the values. It is only
+/// given up once it is full, spreading the wear over all of its words: the
+/// next value is written to the other page together with the next
+/// generation, and only then the full page is erased. A power loss at any
+/// point leaves the last value in one of the pages, so a counter never goes
+/// back.
+#[cfg(any(feature = "auth", feature = "energy", feature = "factory"))]
+pub struct Log {
+    pages: [u32; 2],
+}
+
+#[cfg(any(feature = "auth", feature = "energy", feature = "factory"))]
+impl Log {
+    const ENTRIES: u32 = PAGE_SIZE / PROGRAM_SIZE;
+    pub const ERASED: u32 = u32::MAX;
+
+    pub const fn new(pages: [u32; 2]) -> Self {
+        Self { pages }
+    }
+
+    fn word(page: u32, slot: u32) -> u32 {
+        // SAFETY: the pages are plain memory mapped flash
+        unsafe { read_volatile((page + slot * PROGRAM_SIZE) as *const u32) }
+    }
+
+    /// Generation following `generation`, never [`Self::ERASED`].
+    fn next(generation: u32) -> u32 {
+        generation.wrapping_add(1) % Self::ERASED
+    }
+
+    /// Index of the page holding the current value, and its generation.
+    fn current(&self) -> Option<(usize, u32)> {
+        let generations = self
+            .pages
+            .map(|page| Some(Self::word(page, 0)).filter(|g| *g != Self::ERASED));
+        match generations {
+            // Both only after a power loss before the full page was erased
+            [Some(first), Some(second)] if second == Self::next(first) => Some((1, second)),
+            [Some(first), _] => Some((0, first)),
+            [None, Some(second)] => Some((1, second)),
+            [None, None] => None,
+        }
+    }
+
+    /// Last stored value in `page`, if any, and the slot for the next one.
+    fn find(page: u32) -> (Option<u32>, u32) {
+        let mut last = None;
+        let mut slot = 1;
+        while slot < Self::ENTRIES {
+            let value = Self::word(page, slot);
+            if value == Self::ERASED {
+                break;
+            }
+            last = Some(value);
+            slot += 1;
+        }
+        (last, slot)
+    }
+
+    fn blank(page: u32) -> bool {
+        (0..Self::ENTRIES).all(|slot| Self::word(page, slot) == Self::ERASED)
+    }
+
+    pub fn last(&self) -> Option<u32> {
+        self.current()
+            .and_then(|(index, _)| Self::find(self.pages[index]).0)
+    }
+
+    /// Append `value`, which must not be [`Self::ERASED`].
+    pub fn append(&self, value: u32) {
+        let current = self.current();
+        if let Some((index, _)) = current {
+            let page = self.pages[index];
+            let (_, slot) = Self::find(page);
+            if slot < Self::ENTRIES {
+                cortex_m::interrupt::free(|_| {
+                    // SAFETY: flash operations never yield, so no other one
+                    // can be in progress, and the slot is erased
+                    unsafe {
+                        unlock();
+                        program_word(page + slot * PROGRAM_SIZE, value);
+                        lock();
+                    }
+                });
+                return;
+            }
+        }
+
+        let (index, generation) = match current {
+            Some((index, generation)) => (1 - index, Self::next(generation)),
+            None => (0, 0),
+        };
+        let page = self.pages[index];
+        let blank = Self::blank(page);
+        cortex_m::interrupt::free(|_| {
+            // SAFETY: as above, the other page holds no generation yet and is
+            // erased first unless blank, the value goes in before the
+            // generation makes the page current
+            unsafe {
+                unlock();
+                if !blank {
+                    erase_page(page);
+                }
+                program_word(page + PROGRAM_SIZE, value);
+                program_word(page, generation);
+                if let Some((full, _)) = current {
+                    erase_page(self.pages[full]);
+                }
+                lock();
+            }
+        });
+    }
+}
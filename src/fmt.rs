@@ -0,0 +1,65 @@
+//! Logging macros forwarding to defmt, or doing nothing without the `defmt`
+//! feature so a build without a debug probe carries no logging at all.
+//!
+//! The arguments are still evaluated by reference in that case, which keeps
+//! values only used for logging from being reported as unused.
+
+#![allow(unused_macros)]
+
+#[cfg(feature = "defmt")]
+#[allow(unused_imports)]
+pub use defmt::Display2Format;
+
+/// Formats with [`core::fmt::Display`], for types without defmt support
+#[cfg(not(feature = "defmt"))]
+#[allow(dead_code)]
+pub struct Display2Format<'a, T>(pub &'a T);
+
+macro_rules! trace {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::trace!($s $(, $x)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+macro_rules! debug {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::debug!($s $(, $x)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+macro_rules! info {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::info!($s $(, $x)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+// Named `warn` on export, a macro of that name clashes with the attribute
+macro_rules! warning {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::warn!($s $(, $x)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+macro_rules! error {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::error!($s $(, $x)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+#[allow(unused_imports)]
+pub(crate) use {debug, error, info, trace, warning as warn};
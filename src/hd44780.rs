@@ -0,0 +1,232 @@
+//! HD44780 character LCD in 4-bit mode, 16x2 or 20x4.
+//!
+//! The LCD is driven either through a PCF8574 I2C backpack on I2C1 (PB6 SCL,
+//! PB7 SDA) or directly from GPIOs: PA8 RS, PB9 E, PB12-PB15 D4-D7, with RW
+//! tied to ground. The status page is the same as on the graphic display,
+//! condensed to the available lines.
+
+use core::fmt::Write;
+
+#[cfg(feature = "hd44780-gpio")]
+use embassy_stm32::gpio::Output;
+#[cfg(not(feature = "hd44780-gpio"))]
+use embassy_stm32::i2c::{I2c, Master};
+#[cfg(not(feature = "hd44780-gpio"))]
+use embassy_stm32::mode::Blocking;
+use embassy_time::{Duration, block_for};
+use heapless::String;
+
+use crate::display::{self, Backend};
+use crate::indicator::Status;
+#[cfg(feature = "input")]
+use crate::menu::ITEMS;
+use crate::state;
+use crate::temperature::Celsius;
+
+/// Display size, 16x2 and 20x4 modules are supported
+pub const COLUMNS: usize = 16;
+pub const ROWS: usize = 2;
+
+const ROW_ADDRESS: [u8; 4] = [0x00, 0x40, 0x14, 0x54];
+
+// Commands
+const CLEAR: u8 = 0x01;
+const ENTRY_MODE_INCREMENT: u8 = 0x06;
+const DISPLAY_ON: u8 = 0x0C;
+const FUNCTION_4BIT_2LINE: u8 = 0x28;
+const SET_DDRAM_ADDRESS: u8 = 0x80;
+
+/// Transfer of one 4-bit half of a command or data byte.
+pub trait Bus {
+    /// Write `nibble` (low 4 bits) as data if `rs` is set, false on error.
+    fn write_nibble(&mut self, rs: bool, nibble: u8) -> bool;
+}
+
+/// PCF8574 backpack: P0 RS, P1 RW, P2 E, P3 backlight, P4-P7 D4-D7.
+#[cfg(not(feature = "hd44780-gpio"))]
+pub struct Pcf8574 {
+    i2c: I2c<'static, Blocking, Master>,
+}
+
+#[cfg(not(feature = "hd44780-gpio"))]
+impl Pcf8574 {
+    const ADDRESS: u8 = 0x27;
+    const RS: u8 = 0x01;
+    const E: u8 = 0x04;
+    const BACKLIGHT: u8 = 0x08;
+
+    pub fn new(i2c: I2c<'static, Blocking, Master>) -> Self {
+        Self { i2c }
+    }
+}
+
+#[cfg(not(feature = "hd44780-gpio"))]
+impl Bus for Pcf8574 {
+    fn write_nibble(&mut self, rs: bool, nibble: u8) -> bool {
+        let byte = nibble << 4 | Self::BACKLIGHT | if rs { Self::RS } else { 0 };
+        // Each I2C byte takes longer than the E pulse and command time
+        self.i2c
+            .blocking_write(Self::ADDRESS, &[byte | Self::E, byte])
+            .is_ok()
+    }
+}
+
+#[cfg(feature = "hd44780-gpio")]
+pub struct Gpio {
+    rs: Output<'static>,
+    e: Output<'static>,
+    data: [Output<'static>; 4],
+}
+
+#[cfg(feature = "hd44780-gpio")]
+impl Gpio {
+    pub fn new(rs: Output<'static>, e: Output<'static>, data: [Output<'static>; 4]) -> Self {
+        Self { rs, e, data }
+    }
+}
+
+#[cfg(feature = "hd44780-gpio")]
+impl Bus for Gpio {
+    fn write_nibble(&mut self, rs: bool, nibble: u8) -> bool {
+        self.rs.set_level(rs.into());
+        for (bit, pin) in self.data.iter_mut().enumerate() {
+            pin.set_level((nibble & (1 << bit) != 0).into());
+        }
+        self.e.set_high();
+        block_for(Duration::from_micros(1));
+        self.e.set_low();
+        // Longest command besides clear and home
+        block_for(Duration::from_micros(50));
+        true
+    }
+}
+
+#[cfg(not(feature = "hd44780-gpio"))]
+pub type Lcd = Hd44780<Pcf8574>;
+#[cfg(feature = "hd44780-gpio")]
+pub type Lcd = Hd44780<Gpio>;
+
+pub struct Hd44780<B: Bus> {
+    bus: B,
+    lines: [String<COLUMNS>; ROWS],
+}
+
+impl<B: Bus> Hd44780<B> {
+    pub fn new(bus: B) -> Self {
+        Self {
+            bus,
+            lines: Default::default(),
+        }
+    }
+
+    fn write(&mut self, rs: bool, byte: u8) -> bool {
+        self.bus.write_nibble(rs, byte >> 4) && self.bus.write_nibble(rs, byte & 0x0F)
+    }
+
+    fn command(&mut self, command: u8) -> bool {
+        self.write(false, command)
+    }
+
+    /// Set line `row` to `left` followed by `right` aligned to the end.
+    fn line(&mut self, row: usize, left: &str, right: &str) {
+        let line = &mut self.lines[row];
+        line.clear();
+        let _ = line.push_str(left);
+        while line.len() + right.len() < COLUMNS {
+            let _ = line.push(' ');
+        }
+        let _ = line.push_str(right);
+    }
+}
+
+impl<B: Bus> Backend for Hd44780<B> {
+    const NAME: &'static str = "HD44780";
+
+    fn init(&mut self) -> bool {
+        // Reset into 4-bit mode whatever state the controller is in
+        block_for(Duration::from_millis(50));
+        for delay in [4100, 100, 100] {
+            if !self.bus.write_nibble(false, 0x3) {
+                return false;
+            }
+            block_for(Duration::from_micros(delay));
+        }
+        let ready = self.bus.write_nibble(false, 0x2)
+            && self.command(FUNCTION_4BIT_2LINE)
+            && self.command(DISPLAY_ON)
+            && self.command(ENTRY_MODE_INCREMENT)
+            && self.command(CLEAR);
+        block_for(Duration::from_millis(2));
+        ready
+    }
+
+    fn draw_status(&mut self, status: Status) {
+        let state = state::get();
+        let mut temperature: String<12> = String::new();
+        let mut setpoint: String<16> = String::new();
+        let mut valve: String<12> = String::new();
+        let _ = write!(temperature, "{}", Celsius(state.temperature));
+        let _ = write!(setpoint, "SET {}", Celsius(state.target_setpoint()));
+        let _ = write!(valve, "{}%", state.valve_position);
+
+        let mut mode: String<8> = String::new();
+        match status.fault_code() {
+            Some(code) => {
+                let _ = write!(mode, "FAULT {}", code);
+            }
+            None => {
+                let _ = mode.push_str(display::mode());
+            }
+        }
+
+        if ROWS < 4 {
+            self.line(0, &temperature, &mode);
+            self.line(1, &setpoint, &valve);
+        } else {
+            self.line(0, "TEMP", &temperature);
+            self.line(1, &setpoint, "");
+            self.line(2, "VALVE", &valve);
+            #[cfg(feature = "manual")]
+            let overridden = if state.override_until.is_some() {
+                "OVR"
+            } else {
+                ""
+            };
+            #[cfg(not(feature = "manual"))]
+            let overridden = "";
+            self.line(ROWS - 1, &mode, overridden);
+        }
+    }
+
+    #[cfg(feature = "input")]
+    fn draw_menu(&mut self, selected: usize) {
+        // Scroll so the selected item stays visible below the title
+        let first = selected.saturating_sub(ROWS - 2);
+        self.line(0, "MENU", "");
+        for row in 1..ROWS {
+            let index = first + row - 1;
+            let mut text: String<COLUMNS> = String::new();
+            if let Some(item) = ITEMS.get(index) {
+                let marker = if index == selected { "> " } else { "  " };
+                let _ = text.push_str(marker);
+                let _ = text.push_str(item.label());
+            }
+            self.line(row, &text, "");
+        }
+    }
+
+    fn flush(&mut self) -> bool {
+        for (row, address) in ROW_ADDRESS.iter().enumerate().take(ROWS) {
+            if !self.command(SET_DDRAM_ADDRESS | address) {
+                return false;
+            }
+            for column in 0..COLUMNS {
+                let byte = self.lines[row].as_bytes().get(column).copied();
+                if !self.write(true, byte.unwrap_or(b' ')) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
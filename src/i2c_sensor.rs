@@ -0,0 +1,293 @@
+//! Digital room temperature and humidity sensor on I2C1: PB6 SCL, PB7 SDA.
+//!
+//! Either a Sensirion SHT3x (`sht3x`) or a Bosch BME280 (`bme280`), read in
+//! single shot mode every [`MEASURE_INTERVAL`]. The temperature is published
+//! as the [`TemperatureSource::I2c`] reading, the humidity to the state.
+
+use embassy_executor::task;
+use embassy_stm32::i2c::{I2c, Master};
+use embassy_stm32::mode::Blocking;
+use embassy_time::{Duration, Timer};
+
+use crate::fmt::{info, warn};
+use crate::log::log;
+use crate::temperature::{self, TemperatureSource};
+use crate::{dew_point, state};
+
+const MEASURE_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct Reading {
+    pub temperature: f32,
+    /// Relative humidity in %
+    pub humidity: f32,
+}
+
+/// Single shot measurement of a sensor on the bus.
+pub trait Sensor {
+    const NAME: &'static str;
+    /// Time from starting a measurement until the result is available
+    const CONVERSION: Duration;
+
+    /// Check the sensor is present and prepare it, false on error.
+    fn init(&mut self) -> bool;
+    /// Start a measurement, false on error.
+    fn start(&mut self) -> bool;
+    /// Result of the measurement started last.
+    fn fetch(&mut self) -> Option<Reading>;
+}
+
+#[cfg(feature = "sht3x")]
+pub struct Sht3x {
+    i2c: I2c<'static, Blocking, Master>,
+}
+
+#[cfg(feature = "sht3x")]
+impl Sht3x {
+    /// ADDR pin low
+    const ADDRESS: u8 = 0x44;
+    const SOFT_RESET: [u8; 2] = [0x30, 0xA2];
+    /// High repeatability without clock stretching
+    const MEASURE: [u8; 2] = [0x24, 0x00];
+
+    pub fn new(i2c: I2c<'static, Blocking, Master>) -> Self {
+        Self { i2c }
+    }
+
+    /// CRC-8 of a data word, polynomial 0x31 starting from 0xFF.
+    fn crc(data: &[u8]) -> u8 {
+        let mut crc = 0xFF;
+        for byte in data {
+            crc ^= byte;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 {
+                    crc << 1 ^ 0x31
+                } else {
+                    crc << 1
+                };
+            }
+        }
+        crc
+    }
+}
+
+#[cfg(feature = "sht3x")]
+impl Sensor for Sht3x {
+    const NAME: &'static str = "SHT3x";
+    const CONVERSION: Duration = Duration::from_millis(16);
+
+    fn init(&mut self) -> bool {
+        self.i2c
+            .blocking_write(Self::ADDRESS, &Self::SOFT_RESET)
+            .is_ok()
+    }
+
+    fn start(&mut self) -> bool {
+        self.i2c
+            .blocking_write(Self::ADDRESS, &Self::MEASURE)
+            .is_ok()
+    }
+
+    fn fetch(&mut self) -> Option<Reading> {
+        let mut data = [0u8; 6];
+        self.i2c.blocking_read(Self::ADDRESS, &mut data).ok()?;
+        let [t, rh] = [&data[..3], &data[3..]];
+        if Self::crc(&t[..2]) != t[2] || Self::crc(&rh[..2]) != rh[2] {
+            return None;
+        }
+
+        let t = u16::from_be_bytes([t[0], t[1]]) as f32;
+        let rh = u16::from_be_bytes([rh[0], rh[1]]) as f32;
+        Some(Reading {
+            temperature: -45.0 + 175.0 * t / 65535.0,
+            humidity: 100.0 * rh / 65535.0,
+        })
+    }
+}
+
+/// Trimming parameters read from the sensor, named as in the datasheet.
+#[cfg(feature = "bme280")]
+#[derive(Default)]
+struct Calibration {
+    t1: i32,
+    t2: i32,
+    t3: i32,
+    h1: i32,
+    h2: i32,
+    h3: i32,
+    h4: i32,
+    h5: i32,
+    h6: i32,
+}
+
+#[cfg(feature = "bme280")]
+pub struct Bme280 {
+    i2c: I2c<'static, Blocking, Master>,
+    calibration: Calibration,
+}
+
+#[cfg(feature = "bme280")]
+impl Bme280 {
+    /// SDO pin low
+    const ADDRESS: u8 = 0x76;
+    const CHIP_ID: u8 = 0x60;
+
+    // Registers
+    const REG_CALIBRATION_T: u8 = 0x88;
+    const REG_CALIBRATION_H1: u8 = 0xA1;
+    const REG_CHIP_ID: u8 = 0xD0;
+    const REG_CALIBRATION_H2: u8 = 0xE1;
+    const REG_CTRL_HUM: u8 = 0xF2;
+    const REG_CTRL_MEAS: u8 = 0xF4;
+    const REG_DATA_T: u8 = 0xFA;
+
+    /// Humidity oversampling x1
+    const CTRL_HUM: u8 = 0x01;
+    /// Temperature oversampling x1, pressure skipped, forced mode
+    const CTRL_MEAS_FORCED: u8 = 0x21;
+
+    pub fn new(i2c: I2c<'static, Blocking, Master>) -> Self {
+        Self {
+            i2c,
+            calibration: Calibration::default(),
+        }
+    }
+
+    fn read(&mut self, register: u8, data: &mut [u8]) -> bool {
+        self.i2c
+            .blocking_write_read(Self::ADDRESS, &[register], data)
+            .is_ok()
+    }
+
+    fn write(&mut self, register: u8, value: u8) -> bool {
+        self.i2c
+            .blocking_write(Self::ADDRESS, &[register, value])
+            .is_ok()
+    }
+
+    /// Temperature in 0.01 °C and the fine value for the humidity, using the
+    /// integer compensation from the datasheet.
+    fn temperature(&self, adc: i32) -> (i32, i32) {
+        let c = &self.calibration;
+        let var1 = (((adc >> 3) - (c.t1 << 1)) * c.t2) >> 11;
+        let var2 = (((((adc >> 4) - c.t1) * ((adc >> 4) - c.t1)) >> 12) * c.t3) >> 14;
+        let fine = var1 + var2;
+        ((fine * 5 + 128) >> 8, fine)
+    }
+
+    /// Relative humidity in 1/1024 %.
+    fn humidity(&self, adc: i32, fine: i32) -> i32 {
+        let c = &self.calibration;
+        let v = fine - 76800;
+        let v = ((((adc << 14) - (c.h4 << 20) - (c.h5 * v)) + 16384) >> 15)
+            * (((((((v * c.h6) >> 10) * (((v * c.h3) >> 11) + 32768)) >> 10) + 2097152) * c.h2
+                + 8192)
+                >> 14);
+        let v = v - (((((v >> 15) * (v >> 15)) >> 7) * c.h1) >> 4);
+        v.clamp(0, 419_430_400) >> 12
+    }
+}
+
+#[cfg(feature = "bme280")]
+impl Sensor for Bme280 {
+    const NAME: &'static str = "BME280";
+    const CONVERSION: Duration = Duration::from_millis(10);
+
+    fn init(&mut self) -> bool {
+        let mut id = [0u8];
+        if !self.read(Self::REG_CHIP_ID, &mut id) || id[0] != Self::CHIP_ID {
+            return false;
+        }
+
+        let mut t = [0u8; 6];
+        let mut h1 = [0u8];
+        let mut h = [0u8; 7];
+        if !(self.read(Self::REG_CALIBRATION_T, &mut t)
+            && self.read(Self::REG_CALIBRATION_H1, &mut h1)
+            && self.read(Self::REG_CALIBRATION_H2, &mut h))
+        {
+            return false;
+        }
+
+        self.calibration = Calibration {
+            t1: u16::from_le_bytes([t[0], t[1]]).into(),
+            t2: i16::from_le_bytes([t[2], t[3]]).into(),
+            t3: i16::from_le_bytes([t[4], t[5]]).into(),
+            h1: h1[0].into(),
+            h2: i16::from_le_bytes([h[0], h[1]]).into(),
+            h3: h[2].into(),
+            // 12-bit values sharing the nibbles of 0xE5
+            h4: i32::from(h[3] as i8) << 4 | i32::from(h[4] & 0x0F),
+            h5: i32::from(h[5] as i8) << 4 | i32::from(h[4] >> 4),
+            h6: (h[6] as i8).into(),
+        };
+        self.write(Self::REG_CTRL_HUM, Self::CTRL_HUM)
+    }
+
+    fn start(&mut self) -> bool {
+        // The humidity setting only takes effect with a write to ctrl_meas
+        self.write(Self::REG_CTRL_MEAS, Self::CTRL_MEAS_FORCED)
+    }
+
+    fn fetch(&mut self) -> Option<Reading> {
+        let mut data = [0u8; 5];
+        if !self.read(Self::REG_DATA_T, &mut data) {
+            return None;
+        }
+
+        let adc_t = i32::from(data[0]) << 12 | i32::from(data[1]) << 4 | i32::from(data[2]) >> 4;
+        let adc_h = i32::from(data[3]) << 8 | i32::from(data[4]);
+        // Reset value while no measurement was done
+        if adc_t == 0x80000 {
+            return None;
+        }
+
+        let (temperature, fine) = self.temperature(adc_t);
+        Some(Reading {
+            temperature: temperature as f32 / 100.0,
+            humidity: self.humidity(adc_h, fine) as f32 / 1024.0,
+        })
+    }
+}
+
+#[cfg(feature = "sht3x")]
+pub type Device = Sht3x;
+#[cfg(feature = "bme280")]
+pub type Device = Bme280;
+
+#[task]
+pub async fn i2c_sensor(mut sensor: Device) {
+    while !sensor.init() {
+        warn!("{}: sensor not found", Device::NAME);
+        Timer::after(MEASURE_INTERVAL).await;
+    }
+
+    info!("Starting {} room sensor", Device::NAME);
+    loop {
+        let reading = if sensor.start() {
+            Timer::after(Device::CONVERSION).await;
+            sensor.fetch()
+        } else {
+            None
+        };
+        match reading {
+            Some(reading) => {
+                log!(
+                    Sensors,
+                    trace,
+                    "{}: {} C, {} %",
+                    Device::NAME,
+                    reading.temperature,
+                    reading.humidity
+                );
+                temperature::publish(TemperatureSource::I2c, reading.temperature);
+                state::update(|s| {
+                    s.humidity = reading.humidity;
+                    s.dew_point = dew_point::dew_point(reading.temperature, reading.humidity);
+                });
+            }
+            None => warn!("{}: read failed", Device::NAME),
+        }
+
+        Timer::after(MEASURE_INTERVAL).await;
+    }
+}
@@ -0,0 +1,506 @@
+//! In-application firmware update over UART.
+//!
+//! The flash is split into two slots of the same size, a page holding the
+//! update record and a scratch page for the swap:
+//!
+//! | offset | size  | content                               |
+//! |--------|-------|---------------------------------------|
+//! | 0      | 31 KB | running application                   |
+//! | 31 KB  | 31 KB | staged update or the previous image   |
+//! | 62 KB  | 1 KB  | update record and swap progress       |
+//! | 63 KB  | 1 KB  | scratch page                          |
+//!
+//! The host requests an update with a `link` frame carrying
+//! `REQUEST_UPDATE, length (u32 LE), CRC-32 (u32 LE)`, waits for the
+//! `RESPONSE_READY` frame and sends the image with XMODEM-CRC (128 or 1024
+//! byte blocks). The outcome is reported with a `RESPONSE_DONE` frame, see
+//! `tools/iap_update.py`. Once the CRC-32 of the staged image matches, the
+//! record is written and the MCU restarts. At boot
+//! [`check`] verifies the staged image again and swaps both slots from a
+//! routine running in RAM, keeping the previous image in the staging slot.
+//! Each page goes through the scratch page and every step is marked in the
+//! record before the next erase, so a swap cut short by a power loss is
+//! resumed on the next boot.
+//!
+//! The new image then runs on trial under the watchdog and has to confirm
+//! itself with [`trial`]. A restart before that, by the watchdog or
+//! otherwise, swaps the previous image back.
+
+use core::arch::asm;
+use core::ptr::read_volatile;
+
+use embassy_executor::task;
+use embassy_stm32::Peri;
+use embassy_stm32::flash::{Blocking, Flash};
+use embassy_stm32::peripherals::{FLASH, IWDG, USART1};
+use embassy_stm32::usart::{BufferedUart, Config};
+use embassy_stm32::wdg::IndependentWatchdog;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use embedded_io_async::{Read, Write};
+
+use crate::board::{Usart1RxPin, Usart1TxPin};
+use crate::flash::{
+    FLASH_BASE, PAGE_SIZE, erase_page, lock, program, program_page, program_word, read16, unlock,
+    write32,
+};
+use crate::fmt::{info, warn};
+use crate::link::{self, Decoder};
+use crate::{Irqs, bootloader};
+
+const BAUDRATE: u32 = 115_200;
+
+// Update requests and responses
+const REQUEST_UPDATE: u8 = 0x01;
+const RESPONSE_READY: u8 = 0x81;
+const RESPONSE_DONE: u8 = 0x82;
+const RESULT_OK: u8 = 0;
+const RESULT_INVALID: u8 = 1;
+const RESULT_ERASE_FAILED: u8 = 2;
+const RESULT_TRANSFER_FAILED: u8 = 3;
+const RESULT_INCOMPLETE: u8 = 4;
+const RESULT_CRC_MISMATCH: u8 = 5;
+const RESULT_WRITE_FAILED: u8 = 6;
+
+// Flash layout, the application size must match build.rs
+const APP_SIZE: u32 = 31 * PAGE_SIZE;
+const STAGING_OFFSET: u32 = APP_SIZE;
+const RECORD_OFFSET: u32 = 2 * APP_SIZE;
+const SCRATCH_OFFSET: u32 = RECORD_OFFSET + PAGE_SIZE;
+const UPDATE_MAGIC: u32 = u32::from_le_bytes(*b"UPDT");
+const TRIAL_MAGIC: u32 = u32::from_le_bytes(*b"TRYB");
+const BOOTED_OFFSET: u32 = 12;
+/// Swap progress, three half-word marks per page
+const PROGRESS_OFFSET: u32 = 16;
+
+// Rollback supervision
+const TRIAL_PERIOD: Duration = Duration::from_secs(60);
+const WATCHDOG_TIMEOUT_US: u32 = 10_000_000;
+
+// XMODEM
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_MODE: u8 = b'C';
+const MAX_BLOCK: usize = 1024;
+const MAX_RETRIES: u8 = 10;
+const START_TIMEOUT: Duration = Duration::from_secs(3);
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Independent watchdog, started before the HAL for a trial
+const IWDG_KR: u32 = 0x4000_3000;
+const IWDG_PR: u32 = 0x4000_3004;
+const IWDG_RLR: u32 = 0x4000_3008;
+const KEY_START: u32 = 0xCCCC;
+const KEY_ACCESS: u32 = 0x5555;
+/// LSI divided by 256, one tick per 8 ms as the HAL assumes
+const PR_DIV256: u32 = 6;
+const TICK_US: u32 = 8_000;
+
+// System reset from the RAM routine
+const SCB_AIRCR: u32 = 0xE000_ED0C;
+const AIRCR_SYSRESETREQ: u32 = 0x05FA_0004;
+
+type Uart<'d> = BufferedUart<'d>;
+
+/// CRC-32 (IEEE, as computed by zlib) of the first `len` bytes of the staging area.
+fn staged_crc(len: u32) -> u32 {
+    // SAFETY: the staging area is plain memory mapped flash
+    let image = unsafe {
+        core::slice::from_raw_parts((FLASH_BASE + STAGING_OFFSET) as *const u8, len as usize)
+    };
+
+    let mut crc = !0u32;
+    for byte in image {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Pending update record as `(magic, length, crc)`.
+fn read_record() -> (u32, u32, u32) {
+    let record = (FLASH_BASE + RECORD_OFFSET) as *const u32;
+    // SAFETY: the record page is plain memory mapped flash
+    unsafe {
+        (
+            read_volatile(record),
+            read_volatile(record.add(1)),
+            read_volatile(record.add(2)),
+        )
+    }
+}
+
+/// Whether the trial boot of a new image was already started.
+fn trial_booted() -> bool {
+    let booted = (FLASH_BASE + RECORD_OFFSET + BOOTED_OFFSET) as *const u16;
+    // SAFETY: the record page is plain memory mapped flash
+    unsafe { read_volatile(booted) != u16::MAX }
+}
+
+/// Whether a swap was cut short and has to be finished.
+fn swap_started() -> bool {
+    let progress = (FLASH_BASE + RECORD_OFFSET + PROGRESS_OFFSET) as *const u16;
+    // SAFETY: the record page is plain memory mapped flash
+    unsafe { read_volatile(progress) != u16::MAX }
+}
+
+/// Running a newly installed image that has not confirmed itself yet.
+pub fn in_trial() -> bool {
+    read_record().0 == TRIAL_MAGIC
+}
+
+/// Start the watchdog with the same timeout [`trial`] configures later, so
+/// an image hanging before its tasks run is reset and rolled back as well.
+fn start_watchdog() {
+    // SAFETY: plain writes of the IWDG registers, nothing else uses it yet
+    unsafe {
+        write32(IWDG_KR, KEY_START);
+        write32(IWDG_KR, KEY_ACCESS);
+        write32(IWDG_PR, PR_DIV256);
+        write32(IWDG_RLR, WATCHDOG_TIMEOUT_US / TICK_US);
+    }
+}
+
+/// Install a staged image or roll back one that failed its trial. Never
+/// returns in those cases.
+///
+/// Called first thing at boot, before the HAL is initialized.
+pub fn check() {
+    let (magic, len, crc) = read_record();
+    if magic == TRIAL_MAGIC {
+        start_watchdog();
+    }
+    match magic {
+        // Half swapped slots no longer match the CRC
+        UPDATE_MAGIC
+            if swap_started() || (len > 0 && len <= APP_SIZE && staged_crc(len) == crc) =>
+        {
+            cortex_m::interrupt::disable();
+            // SAFETY: interrupts are off and nothing else touches the flash yet
+            unsafe { swap(TRIAL_MAGIC) }
+        }
+        TRIAL_MAGIC if !trial_booted() => {
+            // SAFETY: the executor is not running yet, the word is still erased
+            unsafe {
+                unlock();
+                program(FLASH_BASE + RECORD_OFFSET + BOOTED_OFFSET, 0);
+                lock();
+            }
+        }
+        TRIAL_MAGIC => {
+            cortex_m::interrupt::disable();
+            // SAFETY: interrupts are off and nothing else touches the flash yet
+            unsafe { swap(u32::MAX) }
+        }
+        // A stale record is cleared by the updater task
+        _ => {}
+    }
+}
+
+/// Exchange the application and staging slots, replace the update record with
+/// `magic` (left erased for `u32::MAX`) and reset.
+///
+/// A page is copied to the scratch page, replaced by its staged one, and the
+/// staged one replaced from the scratch page. Each step is marked done in
+/// the record, steps already marked are skipped when resuming a swap cut
+/// short. Only a power loss while the record itself is rewritten at the end
+/// leaves it erased, an installed image then runs without the trial.
+///
+/// Runs from RAM as the application it is executing from gets erased, so it
+/// must not call anything outside itself, including `memcpy`. Flash accesses
+/// are done with inline assembly for the same reason.
+#[unsafe(link_section = ".data.iap_swap")]
+#[inline(never)]
+unsafe fn swap(magic: u32) -> ! {
+    let scratch = FLASH_BASE + SCRATCH_OFFSET;
+
+    unsafe {
+        unlock();
+
+        let mut offset = 0;
+        while offset < APP_SIZE {
+            let application = FLASH_BASE + offset;
+            let staging = application + STAGING_OFFSET;
+            let marks = FLASH_BASE + RECORD_OFFSET + PROGRESS_OFFSET + offset / PAGE_SIZE * 6;
+
+            if read16(marks) == u16::MAX {
+                erase_page(scratch);
+                program_page(scratch, application);
+                program(marks, 0);
+            }
+            if read16(marks + 2) == u16::MAX {
+                erase_page(application);
+                program_page(application, staging);
+                program(marks + 2, 0);
+            }
+            if read16(marks + 4) == u16::MAX {
+                erase_page(staging);
+                program_page(staging, scratch);
+                program(marks + 4, 0);
+            }
+            offset += PAGE_SIZE;
+        }
+
+        erase_page(FLASH_BASE + RECORD_OFFSET);
+        if magic != u32::MAX {
+            program_word(FLASH_BASE + RECORD_OFFSET, magic);
+        }
+        lock();
+
+        write32(SCB_AIRCR, AIRCR_SYSRESETREQ);
+        loop {
+            asm!("wfi", options(nomem, nostack, preserves_flags));
+        }
+    }
+}
+
+async fn read_byte(uart: &mut Uart<'_>, timeout: Duration) -> Option<u8> {
+    let mut byte = [0u8];
+    match with_timeout(timeout, uart.read_exact(&mut byte)).await {
+        Ok(Ok(())) => Some(byte[0]),
+        _ => None,
+    }
+}
+
+/// Parse an update request into the image length and CRC-32.
+fn parse_request(request: &[u8]) -> Option<(u32, u32)> {
+    let [REQUEST_UPDATE, l0, l1, l2, l3, c0, c1, c2, c3] = *request else {
+        return None;
+    };
+    let len = u32::from_le_bytes([l0, l1, l2, l3]);
+    let crc = u32::from_le_bytes([c0, c1, c2, c3]);
+    (len > 0 && len <= APP_SIZE && len.is_multiple_of(2)).then_some((len, crc))
+}
+
+/// Receive an XMODEM-CRC transfer into the staging area, returning the number
+/// of bytes written.
+async fn receive(
+    uart: &mut Uart<'_>,
+    flash: &mut Flash<'_, Blocking>,
+) -> Result<u32, &'static str> {
+    let mut block = [0u8; MAX_BLOCK + 4];
+    let mut expected: u8 = 1;
+    let mut offset: u32 = 0;
+    let mut retries: u8 = 0;
+
+    loop {
+        let timeout = if offset == 0 && expected == 1 {
+            // Keep asking for CRC mode until the sender starts
+            let _ = uart.write_all(&[CRC_MODE]).await;
+            START_TIMEOUT
+        } else {
+            BLOCK_TIMEOUT
+        };
+
+        let size = match read_byte(uart, timeout).await {
+            Some(SOH) => 128,
+            Some(STX) => MAX_BLOCK,
+            Some(EOT) => {
+                let _ = uart.write_all(&[ACK]).await;
+                return Ok(offset);
+            }
+            Some(CAN) => return Err("cancelled by sender"),
+            Some(_) => continue,
+            None => {
+                retries += 1;
+                if retries > MAX_RETRIES {
+                    let _ = uart.write_all(&[CAN, CAN]).await;
+                    return Err("timeout");
+                }
+                if offset > 0 {
+                    let _ = uart.write_all(&[NAK]).await;
+                }
+                continue;
+            }
+        };
+
+        // Block number, its complement, data and CRC
+        let frame = &mut block[..size + 4];
+        let mut complete = true;
+        for byte in frame.iter_mut() {
+            match read_byte(uart, BLOCK_TIMEOUT).await {
+                Some(value) => *byte = value,
+                None => {
+                    complete = false;
+                    break;
+                }
+            }
+        }
+
+        let crc = u16::from_be_bytes([frame[size + 2], frame[size + 3]]);
+        if !complete || frame[0] != !frame[1] || link::crc16(&frame[2..size + 2]) != crc {
+            retries += 1;
+            if retries > MAX_RETRIES {
+                let _ = uart.write_all(&[CAN, CAN]).await;
+                return Err("too many errors");
+            }
+            let _ = uart.write_all(&[NAK]).await;
+            continue;
+        }
+
+        // The sender missed our ACK and repeated the previous block
+        if frame[0] == expected.wrapping_sub(1) && offset > 0 {
+            let _ = uart.write_all(&[ACK]).await;
+            continue;
+        }
+
+        if frame[0] != expected {
+            let _ = uart.write_all(&[CAN, CAN]).await;
+            return Err("block out of sequence");
+        }
+
+        if offset + size as u32 > APP_SIZE {
+            let _ = uart.write_all(&[CAN, CAN]).await;
+            return Err("image too large");
+        }
+
+        if flash
+            .blocking_write(STAGING_OFFSET + offset, &frame[2..size + 2])
+            .is_err()
+        {
+            let _ = uart.write_all(&[CAN, CAN]).await;
+            return Err("flash write failed");
+        }
+
+        offset += size as u32;
+        expected = expected.wrapping_add(1);
+        retries = 0;
+        let _ = uart.write_all(&[ACK]).await;
+    }
+}
+
+async fn update(
+    uart: &mut Uart<'_>,
+    flash: &mut Flash<'_, Blocking>,
+    len: u32,
+    crc: u32,
+) -> Result<(), u8> {
+    flash
+        .blocking_erase(STAGING_OFFSET, STAGING_OFFSET + APP_SIZE)
+        .map_err(|_| RESULT_ERASE_FAILED)?;
+
+    let _ = link::write_frame(uart, &[RESPONSE_READY, RESULT_OK]).await;
+    let received = receive(uart, flash).await.map_err(|error| {
+        warn!("IAP: transfer failed: {}", error);
+        RESULT_TRANSFER_FAILED
+    })?;
+
+    if received < len {
+        return Err(RESULT_INCOMPLETE);
+    }
+    if staged_crc(len) != crc {
+        return Err(RESULT_CRC_MISMATCH);
+    }
+
+    let mut record = [0u8; 12];
+    record[0..4].copy_from_slice(&UPDATE_MAGIC.to_le_bytes());
+    record[4..8].copy_from_slice(&len.to_le_bytes());
+    record[8..12].copy_from_slice(&crc.to_le_bytes());
+    flash
+        .blocking_erase(RECORD_OFFSET, RECORD_OFFSET + PAGE_SIZE)
+        .and_then(|_| flash.blocking_write(RECORD_OFFSET, &record))
+        .map_err(|_| RESULT_WRITE_FAILED)
+}
+
+#[task]
+pub async fn iap(
+    usart: Peri<'static, USART1>,
+    tx_pin: Peri<'static, Usart1TxPin>,
+    rx_pin: Peri<'static, Usart1RxPin>,
+    flash: Peri<'static, FLASH>,
+) {
+    let mut flash = Flash::new_blocking(flash);
+
+    // Still set after boot means the staged image failed verification
+    if read_record().0 == UPDATE_MAGIC {
+        warn!("IAP: discarding invalid update record");
+        let _ = flash.blocking_erase(RECORD_OFFSET, RECORD_OFFSET + PAGE_SIZE);
+    }
+
+    let mut tx_buffer = [0u8; 32];
+    let mut rx_buffer = [0u8; MAX_BLOCK + 8];
+    let mut config = Config::default();
+    config.baudrate = BAUDRATE;
+
+    let mut uart = BufferedUart::new(
+        usart,
+        rx_pin,
+        tx_pin,
+        &mut tx_buffer,
+        &mut rx_buffer,
+        Irqs,
+        config,
+    )
+    .unwrap();
+
+    info!("Starting UART firmware updater");
+
+    let mut decoder = Decoder::<{ 9 + link::OVERHEAD }>::new();
+    loop {
+        if link::read_frame(&mut uart, &mut decoder).await.is_err() {
+            continue;
+        }
+        let Some((len, crc)) = parse_request(decoder.payload()) else {
+            let _ = link::write_frame(&mut uart, &[RESPONSE_READY, RESULT_INVALID]).await;
+            continue;
+        };
+
+        info!("IAP: receiving {} byte image", len);
+        match update(&mut uart, &mut flash, len, crc).await {
+            Ok(()) => {
+                info!("IAP: image staged, restarting to install");
+                let _ = link::write_frame(&mut uart, &[RESPONSE_DONE, RESULT_OK]).await;
+                let _ = uart.flush().await;
+                bootloader::safe_state().await;
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+            Err(result) => {
+                warn!("IAP: update failed with result {}", result);
+                // Give the sender time to finish after a cancel
+                Timer::after_secs(1).await;
+                let _ = link::write_frame(&mut uart, &[RESPONSE_DONE, result]).await;
+            }
+        }
+    }
+}
+
+/// Supervise the trial of a new image with the watchdog and confirm it once
+/// it has been running for the whole trial period.
+#[task]
+pub async fn trial(wdg: Peri<'static, IWDG>) {
+    let mut watchdog = IndependentWatchdog::new(wdg, WATCHDOG_TIMEOUT_US);
+    watchdog.unleash();
+
+    info!("IAP: new firmware on trial");
+    let deadline = Instant::now() + TRIAL_PERIOD;
+    while Instant::now() < deadline {
+        watchdog.pet();
+        Timer::after_secs(1).await;
+    }
+
+    cortex_m::interrupt::free(|_| {
+        // SAFETY: flash operations never yield, so none of the updater's can
+        // be in progress
+        unsafe {
+            unlock();
+            erase_page(FLASH_BASE + RECORD_OFFSET);
+            lock();
+        }
+    });
+    info!("IAP: new firmware confirmed");
+
+    // The watchdog cannot be stopped once started
+    loop {
+        watchdog.pet();
+        Timer::after_secs(1).await;
+    }
+}
@@ -0,0 +1,31 @@
+//! Device serial number and BLE pairing code derived from the 96-bit MCU
+//! unique ID, the pairing code keyed with `auth`.
+
+use embassy_stm32::uid;
+
+/// FNV-1a hash of the unique ID, starting from `basis`.
+fn hash(basis: u32) -> u32 {
+    uid::uid().iter().fold(basis, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Stable 32-bit serial, the FNV-1a hash of the unique ID.
+pub fn serial() -> u32 {
+    hash(0x811C_9DC5)
+}
+
+/// Six-digit BLE pairing code of the unit, printed on its label.
+///
+/// With `auth` it is an HMAC of the unique ID under the authentication key,
+/// so it takes the key or the label to know it. Without `auth` it only keeps
+/// a phone from pairing with the wrong unit by mistake: hashed from another
+/// starting value than the serial sent over LoRa and M-Bus it is not given
+/// away by the serial, but anyone reading the unique ID can compute it.
+#[cfg(feature = "ble")]
+pub fn pairing_code() -> u32 {
+    #[cfg(feature = "auth")]
+    return crate::auth::pairing_code(uid::uid());
+    #[cfg(not(feature = "auth"))]
+    return hash(0x5041_4952) % 1_000_000;
+}
@@ -0,0 +1,69 @@
+//! Integrity check of the application image.
+//!
+//! The linker appends a footer with the image length and a CRC placeholder
+//! that `tools/seal_image.py` fills in with the CRC of the binary. Unsealed
+//! images, e.g. flashed by a debug probe, skip the check.
+
+use core::ptr::{addr_of, read_volatile};
+
+use embassy_stm32::Peri;
+use embassy_stm32::gpio::{Output, Speed};
+use embassy_stm32::peripherals::CRC;
+use embassy_time::Timer;
+
+use crate::board::{self, StatusLedPin};
+use crate::chip::{self, FLASH_SIZE};
+use crate::fmt::{error, info, warn};
+
+const FLASH_BASE: u32 = 0x0800_0000;
+const UNSEALED: u32 = u32::MAX;
+
+unsafe extern "C" {
+    static __image_footer: [u32; 2];
+}
+
+/// Verify the image against its footer with the hardware CRC unit.
+pub fn verify(crc: Peri<'_, CRC>) -> bool {
+    // SAFETY: the footer is placed in flash by the linker script
+    let [length, expected] = unsafe { read_volatile(addr_of!(__image_footer)) };
+    if expected == UNSEALED {
+        warn!("Image not sealed, skipping CRC check");
+        return true;
+    }
+
+    if length > FLASH_SIZE || !length.is_multiple_of(4) {
+        error!("Image footer corrupted");
+        return false;
+    }
+
+    // SAFETY: the image is plain memory mapped flash
+    let words =
+        unsafe { core::slice::from_raw_parts(FLASH_BASE as *const u32, length as usize / 4) };
+    let mut crc = chip::crc(crc);
+    crc.reset();
+    let actual = crc.feed_words(words);
+    if actual != expected {
+        error!(
+            "Image CRC {=u32:x} does not match {=u32:x}",
+            actual, expected
+        );
+        return false;
+    }
+
+    info!("Image CRC ok");
+    true
+}
+
+/// Refuse to run, repeating three short LED flashes followed by a pause.
+pub async fn halt(led: Peri<'static, StatusLedPin>) -> ! {
+    let mut led = Output::new(led, board::status_led_level(false), Speed::Low);
+    loop {
+        for _ in 0..3 {
+            led.set_level(board::status_led_level(true));
+            Timer::after_millis(100).await;
+            led.set_level(board::status_led_level(false));
+            Timer::after_millis(200).await;
+        }
+        Timer::after_secs(1).await;
+    }
+}
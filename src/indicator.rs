@@ -0,0 +1,176 @@
+//! System status shared by the indicators: LEDs, buzzer and displays.
+//!
+//! Tasks raise and clear [`Condition`]s as they detect them. Every indicator
+//! implements [`StatusIndicator`] and is driven by [`run`], which passes on
+//! each change of the [`Status`] and wakes the indicator for its own timing.
+//! The status LED has a task of its own, the indicators of the features are
+//! fields of the board's [`Indicators`] driven together by [`indicators`].
+
+use embassy_executor::task;
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::Watch;
+use embassy_time::{Instant, Timer};
+
+use crate::board::Indicators;
+use crate::fmt::info;
+use crate::motor_control::MotorStatus;
+
+/// Receivers of the status: the status LED and the feature indicators
+const MAX_INDICATORS: usize = 2;
+
+/// States to indicate, faults first in order of priority.
+#[derive(Clone, Copy)]
+pub enum Condition {
+    /// Safe mode latched after repeated actuator failures
+    Lockout,
+    /// Temperature far above the setpoint
+    Overtemperature,
+    /// No valid reading from the regulation sensor
+    SensorFault,
+    /// The pump stopped for lack of flow
+    #[cfg(all(feature = "pump", feature = "flow"))]
+    DryRun,
+    /// The radio module did not respond at startup
+    #[cfg(any(feature = "lora", feature = "nrf24"))]
+    RadioFault,
+    /// A check of the power-on self-test failed
+    #[cfg(feature = "self-test")]
+    SelfTest,
+    /// Valve driven against an end stop to find its position
+    Calibration,
+    Opening,
+    Closing,
+    /// Manual override running
+    #[cfg(feature = "manual")]
+    Override,
+}
+
+impl Condition {
+    const fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// Set of active conditions.
+#[derive(PartialEq, Clone, Copy, Default)]
+pub struct Status(u16);
+
+/// What the most important active condition asks to show.
+#[derive(PartialEq, Clone, Copy)]
+pub enum Pattern {
+    /// Fault with its blink code
+    Fault(u8),
+    Calibration,
+    Opening,
+    Closing,
+    #[cfg(feature = "manual")]
+    Override,
+    /// Nothing to report
+    Idle,
+}
+
+impl Status {
+    pub fn is(self, condition: Condition) -> bool {
+        self.0 & condition.bit() != 0
+    }
+
+    /// Blink code of the most important fault, if any.
+    pub fn fault_code(self) -> Option<u8> {
+        if self.is(Condition::Lockout) {
+            return Some(7);
+        }
+        if self.is(Condition::Overtemperature) {
+            return Some(4);
+        }
+        if self.is(Condition::SensorFault) {
+            return Some(2);
+        }
+        #[cfg(all(feature = "pump", feature = "flow"))]
+        if self.is(Condition::DryRun) {
+            return Some(5);
+        }
+        #[cfg(any(feature = "lora", feature = "nrf24"))]
+        if self.is(Condition::RadioFault) {
+            return Some(3);
+        }
+        #[cfg(feature = "self-test")]
+        if self.is(Condition::SelfTest) {
+            return Some(6);
+        }
+        None
+    }
+
+    pub fn pattern(self) -> Pattern {
+        if let Some(code) = self.fault_code() {
+            Pattern::Fault(code)
+        } else if self.is(Condition::Calibration) {
+            Pattern::Calibration
+        } else if self.is(Condition::Opening) {
+            Pattern::Opening
+        } else if self.is(Condition::Closing) {
+            Pattern::Closing
+        } else {
+            #[cfg(feature = "manual")]
+            if self.is(Condition::Override) {
+                return Pattern::Override;
+            }
+            Pattern::Idle
+        }
+    }
+}
+
+/// No temperature before the first reading
+static STATUS: Watch<CriticalSectionRawMutex, Status, MAX_INDICATORS> =
+    Watch::new_with(Status(Condition::SensorFault.bit()));
+
+pub fn set(condition: Condition, active: bool) {
+    STATUS.sender().send_if_modified(|status| {
+        let Some(Status(bits)) = status else {
+            return false;
+        };
+        let previous = *bits;
+        if active {
+            *bits |= condition.bit();
+        } else {
+            *bits &= !condition.bit();
+        }
+        *bits != previous
+    });
+}
+
+/// Indicate the direction the motor is running in.
+pub fn motor(status: MotorStatus) {
+    set(Condition::Opening, status == MotorStatus::Opening);
+    set(Condition::Closing, status == MotorStatus::Closing);
+}
+
+/// An output showing the system status.
+pub trait StatusIndicator {
+    const NAME: &'static str;
+
+    /// Take over a changed status.
+    fn update(&mut self, status: Status);
+    /// Drive the output, returns when to be called again at the latest.
+    fn tick(&mut self) -> Instant;
+}
+
+/// Keep `indicator` up to date with the status.
+pub async fn run<I: StatusIndicator>(indicator: &mut I) -> ! {
+    info!("Starting {} indicator", I::NAME);
+    let mut receiver = STATUS.receiver().unwrap();
+    indicator.update(receiver.get().await);
+
+    loop {
+        let next = indicator.tick();
+        if let Either::Second(status) = select(Timer::at(next), receiver.changed()).await {
+            indicator.update(status);
+        }
+    }
+}
+
+/// Drive the indicators of the enabled features, on one status receiver.
+#[task]
+pub async fn indicators(mut indicators: Indicators) {
+    run(&mut indicators).await;
+}
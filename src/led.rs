@@ -0,0 +1,102 @@
+//! Blink codes on the status LED.
+//!
+//! The LED shows the pattern of the most important condition, so common
+//! faults can be told apart without a debugger. A pattern only restarts when
+//! a status change selects a different one, so fault codes stay countable.
+
+use embassy_executor::task;
+use embassy_stm32::gpio::Output;
+use embassy_time::{Duration, Instant};
+
+use crate::board;
+#[cfg(feature = "factory")]
+use crate::factory;
+use crate::indicator::{self, Pattern, Status, StatusIndicator};
+
+pub const FAULT_BLINK_MS: u64 = 400;
+pub const FAULT_PAUSE_MS: u64 = 1600;
+
+// LED on or off for a time in ms
+const CALIBRATION: [(bool, u64); 4] = [(true, 100), (false, 100), (true, 100), (false, 700)];
+const OPENING: [(bool, u64); 2] = [(true, 100), (false, 100)];
+const CLOSING: [(bool, u64); 1] = [(true, 1000)];
+#[cfg(feature = "manual")]
+const OVERRIDE: [(bool, u64); 2] = [(true, 1000), (false, 1000)];
+const HEARTBEAT: [(bool, u64); 2] = [(true, 50), (false, 1950)];
+
+/// Step `index` of a pattern cycle, `None` past its end.
+fn step(pattern: Pattern, index: usize) -> Option<(bool, u64)> {
+    match pattern {
+        // Long blinks repeated `code` times, then a pause
+        Pattern::Fault(code) => {
+            let blinks = 2 * usize::from(code);
+            match index {
+                _ if index < blinks => Some((index.is_multiple_of(2), FAULT_BLINK_MS)),
+                _ if index == blinks => Some((false, FAULT_PAUSE_MS)),
+                _ => None,
+            }
+        }
+        Pattern::Calibration => CALIBRATION.get(index).copied(),
+        Pattern::Opening => OPENING.get(index).copied(),
+        Pattern::Closing => CLOSING.get(index).copied(),
+        #[cfg(feature = "manual")]
+        Pattern::Override => OVERRIDE.get(index).copied(),
+        Pattern::Idle => HEARTBEAT.get(index).copied(),
+    }
+}
+
+struct Led {
+    pin: Output<'static>,
+    pattern: Pattern,
+    index: usize,
+    next: Instant,
+}
+
+impl StatusIndicator for Led {
+    const NAME: &'static str = "LED";
+
+    fn update(&mut self, status: Status) {
+        let pattern = status.pattern();
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            self.index = 0;
+            self.next = Instant::now();
+        }
+    }
+
+    fn tick(&mut self) -> Instant {
+        // The test fixture switches the LED itself
+        #[cfg(feature = "factory")]
+        if factory::active() {
+            return Instant::MAX;
+        }
+
+        let now = Instant::now();
+        if now < self.next {
+            return self.next;
+        }
+
+        let (on, ms) = match step(self.pattern, self.index) {
+            Some(step) => step,
+            None => {
+                self.index = 0;
+                step(self.pattern, 0).unwrap()
+            }
+        };
+        self.index += 1;
+        self.pin.set_level(board::status_led_level(on));
+        self.next = now + Duration::from_millis(ms);
+        self.next
+    }
+}
+
+#[task]
+pub async fn led(pin: Output<'static>) {
+    let mut led = Led {
+        pin,
+        pattern: Pattern::Idle,
+        index: 0,
+        next: Instant::now(),
+    };
+    indicator::run(&mut led).await;
+}
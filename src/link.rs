@@ -0,0 +1,134 @@
+//! Framed, CRC-checked link layer for the serial protocols.
+//!
+//! Frame: `START, length, payload..., CRC-16 (BE)` where the CRC-16/XMODEM
+//! covers length and payload. Any `START` or `ESCAPE` byte after the start is
+//! sent as `ESCAPE, byte ^ 0x20`, so `START` always marks a frame boundary
+//! and the receiver resynchronises on the next frame after line noise.
+
+use embedded_io_async::{Read, Write};
+
+use crate::fmt::warn;
+
+pub const START: u8 = 0x7E;
+const ESCAPE: u8 = 0x7D;
+const ESCAPE_XOR: u8 = 0x20;
+/// Length byte and CRC around the payload
+pub const OVERHEAD: usize = 3;
+
+/// CRC-16/XMODEM, continuing from `crc`.
+fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// CRC-16/XMODEM, as used by the frames and XMODEM blocks.
+pub fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0, data)
+}
+
+/// Receiver state for frames of up to `N` bytes including the overhead.
+pub struct Decoder<const N: usize> {
+    buffer: [u8; N],
+    len: usize,
+    escaped: bool,
+    in_frame: bool,
+}
+
+impl<const N: usize> Decoder<N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            len: 0,
+            escaped: false,
+            in_frame: false,
+        }
+    }
+
+    /// Feed a received byte, returns true once a valid frame is complete.
+    pub fn feed(&mut self, byte: u8) -> bool {
+        if byte == START {
+            self.len = 0;
+            self.escaped = false;
+            self.in_frame = true;
+            return false;
+        }
+        if !self.in_frame {
+            return false;
+        }
+
+        let byte = if self.escaped {
+            self.escaped = false;
+            byte ^ ESCAPE_XOR
+        } else if byte == ESCAPE {
+            self.escaped = true;
+            return false;
+        } else {
+            byte
+        };
+
+        if self.len == N {
+            warn!("Link: frame too long");
+            self.in_frame = false;
+            return false;
+        }
+        self.buffer[self.len] = byte;
+        self.len += 1;
+
+        let end = self.buffer[0] as usize + 1;
+        if self.len < end + 2 {
+            return false;
+        }
+
+        self.in_frame = false;
+        let crc = u16::from_be_bytes([self.buffer[end], self.buffer[end + 1]]);
+        if crc16(&self.buffer[..end]) != crc {
+            warn!("Link: CRC error");
+            return false;
+        }
+        true
+    }
+
+    /// Payload of the last complete frame.
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer[1..self.buffer[0] as usize + 1]
+    }
+}
+
+/// Wait for the next valid frame, its payload is then in the decoder.
+pub async fn read_frame<R: Read, const N: usize>(
+    reader: &mut R,
+    decoder: &mut Decoder<N>,
+) -> Result<(), R::Error> {
+    let mut byte = [0u8];
+    loop {
+        // Byte by byte, so nothing after the end of the frame is consumed
+        if reader.read(&mut byte).await? == 1 && decoder.feed(byte[0]) {
+            return Ok(());
+        }
+    }
+}
+
+/// Send `payload` (at most 255 bytes) as one frame.
+pub async fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> Result<(), W::Error> {
+    let len = [payload.len() as u8];
+    let crc = crc16_update(crc16(&len), payload).to_be_bytes();
+
+    writer.write_all(&[START]).await?;
+    for byte in len.iter().chain(payload).chain(&crc) {
+        if *byte == START || *byte == ESCAPE {
+            writer.write_all(&[ESCAPE, byte ^ ESCAPE_XOR]).await?;
+        } else {
+            writer.write_all(&[*byte]).await?;
+        }
+    }
+    Ok(())
+}
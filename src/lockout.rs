@@ -0,0 +1,122 @@
+//! Safe mode latched after repeated actuator failures.
+//!
+//! The firmware has no current sense or limit switches to see the actuator
+//! stall, what it notices is the result: the temperature still past the
+//! overtemperature margin after the valve was driven closed and given
+//! `LOCKOUT_SETTLE_S` to take effect, so the valve did not close. A plain
+//! overshoot while the valve is still closing does not count. [`TRIPS`] such
+//! failures within [`WINDOW`] latch the safe mode, the motor is de-energized
+//! and stays so until the user clears it with the mode button or
+//! `lockout clear` in the shell.
+//!
+//! The reason is kept in RAM surviving a reset, a watchdog or a crash
+//! restart does not release the lockout, only a power cycle does.
+
+use core::cell::RefCell;
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of_mut, read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+
+#[cfg(any(feature = "shell", feature = "input"))]
+use crate::fmt::info;
+use crate::fmt::warn;
+use crate::indicator::{self, Condition};
+
+/// Failures latching the safe mode
+const TRIPS: usize = 3;
+/// Time the failures have to fall into
+const WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Upper half of the stored word, the reason is in the lower one
+const LOCKOUT_MAGIC: u32 = 0x10C0_0000;
+
+/// Why the safe mode was latched.
+#[derive(Clone, Copy)]
+pub enum Reason {
+    /// The temperature overshot with the valve meant to be closed
+    Overtemperature = 1,
+}
+
+impl Reason {
+    pub fn name(self) -> &'static str {
+        match self {
+            Reason::Overtemperature => "overtemperature",
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Reason::Overtemperature),
+            _ => None,
+        }
+    }
+}
+
+#[unsafe(link_section = ".uninit.LOCKOUT")]
+static mut LOCKOUT: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// Reason code of the latched safe mode, 0 while not latched
+static LATCHED: AtomicU8 = AtomicU8::new(0);
+static FAILURES: Mutex<CriticalSectionRawMutex, RefCell<[Option<Instant>; TRIPS]>> =
+    Mutex::new(RefCell::new([None; TRIPS]));
+
+fn store(value: u32) {
+    // SAFETY: single volatile write of a plain word in .uninit
+    unsafe { write_volatile(addr_of_mut!(LOCKOUT).cast::<u32>(), value) };
+}
+
+/// Restore a lockout latched before the last reset.
+pub fn check() {
+    // SAFETY: single volatile read of a plain word in .uninit, garbage after
+    // a power cycle does not match the magic
+    let value = unsafe { read_volatile(addr_of_mut!(LOCKOUT).cast::<u32>()) };
+    if value & 0xFFFF_0000 != LOCKOUT_MAGIC {
+        return;
+    }
+    match Reason::from_code(value as u8) {
+        Some(reason) => {
+            warn!("Safe mode latched before the reset: {}", reason.name());
+            LATCHED.store(reason as u8, Ordering::Relaxed);
+            indicator::set(Condition::Lockout, true);
+        }
+        None => store(0),
+    }
+}
+
+pub fn latched() -> Option<Reason> {
+    Reason::from_code(LATCHED.load(Ordering::Relaxed))
+}
+
+/// Count an actuator failure, latching the safe mode on too many.
+pub fn trip(reason: Reason) {
+    let now = Instant::now();
+    let latch = FAILURES.lock(|failures| {
+        let mut failures = failures.borrow_mut();
+        failures.rotate_right(1);
+        failures[0] = Some(now);
+        failures
+            .iter()
+            .all(|failure| failure.is_some_and(|at| now - at <= WINDOW))
+    });
+    warn!("Actuator failure: {}", reason.name());
+
+    if latch && latched().is_none() {
+        warn!("Safe mode latched: {}", reason.name());
+        store(LOCKOUT_MAGIC | reason as u32);
+        LATCHED.store(reason as u8, Ordering::Relaxed);
+        indicator::set(Condition::Lockout, true);
+    }
+}
+
+/// Release the safe mode on the user's request.
+#[cfg(any(feature = "shell", feature = "input"))]
+pub fn clear() {
+    info!("Safe mode cleared");
+    store(0);
+    FAILURES.lock(|failures| *failures.borrow_mut() = [None; TRIPS]);
+    LATCHED.store(0, Ordering::Relaxed);
+    indicator::set(Condition::Lockout, false);
+}
@@ -0,0 +1,121 @@
+//! Log levels adjustable at run time per module.
+//!
+//! `DEFMT_LOG` sets the most verbose level compiled in, the levels here only
+//! silence what is there. Messages logged through [`log!`] pass the level of
+//! their module, e.g. to hide the NTC readings every second but keep the
+//! motor movements. Other messages are not filtered. The level after a
+//! restart is the `log_level` tuning setting.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::config::LOG_LEVEL;
+
+/// Sources of messages with their own level.
+#[derive(Clone, Copy)]
+pub enum Module {
+    /// Readings of the on-board NTC
+    Ntc,
+    /// Valve movements and regulation decisions
+    Motor,
+    /// Readings of the other sensors
+    Sensors,
+}
+
+impl Module {
+    pub const ALL: [Module; 3] = [Module::Ntc, Module::Motor, Module::Sensors];
+
+    #[cfg(feature = "shell")]
+    pub fn name(self) -> &'static str {
+        match self {
+            Module::Ntc => "ntc",
+            Module::Motor => "motor",
+            Module::Sensors => "sensors",
+        }
+    }
+
+    #[cfg(feature = "shell")]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|module| module.name() == name)
+    }
+}
+
+/// Verbosity, each level includes the ones before.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    const ALL: [Level; 6] = [
+        Level::Off,
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    #[cfg(feature = "shell")]
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    #[cfg(feature = "shell")]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|level| level.name() == name)
+    }
+}
+
+static LEVELS: [AtomicU8; Module::ALL.len()] =
+    [const { AtomicU8::new(LOG_LEVEL as u8) }; Module::ALL.len()];
+
+pub fn level(module: Module) -> Level {
+    Level::ALL[usize::from(LEVELS[module as usize].load(Ordering::Relaxed))]
+}
+
+#[cfg(feature = "shell")]
+pub fn set_level(module: Module, level: Level) {
+    LEVELS[module as usize].store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(module: Module, level: Level) -> bool {
+    level <= self::level(module)
+}
+
+/// Log statement filtered by the level of a module:
+/// `log!(Motor, info, "Opening motor for {}s", duration)`.
+macro_rules! log {
+    ($module:ident, error, $($arg:tt)+) => {
+        $crate::log::log!(@ $module, Error, error, $($arg)+)
+    };
+    ($module:ident, warn, $($arg:tt)+) => {
+        $crate::log::log!(@ $module, Warn, warn, $($arg)+)
+    };
+    ($module:ident, info, $($arg:tt)+) => {
+        $crate::log::log!(@ $module, Info, info, $($arg)+)
+    };
+    ($module:ident, debug, $($arg:tt)+) => {
+        $crate::log::log!(@ $module, Debug, debug, $($arg)+)
+    };
+    ($module:ident, trace, $($arg:tt)+) => {
+        $crate::log::log!(@ $module, Trace, trace, $($arg)+)
+    };
+    (@ $module:ident, $level:ident, $macro:ident, $($arg:tt)+) => {
+        if $crate::log::enabled($crate::log::Module::$module, $crate::log::Level::$level) {
+            $crate::fmt::$macro!($($arg)+);
+        }
+    };
+}
+pub(crate) use log;
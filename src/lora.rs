@@ -0,0 +1,295 @@
+//! LoRa telemetry through an SX1276-class radio.
+//!
+//! Sends a compact status frame every uplink interval and listens for
+//! setpoint downlinks in between.
+//!
+//! Uplink frame: `MAGIC, serial (u32 LE), sequence, temperature (0.1 °C, i16 LE),
+//! setpoint (0.1 °C, i16 LE), valve position (%), motor status`
+//!
+//! Downlink frame: `MAGIC, serial (u32 LE), DOWNLINK_SETPOINT, setpoint (0.1 °C, i16 LE)`
+//! or, with the `rtc` feature, `MAGIC, serial (u32 LE), DOWNLINK_TIME, UTC
+//! seconds since the epoch (u32 LE)` to keep the clock in sync.
+//!
+//! The serial is the device serial derived from the MCU unique ID. With the
+//! `auth` feature downlinks end with a counter (u32 LE) and tag, see `auth`.
+
+use embassy_executor::task;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Output;
+use embassy_stm32::mode::Blocking;
+use embassy_stm32::spi::Spi;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+
+#[cfg(feature = "auth")]
+use crate::auth;
+use crate::fmt::{debug, info, warn};
+use crate::indicator::{self, Condition};
+#[cfg(feature = "rtc")]
+use crate::rtc;
+use crate::{identity, state};
+
+const FREQUENCY_HZ: u64 = 868_100_000;
+const SPREADING_FACTOR: u8 = 9;
+const TX_POWER_DBM: u8 = 14;
+const SYNC_WORD: u8 = 0x12; // Private network
+const UPLINK_INTERVAL: Duration = Duration::from_secs(60);
+const TX_TIMEOUT: Duration = Duration::from_secs(2);
+const MAGIC: u8 = 0x48;
+const DOWNLINK_SETPOINT: u8 = 0x01;
+#[cfg(feature = "rtc")]
+const DOWNLINK_TIME: u8 = 0x02;
+
+const FXOSC_HZ: u64 = 32_000_000;
+const CHIP_VERSION: u8 = 0x12;
+
+// Registers
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_PA_CONFIG: u8 = 0x09;
+const REG_LNA: u8 = 0x0C;
+const REG_FIFO_ADDR_PTR: u8 = 0x0D;
+const REG_FIFO_TX_BASE_ADDR: u8 = 0x0E;
+const REG_FIFO_RX_BASE_ADDR: u8 = 0x0F;
+const REG_FIFO_RX_CURRENT_ADDR: u8 = 0x10;
+const REG_IRQ_FLAGS: u8 = 0x12;
+const REG_RX_NB_BYTES: u8 = 0x13;
+const REG_PKT_RSSI_VALUE: u8 = 0x1A;
+const REG_MODEM_CONFIG_1: u8 = 0x1D;
+const REG_MODEM_CONFIG_2: u8 = 0x1E;
+const REG_PAYLOAD_LENGTH: u8 = 0x22;
+const REG_MODEM_CONFIG_3: u8 = 0x26;
+const REG_SYNC_WORD: u8 = 0x39;
+const REG_DIO_MAPPING_1: u8 = 0x40;
+const REG_VERSION: u8 = 0x42;
+
+// Operating modes
+const MODE_LONG_RANGE: u8 = 0x80;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STANDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+const MODE_RX_CONTINUOUS: u8 = 0x05;
+
+// IRQ flags
+const IRQ_RX_DONE: u8 = 0x40;
+const IRQ_PAYLOAD_CRC_ERROR: u8 = 0x20;
+const IRQ_TX_DONE: u8 = 0x08;
+
+// DIO0 mapping
+const DIO0_RX_DONE: u8 = 0x00;
+const DIO0_TX_DONE: u8 = 0x40;
+
+pub struct Sx127x {
+    spi: Spi<'static, Blocking>,
+    nss: Output<'static>,
+    reset: Output<'static>,
+    dio0: ExtiInput<'static>,
+}
+
+impl Sx127x {
+    pub fn new(
+        spi: Spi<'static, Blocking>,
+        nss: Output<'static>,
+        reset: Output<'static>,
+        dio0: ExtiInput<'static>,
+    ) -> Self {
+        Self {
+            spi,
+            nss,
+            reset,
+            dio0,
+        }
+    }
+
+    fn read_register(&mut self, register: u8) -> u8 {
+        let mut buf = [register & 0x7F, 0];
+        self.nss.set_low();
+        let _ = self.spi.blocking_transfer_in_place(&mut buf);
+        self.nss.set_high();
+        buf[1]
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) {
+        self.nss.set_low();
+        let _ = self.spi.blocking_write(&[register | 0x80, value]);
+        self.nss.set_high();
+    }
+
+    fn set_mode(&mut self, mode: u8) {
+        self.write_register(REG_OP_MODE, MODE_LONG_RANGE | mode);
+    }
+
+    pub async fn init(&mut self) -> bool {
+        self.reset.set_low();
+        Timer::after_millis(1).await;
+        self.reset.set_high();
+        Timer::after_millis(10).await;
+
+        let version = self.read_register(REG_VERSION);
+        if version != CHIP_VERSION {
+            warn!("LoRa: unexpected chip version {:x}", version);
+            return false;
+        }
+
+        // LoRa mode can only be selected in sleep
+        self.write_register(REG_OP_MODE, MODE_SLEEP);
+        self.set_mode(MODE_SLEEP);
+
+        let frf = (FREQUENCY_HZ << 19) / FXOSC_HZ;
+        self.write_register(REG_FRF_MSB, (frf >> 16) as u8);
+        self.write_register(REG_FRF_MSB + 1, (frf >> 8) as u8);
+        self.write_register(REG_FRF_MSB + 2, frf as u8);
+
+        self.write_register(REG_FIFO_TX_BASE_ADDR, 0x00);
+        self.write_register(REG_FIFO_RX_BASE_ADDR, 0x00);
+        let lna = self.read_register(REG_LNA);
+        self.write_register(REG_LNA, lna | 0x03); // LNA boost
+        self.write_register(REG_MODEM_CONFIG_1, 0x72); // 125 kHz, 4/5, explicit header
+        self.write_register(REG_MODEM_CONFIG_2, (SPREADING_FACTOR << 4) | 0x04); // CRC on
+        self.write_register(REG_MODEM_CONFIG_3, 0x04); // AGC auto
+        self.write_register(REG_SYNC_WORD, SYNC_WORD);
+        self.write_register(REG_PA_CONFIG, 0x80 | (TX_POWER_DBM - 2)); // PA_BOOST
+
+        self.set_mode(MODE_STANDBY);
+        true
+    }
+
+    pub async fn transmit(&mut self, payload: &[u8]) -> bool {
+        self.set_mode(MODE_STANDBY);
+        self.write_register(REG_DIO_MAPPING_1, DIO0_TX_DONE);
+        self.write_register(REG_FIFO_ADDR_PTR, 0x00);
+
+        self.nss.set_low();
+        let _ = self.spi.blocking_write(&[REG_FIFO | 0x80]);
+        let _ = self.spi.blocking_write(payload);
+        self.nss.set_high();
+
+        self.write_register(REG_PAYLOAD_LENGTH, payload.len() as u8);
+        self.set_mode(MODE_TX);
+
+        let done = with_timeout(TX_TIMEOUT, self.dio0.wait_for_high())
+            .await
+            .is_ok();
+        self.write_register(REG_IRQ_FLAGS, IRQ_TX_DONE);
+        done
+    }
+
+    pub fn start_receive(&mut self) {
+        self.write_register(REG_DIO_MAPPING_1, DIO0_RX_DONE);
+        self.set_mode(MODE_RX_CONTINUOUS);
+    }
+
+    /// Wait for a received packet, returns its length and RSSI.
+    pub async fn receive(&mut self, buf: &mut [u8]) -> Option<(usize, i16)> {
+        self.dio0.wait_for_high().await;
+
+        let flags = self.read_register(REG_IRQ_FLAGS);
+        self.write_register(REG_IRQ_FLAGS, flags);
+        if flags & IRQ_RX_DONE == 0 || flags & IRQ_PAYLOAD_CRC_ERROR != 0 {
+            return None;
+        }
+
+        let len = (self.read_register(REG_RX_NB_BYTES) as usize).min(buf.len());
+        let address = self.read_register(REG_FIFO_RX_CURRENT_ADDR);
+        self.write_register(REG_FIFO_ADDR_PTR, address);
+
+        self.nss.set_low();
+        let _ = self.spi.blocking_write(&[REG_FIFO & 0x7F]);
+        let _ = self.spi.blocking_read(&mut buf[..len]);
+        self.nss.set_high();
+
+        let rssi = self.read_register(REG_PKT_RSSI_VALUE) as i16 - 157;
+        Some((len, rssi))
+    }
+}
+
+fn status_frame(sequence: u8) -> [u8; 12] {
+    let state = state::get();
+    let serial = identity::serial().to_le_bytes();
+    let temperature = state::deci_degrees(state.temperature).to_le_bytes();
+    let setpoint = state::deci_degrees(state.setpoint).to_le_bytes();
+
+    [
+        MAGIC,
+        serial[0],
+        serial[1],
+        serial[2],
+        serial[3],
+        sequence,
+        temperature[0],
+        temperature[1],
+        setpoint[0],
+        setpoint[1],
+        state.valve_position,
+        state.motor_status as u8,
+    ]
+}
+
+fn handle_downlink(frame: &[u8]) {
+    // Downlinks to the other nodes on the channel are none of our business
+    if frame.get(1..5) != Some(&identity::serial().to_le_bytes()[..]) {
+        debug!("LoRa: downlink for another node");
+        return;
+    }
+
+    // Frames for this node end with a counter and tag
+    #[cfg(feature = "auth")]
+    let Some(frame) = auth::verify_binary(&[], frame) else {
+        return;
+    };
+
+    match frame {
+        [MAGIC, _, _, _, _, DOWNLINK_SETPOINT, low, high] => {
+            let setpoint = i16::from_le_bytes([*low, *high]) as f32 / 10.0;
+            if state::set_setpoint(setpoint) {
+                info!("LoRa: setpoint changed to {}", setpoint);
+            } else {
+                warn!("LoRa: setpoint {} out of range", setpoint);
+            }
+        }
+        #[cfg(feature = "rtc")]
+        [MAGIC, _, _, _, _, DOWNLINK_TIME, time @ ..] if time.len() == 4 => {
+            rtc::sync(u32::from_le_bytes([time[0], time[1], time[2], time[3]]));
+        }
+        _ => warn!("LoRa: unknown downlink"),
+    }
+}
+
+#[task]
+pub async fn lora(mut radio: Sx127x) {
+    if !radio.init().await {
+        warn!("LoRa: radio not found, telemetry disabled");
+        indicator::set(Condition::RadioFault, true);
+        return;
+    }
+
+    info!(
+        "Starting LoRa telemetry, serial {=u32:08x}",
+        identity::serial()
+    );
+
+    let mut sequence: u8 = 0;
+    let mut buf = [0u8; 32];
+
+    loop {
+        if !radio.transmit(&status_frame(sequence)).await {
+            warn!("LoRa: uplink timeout");
+        }
+        sequence = sequence.wrapping_add(1);
+
+        // Listen for downlinks until the next uplink is due
+        let next_uplink = Instant::now() + UPLINK_INTERVAL;
+        radio.start_receive();
+        while let Ok(received) = with_timeout(
+            next_uplink.saturating_duration_since(Instant::now()),
+            radio.receive(&mut buf),
+        )
+        .await
+        {
+            if let Some((len, rssi)) = received {
+                info!("LoRa: downlink {} bytes, RSSI {}", len, rssi);
+                handle_downlink(&buf[..len]);
+            }
+        }
+    }
+}
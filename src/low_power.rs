@@ -0,0 +1,186 @@
+//! STOP mode between events, with the time driver on the RTC.
+//!
+//! The timers of the HAL time driver stop along with the high-speed clocks
+//! in STOP mode. With `low-power` the RTC counter on the LSE crystal is the
+//! time base instead, counting at the tick rate of 1024 Hz, and its alarm
+//! wakes the controller through EXTI17 for the next timer. The other
+//! wakeups come from the EXTI inputs, features relying on any other
+//! interrupt are refused.
+//!
+//! The executor enters STOP whenever it runs out of work, unless an
+//! [`Awake`] is held by a task waiting for a peripheral that needs the
+//! clocks, like an ADC conversion. It sleeps as before then. After STOP the
+//! controller runs from the HSI again, the system clock without `hse`, and
+//! the RTC registers are read only once the APB interface caught up.
+//!
+//! The counter wraps around after 48 days. The alarm is set half of that
+//! ahead at most, so every wrap is seen and counted into the upper half of
+//! the 64-bit time.
+
+use core::cell::{Cell, RefCell};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU32, Ordering, compiler_fence};
+use core::task::Waker;
+
+use embassy_executor::{Spawner, raw};
+use embassy_stm32::interrupt::{Interrupt, InterruptExt};
+use embassy_stm32::pac::pwr::vals::Pdds;
+use embassy_stm32::pac::{EXTI, PWR, interrupt};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time_driver::Driver;
+use embassy_time_queue_utils::Queue;
+
+use crate::rtc;
+
+/// EXTI line of the RTC alarm
+const ALARM_LINE: usize = 17;
+/// Furthest the alarm is set ahead, half of the counter range
+const MAX_ALARM: u64 = 1 << 31;
+/// Context of the thread mode executor, for which the pender sends an event
+const THREAD_PENDER: usize = usize::MAX;
+
+/// Tasks keeping the executor out of STOP
+static AWAKE: AtomicU32 = AtomicU32::new(0);
+
+/// Keeps the executor out of STOP mode while held.
+pub struct Awake(());
+
+impl Awake {
+    pub fn new() -> Self {
+        AWAKE.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for Awake {
+    fn drop(&mut self) {
+        AWAKE.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct RtcDriver {
+    /// Counter wrap arounds seen, the upper half of the time
+    wraps: Mutex<CriticalSectionRawMutex, Cell<u32>>,
+    queue: Mutex<CriticalSectionRawMutex, RefCell<Queue>>,
+}
+
+embassy_time_driver::time_driver_impl!(static DRIVER: RtcDriver = RtcDriver {
+    wraps: Mutex::new(Cell::new(0)),
+    queue: Mutex::new(RefCell::new(Queue::new())),
+});
+
+impl RtcDriver {
+    /// Set the alarm for `at`, false if it passed already.
+    fn set_alarm(&self, at: u64) -> bool {
+        let now = self.now();
+        if at <= now {
+            return false;
+        }
+        rtc::set_alarm(at.min(now + MAX_ALARM) as u32);
+
+        // The alarm only rings when the counter reaches it, which it may
+        // have done while the alarm was being written
+        at > self.now()
+    }
+
+    /// Set the alarm for the next timer in the queue.
+    fn rearm(&self, queue: &mut Queue) {
+        let mut next = queue.next_expiration(self.now());
+        while !self.set_alarm(next) {
+            next = queue.next_expiration(self.now());
+        }
+    }
+
+    fn on_alarm(&self) {
+        self.queue.lock(|queue| self.rearm(&mut queue.borrow_mut()));
+    }
+}
+
+impl Driver for RtcDriver {
+    fn now(&self) -> u64 {
+        self.wraps.lock(|wraps| {
+            let (counter, wrapped) = rtc::ticks();
+            if wrapped {
+                wraps.set(wraps.get() + 1);
+            }
+            u64::from(wraps.get()) << 32 | u64::from(counter)
+        })
+    }
+
+    fn schedule_wake(&self, at: u64, waker: &Waker) {
+        self.queue.lock(|queue| {
+            let mut queue = queue.borrow_mut();
+            if queue.schedule_wake(at, waker) {
+                self.rearm(&mut queue);
+            }
+        });
+    }
+}
+
+#[interrupt]
+fn RTC_ALARM() {
+    EXTI.pr(0).write(|w| w.set_line(ALARM_LINE, true));
+    rtc::resync();
+    rtc::clear_alarm();
+    DRIVER.on_alarm();
+}
+
+/// Route the RTC alarm to its interrupt and select STOP mode for deep
+/// sleep, after the RTC was initialized.
+pub fn init() {
+    PWR.cr().modify(|w| {
+        w.set_pdds(Pdds::STOP_MODE);
+        // Low-power regulator, a few µs more to wake up
+        w.set_lpds(true);
+    });
+
+    EXTI.rtsr(0).modify(|w| w.set_line(ALARM_LINE, true));
+    EXTI.imr(0).modify(|w| w.set_line(ALARM_LINE, true));
+    rtc::enable_alarm();
+    Interrupt::RTC_ALARM.unpend();
+    // SAFETY: the handler only touches the driver, under its lock
+    unsafe { Interrupt::RTC_ALARM.enable() };
+
+    // Wakes for the wrap around even without timers
+    DRIVER.on_alarm();
+}
+
+/// Thread mode executor entering STOP mode when idle.
+pub struct Executor {
+    inner: raw::Executor,
+    not_send: PhantomData<*mut ()>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self {
+            inner: raw::Executor::new(THREAD_PENDER as *mut ()),
+            not_send: PhantomData,
+        }
+    }
+
+    pub fn run(&'static mut self, init: impl FnOnce(Spawner)) -> ! {
+        init(self.inner.spawner());
+
+        // SAFETY: only SLEEPDEEP is changed, nothing else uses the SCB
+        let mut scb = unsafe { cortex_m::Peripherals::steal() }.SCB;
+        loop {
+            // SAFETY: polled from this thread only
+            unsafe { self.inner.poll() };
+
+            let stop = AWAKE.load(Ordering::Relaxed) == 0;
+            if stop {
+                scb.set_sleepdeep();
+            } else {
+                scb.clear_sleepdeep();
+            }
+            compiler_fence(Ordering::SeqCst);
+            cortex_m::asm::wfe();
+
+            if stop {
+                rtc::resync();
+            }
+        }
+    }
+}
@@ -1,68 +1,565 @@
 #![no_std]
 #![no_main]
 
+mod actuators;
+#[cfg(feature = "analog")]
+mod analog_input;
+#[cfg(feature = "auth")]
+mod auth;
+#[cfg(feature = "bacnet")]
+mod bacnet;
+#[cfg(feature = "ble")]
+mod ble;
+mod board;
+#[cfg(feature = "boiler")]
+mod boiler;
+#[cfg(feature = "bootloader")]
+mod bootloader;
+#[cfg(feature = "buttons")]
+mod buttons;
+#[cfg(feature = "buzzer")]
+mod buzzer;
+mod chip;
+mod clock;
+mod config;
+#[cfg(feature = "demand")]
+mod demand;
+#[cfg(feature = "i2c-sensor")]
+mod dew_point;
+#[cfg(feature = "display")]
+mod display;
+#[cfg(feature = "encoder")]
+mod encoder;
+#[cfg(feature = "energy")]
+mod energy;
+#[cfg(feature = "factory")]
+mod factory;
+#[cfg(feature = "fan")]
+mod fan;
+#[cfg(any(
+    feature = "iap",
+    feature = "auth",
+    feature = "energy",
+    feature = "factory"
+))]
+mod flash;
+#[cfg(feature = "flow")]
+mod flow;
+mod fmt;
+#[cfg(feature = "hd44780")]
+mod hd44780;
+#[cfg(feature = "i2c-sensor")]
+mod i2c_sensor;
+#[cfg(feature = "iap")]
+mod iap;
+mod identity;
+mod image;
+mod indicator;
+mod led;
+#[cfg(any(feature = "ble", feature = "iap"))]
+mod link;
+mod lockout;
+mod log;
+#[cfg(feature = "lora")]
+mod lora;
+#[cfg(feature = "low-power")]
+mod low_power;
+#[cfg(feature = "manual")]
+mod manual;
+#[cfg(feature = "mbus")]
+mod mbus;
+#[cfg(all(feature = "display", feature = "input"))]
+mod menu;
 mod motor_control;
+#[cfg(feature = "nrf24")]
+mod nrf24;
 mod ntc;
+mod panic;
+#[cfg(feature = "power")]
+mod power;
+#[cfg(feature = "pump")]
+mod pump;
+#[cfg(feature = "pwm-input")]
+mod pwm_input;
+#[cfg(feature = "rgb-led")]
+mod rgb_led;
+#[cfg(feature = "rtc")]
+mod rtc;
+#[cfg(feature = "self-test")]
+mod self_test;
+#[cfg(feature = "sg-ready")]
+mod sg_ready;
+#[cfg(feature = "shell")]
+mod shell;
+#[cfg(feature = "spi-sensor")]
+mod spi_sensor;
+#[cfg(feature = "ssd1306")]
+mod ssd1306;
+#[cfg(feature = "stack")]
+mod stack;
+#[cfg(feature = "stages")]
+mod stages;
+mod state;
+mod temperature;
+#[cfg(feature = "tm1637")]
+mod tm1637;
+#[cfg(feature = "usb")]
+mod usb;
+mod version;
+#[cfg(feature = "window")]
+mod window;
 
-use crate::motor_control::{MotorControl, MotorStatus, motor_control};
+use crate::fmt::info;
+#[cfg(feature = "commands")]
+use crate::motor_control::MotorCommand;
+use crate::motor_control::{MotorControl, motor_control};
 use crate::ntc::ntc;
-use defmt::info;
 use embassy_executor::Spawner;
+use embassy_stm32::bind_interrupts;
+// Unused by the G0 without features, it binds no interrupt then
 use embassy_stm32::gpio::{Level, Output, Speed};
+#[allow(unused_imports)]
 use embassy_stm32::peripherals::*;
-use embassy_stm32::{adc, bind_interrupts};
+#[cfg(feature = "commands")]
+use embassy_sync::channel::Channel;
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
-use embassy_time::Duration;
-use embassy_time::Timer;
-
-#[cfg(not(feature = "defmt"))]
-use panic_halt as _;
 
 #[cfg(feature = "defmt")]
-use {defmt_rtt as _, panic_probe as _};
+use defmt_rtt as _;
+
+#[cfg(all(feature = "hse", feature = "low-clock"))]
+compile_error!("features `hse` and `low-clock` are alternative clock setups, `usb` needs `hse`");
+#[cfg(all(feature = "bacnet", feature = "mbus"))]
+compile_error!("features `bacnet` and `mbus` both use USART3");
+#[cfg(all(
+    feature = "auth",
+    not(any(feature = "ble", feature = "lora", feature = "shell"))
+))]
+compile_error!("feature `auth` needs one of `ble`, `lora` or `usb`");
+#[cfg(all(feature = "ble", feature = "iap"))]
+compile_error!("features `ble` and `iap` both use USART1");
+#[cfg(all(feature = "analog", feature = "pwm-input"))]
+compile_error!("features `analog` and `pwm-input` both set the demand");
+#[cfg(all(feature = "buttons", feature = "encoder"))]
+compile_error!("features `buttons` and `encoder` both use PA15");
+#[cfg(all(feature = "encoder", feature = "pwm-input"))]
+compile_error!("feature `encoder` uses PA6, PA7 and TIM3");
+#[cfg(all(feature = "hd44780", feature = "ssd1306"))]
+compile_error!("features `hd44780` and `ssd1306` are alternative displays");
+#[cfg(all(
+    feature = "tm1637",
+    any(
+        all(feature = "hd44780", not(feature = "hd44780-gpio")),
+        feature = "ssd1306"
+    )
+))]
+compile_error!("feature `tm1637` uses PB6 and PB7 like the I2C displays");
+#[cfg(all(feature = "sht3x", feature = "bme280"))]
+compile_error!("features `sht3x` and `bme280` are alternative room sensors");
+#[cfg(all(feature = "i2c-sensor", feature = "nrf24"))]
+compile_error!("features `sht3x`/`bme280` and `nrf24` are alternative room sensors");
+#[cfg(all(
+    feature = "i2c-sensor",
+    any(
+        all(feature = "hd44780", not(feature = "hd44780-gpio")),
+        feature = "ssd1306",
+        feature = "tm1637"
+    )
+))]
+compile_error!("features `sht3x` and `bme280` use I2C1 on PB6 and PB7 like the displays");
+#[cfg(all(feature = "max31865", feature = "max31855"))]
+compile_error!("features `max31865` and `max31855` are alternative temperature sensors");
+#[cfg(all(feature = "spi-sensor", any(feature = "nrf24", feature = "i2c-sensor")))]
+compile_error!("features `max31865`/`max31855` and a room sensor both set the regulation source");
+#[cfg(all(
+    feature = "spi-sensor",
+    any(
+        feature = "nrf24",
+        feature = "hd44780-gpio",
+        feature = "stages",
+        feature = "bacnet"
+    )
+))]
+compile_error!("features `max31865` and `max31855` use SPI2 on PB12-PB15");
+#[cfg(all(feature = "hd44780-gpio", any(feature = "bacnet", feature = "nrf24")))]
+compile_error!("feature `hd44780-gpio` uses PA8, PB9 and PB12-PB15");
+#[cfg(all(
+    feature = "rgb-led",
+    any(
+        feature = "ble",
+        feature = "iap",
+        feature = "nrf24",
+        feature = "hd44780-gpio"
+    )
+))]
+compile_error!("feature `rgb-led` uses PA8, PA9, PA10 and TIM1");
+#[cfg(all(feature = "buzzer", any(feature = "bacnet", feature = "mbus")))]
+compile_error!("feature `buzzer` uses PB10");
+#[cfg(all(
+    feature = "fan",
+    any(feature = "lora", feature = "pwm-input", feature = "encoder")
+))]
+compile_error!("feature `fan` uses PB0, PB1 and TIM3");
+#[cfg(all(
+    feature = "stages",
+    any(feature = "bacnet", feature = "mbus", feature = "hd44780-gpio")
+))]
+compile_error!("feature `stages` uses PB11 and PB12");
+#[cfg(all(feature = "flow", any(feature = "nrf24", feature = "hd44780-gpio")))]
+compile_error!("feature `flow` uses PB9");
+#[cfg(all(feature = "energy", feature = "analog"))]
+compile_error!("features `energy` and `analog` both use ADC2");
+#[cfg(all(feature = "energy", any(feature = "nrf24", feature = "i2c-sensor")))]
+compile_error!("feature `energy` needs the on-board NTC as the supply temperature");
+#[cfg(all(feature = "energy", feature = "iap"))]
+compile_error!("feature `energy` keeps its total in a flash page used by `iap`");
+#[cfg(all(feature = "auth", feature = "iap"))]
+compile_error!("feature `auth` keeps its counters in flash pages used by `iap`");
+#[cfg(all(feature = "factory", feature = "iap"))]
+compile_error!("feature `factory` keeps the calibration in a flash page used by `iap`");
+#[cfg(all(feature = "boiler", feature = "nrf24"))]
+compile_error!("features `boiler` and `nrf24` both use PB8");
+#[cfg(all(feature = "buttons", feature = "sg-ready"))]
+compile_error!("features `buttons` and `sg-ready` both use PB3 and PB4");
+#[cfg(not(any(feature = "time-driver-tim", feature = "low-power")))]
+compile_error!("select a time driver, `time-driver-tim` or `low-power`");
+#[cfg(all(feature = "time-driver-tim", feature = "low-power"))]
+compile_error!("feature `low-power` replaces the time driver of `time-driver-tim`");
+#[cfg(all(feature = "low-power", feature = "hse"))]
+compile_error!("feature `low-power` wakes up on the HSI, `hse` would need the PLL restarted");
+#[cfg(all(
+    feature = "low-power",
+    any(feature = "ble", feature = "iap", feature = "bacnet", feature = "mbus")
+))]
+compile_error!("feature `low-power` stops the USARTs, which can not wake it up");
+#[cfg(all(
+    feature = "low-power",
+    any(
+        feature = "buzzer",
+        feature = "rgb-led",
+        feature = "fan",
+        feature = "pwm-input",
+        feature = "encoder"
+    )
+))]
+compile_error!("feature `low-power` stops the timers");
+#[cfg(all(feature = "low-power", any(feature = "tm1637", feature = "hd44780")))]
+compile_error!("feature `low-power` ticks at 1 kHz, too coarse for the display bit timing");
+
+// Log with the uptime, `rtc` switches to the wall clock time
+#[cfg(all(feature = "defmt", not(feature = "rtc")))]
+defmt::timestamp!("{=u64:us}", embassy_time::Instant::now().as_micros());
 
 bind_interrupts!(struct Irqs {
-    ADC1_2 => adc::InterruptHandler<ADC1>;
+    #[cfg(all(family = "f1", not(any(feature = "analog", feature = "energy"))))]
+    ADC1_2 => embassy_stm32::adc::InterruptHandler<ADC1>;
+    #[cfg(any(feature = "analog", feature = "energy"))]
+    ADC1_2 => embassy_stm32::adc::InterruptHandler<ADC1>, embassy_stm32::adc::InterruptHandler<ADC2>;
+    #[cfg(family = "f0")]
+    ADC1 => embassy_stm32::adc::InterruptHandler<ADC1>;
+    #[cfg(family = "l0")]
+    ADC1_COMP => embassy_stm32::adc::InterruptHandler<ADC1>;
+    #[cfg(any(feature = "ble", feature = "iap"))]
+    USART1 => embassy_stm32::usart::BufferedInterruptHandler<USART1>;
+    #[cfg(any(feature = "bacnet", feature = "mbus"))]
+    USART3 => embassy_stm32::usart::BufferedInterruptHandler<USART3>;
+    #[cfg(feature = "usb")]
+    USB_LP_CAN1_RX0 => embassy_stm32::usb::InterruptHandler<USB>;
 });
 
 pub static SIGNAL_TEMPERATURE: Signal<CriticalSectionRawMutex, f32> = Signal::new();
-pub static SIGNAL_MOTOR_STATUS: Signal<CriticalSectionRawMutex, MotorStatus> = Signal::new();
+#[cfg(feature = "commands")]
+pub static MOTOR_COMMANDS: Channel<CriticalSectionRawMutex, MotorCommand, 4> = Channel::new();
+#[cfg(feature = "bootloader")]
+pub static SIGNAL_SAFE_STATE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
-#[embassy_executor::main]
+#[cfg_attr(not(feature = "low-power"), embassy_executor::main)]
+#[cfg_attr(
+    feature = "low-power",
+    embassy_executor::main(executor = "low_power::Executor")
+)]
 async fn main(spawner: Spawner) {
-    let p = embassy_stm32::init(Default::default());
+    #[cfg(feature = "stack")]
+    stack::paint();
+    #[cfg(feature = "bootloader")]
+    bootloader::check();
+    #[cfg(feature = "iap")]
+    iap::check();
+
+    let p = embassy_stm32::init(clock::config());
+    #[cfg(feature = "rtc")]
+    rtc::init();
+    #[cfg(feature = "low-power")]
+    low_power::init();
+    info!(
+        "heat-dooRS {} ({}) built {}",
+        version::VERSION,
+        version::GIT_HASH,
+        version::BUILD_TIME
+    );
+    info!("Features: {}", version::FEATURES);
+    info!("Serial: {=u32:08x}", identity::serial());
+
+    let pins = board::pins!(p);
+
+    if !image::verify(p.CRC) {
+        // Let an image on trial fall back to the previous one
+        #[cfg(feature = "iap")]
+        if iap::in_trial() {
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+        image::halt(pins.status_led).await;
+    }
+    #[cfg(feature = "power")]
+    power::init(&p.RCC);
 
     SIGNAL_TEMPERATURE.signal(0.0);
+    lockout::check();
+    #[cfg(feature = "factory")]
+    factory::load();
 
-    let led_pin = Output::new(p.PC13, Level::High, Speed::Low);
-    let motor_en_pin = Output::new(p.PA1, Level::Low, Speed::Low);
-    let motor_dir_pin = Output::new(p.PA2, Level::Low, Speed::Low);
+    let led_pin = Output::new(pins.status_led, board::status_led_level(false), Speed::Low);
+    let motor_en_pin = Output::new(pins.motor_enable, Level::Low, Speed::Low);
+    let motor_dir_pin = Output::new(pins.motor_direction, Level::Low, Speed::Low);
 
-    let motor = MotorControl::new(motor_dir_pin, motor_en_pin);
-    spawner.spawn(led_task(led_pin)).unwrap();
-    spawner.spawn(ntc(p.PA0, p.ADC1)).unwrap();
+    #[allow(unused_mut)]
+    let mut motor = MotorControl::new(motor_dir_pin, motor_en_pin);
+    #[allow(unused_mut)]
+    let mut ntc_pin = pins.ntc;
+    #[allow(unused_mut)]
+    let mut adc1 = p.ADC1;
+    spawner.spawn(led::led(led_pin)).unwrap();
+    #[cfg(feature = "self-test")]
+    self_test::run(ntc_pin.reborrow(), adc1.reborrow(), &mut motor).await;
+    spawner.spawn(ntc(ntc_pin, adc1)).unwrap();
     spawner.spawn(motor_control(motor)).unwrap();
-}
+    #[cfg(feature = "stack")]
+    spawner.spawn(stack::monitor()).unwrap();
+    #[cfg(feature = "manual")]
+    spawner.spawn(manual::override_timeout()).unwrap();
 
-#[embassy_executor::task]
-async fn led_task(mut led_pin: Output<'static>) {
-    info!("Starting LED task");
-    let mut current_status = MotorStatus::Off;
-    loop {
-        if let Some(status) = SIGNAL_MOTOR_STATUS.try_take() {
-            current_status = status;
-        }
-        match current_status {
-            MotorStatus::Closing => {
-                led_pin.set_low();
-            }
-            MotorStatus::Opening => {
-                led_pin.toggle();
-            }
-            MotorStatus::Off => {
-                led_pin.set_high();
-            }
+    // Before freeing the JTAG pins, the buzzer rewrites the remap register
+    spawner
+        .spawn(indicator::indicators(board::indicators!(p)))
+        .unwrap();
+
+    #[cfg(feature = "analog")]
+    spawner
+        .spawn(analog_input::analog_input(pins.analog, p.ADC2))
+        .unwrap();
+
+    #[cfg(feature = "pwm-input")]
+    spawner
+        .spawn(pwm_input::pwm_input(pins.pwm_input, p.TIM3))
+        .unwrap();
+
+    #[cfg(feature = "bacnet")]
+    spawner
+        .spawn(bacnet::bacnet(
+            p.USART3,
+            pins.usart3_tx,
+            pins.usart3_rx,
+            pins.bacnet_de,
+        ))
+        .unwrap();
+
+    #[cfg(feature = "ble")]
+    spawner
+        .spawn(ble::ble(p.USART1, pins.usart1_tx, pins.usart1_rx))
+        .unwrap();
+
+    #[cfg(feature = "iap")]
+    {
+        spawner
+            .spawn(iap::iap(p.USART1, pins.usart1_tx, pins.usart1_rx, p.FLASH))
+            .unwrap();
+        if iap::in_trial() {
+            spawner.spawn(iap::trial(p.IWDG)).unwrap();
         }
-        Timer::after(Duration::from_millis(100)).await;
     }
+
+    #[cfg(feature = "lora")]
+    {
+        use embassy_stm32::gpio::Pull;
+        use embassy_stm32::spi::{Config, Spi};
+        use embassy_stm32::time::Hertz;
+
+        let mut spi_config = Config::default();
+        spi_config.frequency = Hertz(1_000_000);
+        let spi = Spi::new_blocking(
+            pins.lora_spi,
+            pins.lora_sck,
+            pins.lora_mosi,
+            pins.lora_miso,
+            spi_config,
+        );
+        let nss = Output::new(pins.lora_nss, Level::High, Speed::Medium);
+        let reset = Output::new(pins.lora_reset, Level::High, Speed::Low);
+        let dio0 = board::exti_input(pins.lora_dio0, Pull::Down);
+        let radio = lora::Sx127x::new(spi, nss, reset, dio0);
+        spawner.spawn(lora::lora(radio)).unwrap();
+    }
+
+    #[cfg(feature = "nrf24")]
+    {
+        use embassy_stm32::gpio::Pull;
+        use embassy_stm32::spi::{Config, Spi};
+        use embassy_stm32::time::Hertz;
+
+        let mut spi_config = Config::default();
+        spi_config.frequency = Hertz(1_000_000);
+        let spi = Spi::new_blocking(
+            p.SPI2,
+            pins.spi2_sck,
+            pins.spi2_mosi,
+            pins.spi2_miso,
+            spi_config,
+        );
+        let csn = Output::new(pins.nrf24_csn, Level::High, Speed::Medium);
+        let ce = Output::new(pins.nrf24_ce, Level::Low, Speed::Low);
+        let irq = board::exti_input(pins.nrf24_irq, Pull::Up);
+        let radio = nrf24::Nrf24::new(spi, csn, ce, irq);
+        spawner.spawn(nrf24::nrf24(radio)).unwrap();
+    }
+
+    #[cfg(feature = "i2c-sensor")]
+    {
+        use embassy_stm32::i2c::{Config, I2c};
+
+        let i2c = I2c::new_blocking(p.I2C1, pins.i2c1_scl, pins.i2c1_sda, Config::default());
+        spawner
+            .spawn(i2c_sensor::i2c_sensor(i2c_sensor::Device::new(i2c)))
+            .unwrap();
+    }
+
+    #[cfg(feature = "spi-sensor")]
+    {
+        use embassy_stm32::spi::{Config, Spi};
+        use spi_sensor::Sensor;
+
+        let mut spi_config = Config::default();
+        spi_config.mode = spi_sensor::Device::MODE;
+        let spi = Spi::new_blocking(
+            p.SPI2,
+            pins.spi2_sck,
+            pins.spi2_mosi,
+            pins.spi2_miso,
+            spi_config,
+        );
+        let cs = Output::new(pins.spi_sensor_cs, Level::High, Speed::Medium);
+        spawner
+            .spawn(spi_sensor::spi_sensor(spi_sensor::Device::new(spi, cs)))
+            .unwrap();
+    }
+
+    #[cfg(feature = "sg-ready")]
+    {
+        use embassy_stm32::gpio::{Input, Pull};
+
+        chip::free_jtag_pins();
+        let input_1 = Input::new(pins.sg_ready1, Pull::Up);
+        let input_2 = Input::new(pins.sg_ready2, Pull::Up);
+        spawner.spawn(sg_ready::sg_ready(input_1, input_2)).unwrap();
+    }
+
+    #[cfg(feature = "fan")]
+    {
+        use embassy_stm32::gpio::OutputType;
+        use embassy_stm32::time::Hertz;
+        use embassy_stm32::timer::low_level::CountingMode;
+        use embassy_stm32::timer::simple_pwm::{PwmPin, SimplePwm};
+
+        let pwm = SimplePwm::new(
+            p.TIM3,
+            None,
+            None,
+            Some(PwmPin::new(pins.fan, OutputType::PushPull)),
+            None,
+            Hertz(fan::PWM_HZ),
+            CountingMode::EdgeAlignedUp,
+        );
+        spawner.spawn(fan::fan(pwm)).unwrap();
+    }
+
+    #[cfg(feature = "fan-tach")]
+    {
+        use embassy_stm32::gpio::Pull;
+
+        let tach = board::exti_input(pins.fan_tach, Pull::Up);
+        spawner.spawn(fan::tach(tach)).unwrap();
+    }
+
+    #[cfg(feature = "flow")]
+    {
+        use embassy_stm32::gpio::Pull;
+
+        let input = board::exti_input(pins.flow, Pull::Up);
+        spawner.spawn(flow::flow(input)).unwrap();
+    }
+
+    #[cfg(feature = "energy")]
+    spawner.spawn(energy::energy(pins.energy, p.ADC2)).unwrap();
+
+    #[cfg(feature = "boiler")]
+    {
+        let output = Output::new(pins.boiler, Level::Low, Speed::Low);
+        spawner.spawn(boiler::boiler(output)).unwrap();
+    }
+
+    #[cfg(feature = "pump")]
+    {
+        let relay = Output::new(pins.pump, Level::Low, Speed::Low);
+        spawner.spawn(pump::pump(relay)).unwrap();
+    }
+
+    #[cfg(feature = "stages")]
+    {
+        let stage_1 = Output::new(pins.stage1, Level::Low, Speed::Low);
+        let stage_2 = Output::new(pins.stage2, Level::Low, Speed::Low);
+        spawner.spawn(stages::stages(stage_1, stage_2)).unwrap();
+    }
+
+    #[cfg(feature = "window")]
+    {
+        use embassy_stm32::gpio::Pull;
+
+        let contact = board::exti_input(pins.window, Pull::Up);
+        spawner.spawn(window::window(contact)).unwrap();
+    }
+
+    #[cfg(feature = "buttons")]
+    {
+        use embassy_stm32::gpio::Pull;
+
+        chip::free_jtag_pins();
+        let up = board::exti_input(pins.button_up, Pull::Up);
+        let down = board::exti_input(pins.button_down, Pull::Up);
+        let mode = board::exti_input(pins.button_mode, Pull::Up);
+        spawner.spawn(buttons::buttons(up, down, mode)).unwrap();
+    }
+
+    #[cfg(feature = "encoder")]
+    {
+        use embassy_stm32::gpio::Pull;
+        use embassy_stm32::timer::qei::{Qei, QeiPin};
+
+        chip::free_jtag_pins();
+        let qei = Qei::new(
+            p.TIM3,
+            QeiPin::new(pins.encoder_a),
+            QeiPin::new(pins.encoder_b),
+        );
+        let button = board::exti_input(pins.encoder_button, Pull::Up);
+        spawner.spawn(encoder::encoder(qei, button)).unwrap();
+    }
+
+    #[cfg(feature = "usb")]
+    spawner
+        .spawn(usb::usb(p.USB, pins.usb_dp, pins.usb_dm))
+        .unwrap();
+
+    #[cfg(feature = "mbus")]
+    spawner
+        .spawn(mbus::mbus(p.USART3, pins.usart3_tx, pins.usart3_rx))
+        .unwrap();
 }
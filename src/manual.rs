@@ -0,0 +1,170 @@
+//! Manual override shared by the buttons, the rotary encoder and the shell.
+//!
+//! An override either fixes the valve, suspending the regulation, or fixes
+//! the setpoint. It ends after its duration, the setpoint from before the
+//! override is restored and the controller returns to automatic operation.
+//! Moving the valve by hand restarts the duration.
+//!
+//! In automatic operation the buttons and the encoder change the setpoint
+//! for good, in steps of [`SETPOINT_STEP`]. With a display their input goes
+//! through its menu instead.
+
+use embassy_executor::task;
+#[cfg(all(feature = "input", feature = "display"))]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::MOTOR_COMMANDS;
+use crate::fmt::{info, warn};
+use crate::indicator::{self, Condition};
+#[cfg(feature = "input")]
+use crate::lockout;
+use crate::motor_control::MotorCommand;
+#[cfg(feature = "input")]
+use crate::motor_control::MotorStatus;
+use crate::state;
+
+#[cfg(feature = "input")]
+pub const SETPOINT_STEP: f32 = 0.5;
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(2 * 60 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Input from the buttons or the rotary encoder.
+#[cfg(feature = "input")]
+pub enum InputEvent {
+    /// Up (positive) or down by a number of steps
+    Turn(i32),
+    /// Mode button or encoder push
+    Push,
+}
+
+#[cfg(all(feature = "input", feature = "display"))]
+pub static INPUT_EVENTS: Channel<CriticalSectionRawMutex, InputEvent, 4> = Channel::new();
+
+/// Pass a local input to the display menu, or act on it directly without a
+/// display.
+#[cfg(feature = "input")]
+pub fn input(event: InputEvent) {
+    // A push releases the safe mode first
+    if let (InputEvent::Push, Some(_)) = (&event, lockout::latched()) {
+        lockout::clear();
+        return;
+    }
+
+    #[cfg(feature = "display")]
+    if INPUT_EVENTS.try_send(event).is_err() {
+        warn!("Input: menu busy, input dropped");
+    }
+
+    #[cfg(not(feature = "display"))]
+    match event {
+        InputEvent::Turn(steps) => adjust(steps),
+        InputEvent::Push => toggle(),
+    }
+}
+
+/// Queue a motor command without waiting, user input is dropped while the
+/// motor control is busy with earlier commands.
+fn command(command: MotorCommand) {
+    if MOTOR_COMMANDS.try_send(command).is_err() {
+        warn!("Override: motor busy, input dropped");
+    }
+}
+
+/// Suspend the regulation and hold the valve, moving it to `position` %.
+pub fn fix_valve(position: Option<u8>, duration: Duration) {
+    info!("Override: valve for {} min", duration.as_secs() / 60);
+    state::update(|s| s.override_until = Some(Instant::now() + duration));
+    indicator::set(Condition::Override, true);
+    command(MotorCommand::Manual(true));
+    if let Some(position) = position {
+        command(MotorCommand::Position(position));
+    }
+}
+
+/// Use `setpoint` until the override ends, false if it is out of range.
+#[cfg(feature = "shell")]
+pub fn fix_setpoint(setpoint: f32, duration: Duration) -> bool {
+    let previous = state::get().setpoint;
+    if !state::set_setpoint(setpoint) {
+        return false;
+    }
+
+    info!(
+        "Override: setpoint {} for {} min",
+        setpoint,
+        duration.as_secs() / 60
+    );
+    state::update(|s| {
+        s.saved_setpoint.get_or_insert(previous);
+        s.override_until = Some(Instant::now() + duration);
+    });
+    indicator::set(Condition::Override, true);
+    true
+}
+
+/// Return to automatic operation with the setpoint from before the override.
+pub fn end() {
+    let state = state::get();
+    if state.override_until.is_none() && !state.manual {
+        return;
+    }
+
+    info!("Override: ended");
+    state::update(|s| {
+        if let Some(setpoint) = s.saved_setpoint.take() {
+            s.setpoint = setpoint;
+        }
+        s.override_until = None;
+    });
+    indicator::set(Condition::Override, false);
+    if state.manual {
+        command(MotorCommand::Manual(false));
+    }
+}
+
+/// Switch between manual and automatic operation.
+#[cfg(feature = "input")]
+pub fn toggle() {
+    if state::get().manual {
+        end();
+    } else {
+        fix_valve(None, DEFAULT_DURATION);
+    }
+}
+
+/// Raise (positive) or lower the setpoint by `steps`, or nudge the valve in
+/// that direction in manual mode.
+#[cfg(feature = "input")]
+pub fn adjust(steps: i32) {
+    let state = state::get();
+    if state.manual {
+        let direction = if steps > 0 {
+            MotorStatus::Opening
+        } else {
+            MotorStatus::Closing
+        };
+        state::update(|s| s.override_until = Some(Instant::now() + DEFAULT_DURATION));
+        command(MotorCommand::Nudge(direction));
+        return;
+    }
+
+    let setpoint = state.setpoint + steps as f32 * SETPOINT_STEP;
+    if state::set_setpoint(setpoint) {
+        info!("Setpoint {} set locally", setpoint);
+    }
+}
+
+/// End overrides once their duration has passed.
+#[task]
+pub async fn override_timeout() {
+    loop {
+        Timer::after(CHECK_INTERVAL).await;
+        if let Some(until) = state::get().override_until
+            && Instant::now() >= until
+        {
+            info!("Override: timed out");
+            end();
+        }
+    }
+}
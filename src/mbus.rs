@@ -0,0 +1,247 @@
+//! Wired M-Bus (EN 13757-2/3) slave behind a TSS721-class level shifter.
+//!
+//! Answers SND_NKE and REQ_UD2 addressed to the primary address (or the
+//! 0xFE broadcast) with a variable data RSP_UD telegram.
+
+use embassy_executor::task;
+use embassy_stm32::Peri;
+use embassy_stm32::peripherals::USART3;
+use embassy_stm32::usart::{BufferedUart, Config, Parity};
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::{Read, Write};
+
+use crate::board::{Usart3RxPin, Usart3TxPin};
+use crate::fmt::{info, warn};
+use crate::temperature::{CONTROL_SOURCE, TemperatureSource};
+use crate::{Irqs, identity, state};
+
+const BAUDRATE: u32 = 2400;
+const PRIMARY_ADDRESS: u8 = 1;
+const MANUFACTURER: &[u8; 3] = b"HDR";
+const VERSION: u8 = 1;
+const MEDIUM_HEAT_OUTLET: u8 = 0x04;
+const FRAME_TIMEOUT: Duration = Duration::from_millis(100);
+
+const ACK: u8 = 0xE5;
+const SHORT_START: u8 = 0x10;
+const LONG_START: u8 = 0x68;
+const STOP: u8 = 0x16;
+
+const C_SND_NKE: u8 = 0x40;
+const C_SND_UD: u8 = 0x53;
+const C_REQ_UD2: u8 = 0x5B;
+const C_RSP_UD: u8 = 0x08;
+const C_FCB: u8 = 0x20;
+
+const CI_APPLICATION_RESET: u8 = 0x50;
+const CI_RSP_VARIABLE: u8 = 0x72;
+
+const ADDRESS_BROADCAST_REPLY: u8 = 0xFE;
+
+// DIF/VIF of the data records
+const DIF_INT16: u8 = 0x02;
+const DIF_INT8: u8 = 0x01;
+#[cfg(feature = "energy")]
+const DIF_INT32: u8 = 0x04;
+/// Regulation temperature, the flow temperature only when it is the supply
+/// NTC and a plain external temperature from any other sensor, in 0.01 °C
+const VIF_TEMPERATURE: u8 = if matches!(CONTROL_SOURCE, TemperatureSource::Ntc) {
+    0x59
+} else {
+    0x65
+};
+#[cfg(feature = "energy")]
+const VIF_RETURN_TEMPERATURE: u8 = 0x5D; // 0.01 °C
+#[cfg(feature = "energy")]
+const VIF_ENERGY: u8 = 0x03; // Wh
+#[cfg(feature = "energy")]
+const VIF_POWER: u8 = 0x2B; // W
+const VIF_MANUFACTURER_SPECIFIC: u8 = 0x7F; // Valve position in %
+
+enum Request {
+    Reset,
+    ApplicationReset,
+    ClassTwoData,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn manufacturer_code() -> u16 {
+    MANUFACTURER
+        .iter()
+        .fold(0u16, |code, c| (code << 5) | (*c - b'@') as u16)
+}
+
+/// Secondary address, the last 8 decimal digits of the device serial in BCD.
+fn identification() -> u32 {
+    let mut serial = identity::serial() % 100_000_000;
+    let mut bcd = 0;
+    for digit in 0..8 {
+        bcd |= (serial % 10) << (4 * digit);
+        serial /= 10;
+    }
+    bcd
+}
+
+fn is_our_address(address: u8) -> bool {
+    address == PRIMARY_ADDRESS || address == ADDRESS_BROADCAST_REPLY
+}
+
+async fn read_request(uart: &mut BufferedUart<'_>) -> Option<Request> {
+    let mut start = [0u8; 1];
+    uart.read_exact(&mut start).await.ok()?;
+
+    match start[0] {
+        SHORT_START => {
+            let mut frame = [0u8; 4];
+            with_timeout(FRAME_TIMEOUT, uart.read_exact(&mut frame))
+                .await
+                .ok()?
+                .ok()?;
+            let [control, address, sum, stop] = frame;
+            if stop != STOP || checksum(&[control, address]) != sum {
+                warn!("M-Bus: invalid short frame");
+                return None;
+            }
+            if !is_our_address(address) {
+                return None;
+            }
+
+            match control & !C_FCB {
+                C_SND_NKE => Some(Request::Reset),
+                C_REQ_UD2 => Some(Request::ClassTwoData),
+                _ => None,
+            }
+        }
+        LONG_START => {
+            let mut header = [0u8; 3];
+            with_timeout(FRAME_TIMEOUT, uart.read_exact(&mut header))
+                .await
+                .ok()?
+                .ok()?;
+            let len = header[0] as usize;
+            if header[1] != header[0] || header[2] != LONG_START || len < 3 {
+                warn!("M-Bus: invalid long frame header");
+                return None;
+            }
+
+            let mut frame = [0u8; 255 + 2];
+            with_timeout(FRAME_TIMEOUT, uart.read_exact(&mut frame[..len + 2]))
+                .await
+                .ok()?
+                .ok()?;
+            if frame[len + 1] != STOP || checksum(&frame[..len]) != frame[len] {
+                warn!("M-Bus: invalid long frame");
+                return None;
+            }
+
+            let (control, address, ci) = (frame[0], frame[1], frame[2]);
+            if !is_our_address(address) || control & !C_FCB != C_SND_UD {
+                return None;
+            }
+
+            match ci {
+                CI_APPLICATION_RESET => Some(Request::ApplicationReset),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Build the RSP_UD telegram, returns its length
+fn build_response(access_number: u8, telegram: &mut [u8; 64]) -> usize {
+    let state = state::get();
+    let mut data = [0u8; 48];
+    let mut len = 0;
+    let mut push = |bytes: &[u8]| {
+        data[len..len + bytes.len()].copy_from_slice(bytes);
+        len += bytes.len();
+    };
+
+    // Fixed data header
+    push(&[C_RSP_UD, PRIMARY_ADDRESS, CI_RSP_VARIABLE]);
+    push(&identification().to_le_bytes());
+    push(&manufacturer_code().to_le_bytes());
+    push(&[VERSION, MEDIUM_HEAT_OUTLET, access_number]);
+    // Status byte reports a temporary error while no temperature is available
+    let status = if state.temperature.is_nan() {
+        0x08
+    } else {
+        0x00
+    };
+    push(&[status, 0x00, 0x00]);
+
+    // Data records
+    if !state.temperature.is_nan() {
+        let temperature = (state.temperature * 100.0) as i16;
+        push(&[DIF_INT16, VIF_TEMPERATURE]);
+        push(&temperature.to_le_bytes());
+    }
+    #[cfg(feature = "energy")]
+    {
+        if !state.return_temperature.is_nan() {
+            let temperature = (state.return_temperature * 100.0) as i16;
+            push(&[DIF_INT16, VIF_RETURN_TEMPERATURE]);
+            push(&temperature.to_le_bytes());
+        }
+        push(&[DIF_INT32, VIF_ENERGY]);
+        push(&state.heat_energy.to_le_bytes());
+        push(&[DIF_INT32, VIF_POWER]);
+        push(&state.heat_power.to_le_bytes());
+    }
+    push(&[DIF_INT8, VIF_MANUFACTURER_SPECIFIC, state.valve_position]);
+
+    telegram[..4].copy_from_slice(&[LONG_START, len as u8, len as u8, LONG_START]);
+    telegram[4..4 + len].copy_from_slice(&data[..len]);
+    telegram[4 + len] = checksum(&data[..len]);
+    telegram[5 + len] = STOP;
+    len + 6
+}
+
+#[task]
+pub async fn mbus(
+    usart: Peri<'static, USART3>,
+    tx_pin: Peri<'static, Usart3TxPin>,
+    rx_pin: Peri<'static, Usart3RxPin>,
+) {
+    let mut tx_buffer = [0u8; 64];
+    let mut rx_buffer = [0u8; 64];
+    let mut config = Config::default();
+    config.baudrate = BAUDRATE;
+    config.parity = Parity::ParityEven;
+
+    let mut uart = BufferedUart::new(
+        usart,
+        rx_pin,
+        tx_pin,
+        &mut tx_buffer,
+        &mut rx_buffer,
+        Irqs,
+        config,
+    )
+    .unwrap();
+
+    info!("Starting M-Bus slave, primary address {}", PRIMARY_ADDRESS);
+
+    let mut access_number: u8 = 0;
+    let mut telegram = [0u8; 64];
+
+    loop {
+        let result = match read_request(&mut uart).await {
+            Some(Request::Reset) | Some(Request::ApplicationReset) => uart.write_all(&[ACK]).await,
+            Some(Request::ClassTwoData) => {
+                let len = build_response(access_number, &mut telegram);
+                access_number = access_number.wrapping_add(1);
+                uart.write_all(&telegram[..len]).await
+            }
+            None => continue,
+        };
+
+        if result.is_err() {
+            warn!("M-Bus: transmit error");
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Display menu driven by the buttons or the rotary encoder.
+//!
+//! The status screen works like the inputs without a display: turning
+//! changes the setpoint or moves the valve in manual mode. Pushing opens the
+//! menu, which closes again without input for [`MENU_TIMEOUT`].
+
+use embassy_time::{Duration, Instant};
+
+use crate::manual::{self, InputEvent};
+use crate::state;
+
+const MENU_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Screen {
+    Status,
+    /// Menu with the selected item
+    Menu(usize),
+}
+
+#[derive(Clone, Copy)]
+pub enum Item {
+    ManualMode,
+    EndOverride,
+    Back,
+}
+
+pub const ITEMS: [Item; 3] = [Item::ManualMode, Item::EndOverride, Item::Back];
+
+impl Item {
+    pub fn label(self) -> &'static str {
+        match self {
+            Item::ManualMode if state::get().manual => "AUTO MODE",
+            Item::ManualMode => "MANUAL MODE",
+            Item::EndOverride => "END OVERRIDE",
+            Item::Back => "BACK",
+        }
+    }
+}
+
+pub struct Menu {
+    screen: Screen,
+    last_input: Instant,
+}
+
+impl Menu {
+    pub const fn new() -> Self {
+        Self {
+            screen: Screen::Status,
+            last_input: Instant::MIN,
+        }
+    }
+
+    pub fn handle(&mut self, event: InputEvent) {
+        self.last_input = Instant::now();
+        self.screen = match (self.screen, event) {
+            (Screen::Status, InputEvent::Turn(steps)) => {
+                manual::adjust(steps);
+                Screen::Status
+            }
+            (Screen::Status, InputEvent::Push) => Screen::Menu(0),
+            (Screen::Menu(selected), InputEvent::Turn(steps)) => {
+                Screen::Menu((selected as i32 + steps).rem_euclid(ITEMS.len() as i32) as usize)
+            }
+            (Screen::Menu(selected), InputEvent::Push) => {
+                match ITEMS[selected] {
+                    Item::ManualMode => manual::toggle(),
+                    Item::EndOverride => manual::end(),
+                    Item::Back => {}
+                }
+                Screen::Status
+            }
+        };
+    }
+
+    /// Screen to show, back to the status once the menu timed out.
+    pub fn screen(&mut self) -> Screen {
+        if self.screen != Screen::Status && self.last_input.elapsed() >= MENU_TIMEOUT {
+            self.screen = Screen::Status;
+        }
+        self.screen
+    }
+}
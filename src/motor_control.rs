@@ -1,28 +1,46 @@
-use defmt::info;
 use embassy_executor::task;
+#[cfg(feature = "commands")]
+use embassy_futures::select::{Either, select};
 use embassy_stm32::gpio::Output;
-use embassy_time::{Instant, Timer};
+use embassy_time::{Duration, Instant, Timer};
+pub use heat_control::MotorStatus;
+pub use heat_control::regulation::Action;
+use heat_control::regulation::{Regulator, Step};
+use heat_control::travel::Travel;
 use micromath::F32Ext;
 
-use crate::SIGNAL_MOTOR_STATUS;
+#[cfg(feature = "commands")]
+use crate::MOTOR_COMMANDS;
+#[cfg(feature = "bootloader")]
+use crate::SIGNAL_SAFE_STATE;
 use crate::SIGNAL_TEMPERATURE;
-const MAX_TEMPERATURE: f32 = 55.0;
-const MAX_MOVE_TIME: u64 = 13;
-const STEP_MOVE_TIME: u64 = 1;
-const TEMP_HYSTERESIS: f32 = 5.0;
-const WAIT_TIME_S: u64 = 120;
-
-#[derive(PartialEq, Clone, Copy)]
-pub enum MotorStatus {
-    Off,
-    Opening,
-    Closing,
-}
+use crate::actuators;
+use crate::config::{
+    ACTION, FAILSAFE_POSITION, LOCKOUT_SETTLE_S, MAX_MOVE_TIME, OVERTEMPERATURE_MARGIN,
+    STALE_TIMEOUT_S, STEP_MOVE_TIME, WAIT_TIME_S,
+};
+use crate::fmt::{info, warn};
+use crate::indicator::{self, Condition};
+use crate::lockout::{self, Reason};
+use crate::log::log;
+use crate::state;
+use crate::temperature::CONTROL_SOURCE;
 
-pub enum HeatingStatus {
-    Off,
-    Heating,
-    Cooling,
+/// Requests sent to the motor control task by the user interfaces.
+#[cfg(feature = "commands")]
+pub enum MotorCommand {
+    /// Stop the motor and keep it stopped until reset
+    #[cfg(feature = "bootloader")]
+    SafeState,
+    /// Suspend the regulation and leave the valve to the user, or resume
+    #[cfg(feature = "manual")]
+    Manual(bool),
+    /// Move the valve by one step while in manual mode
+    #[cfg(feature = "input")]
+    Nudge(MotorStatus),
+    /// Move the valve to a position in % while in manual mode
+    #[cfg(feature = "manual")]
+    Position(u8),
 }
 
 pub struct MotorControl {
@@ -30,10 +48,16 @@ pub struct MotorControl {
     enable_pin: Output<'static>,
     status: MotorStatus,
     move_start: Option<Instant>,
-    total_movement_time: u64, // Movement time in any direction
-    heating_status: HeatingStatus,
-    last_move_status: MotorStatus,
-    last_temp: f32,
+    travel: Travel,
+    regulator: Regulator,
+    paused: bool,
+    manual: bool,
+    last_reading: Instant,
+    failsafe: bool,
+    /// Since when the valve is estimated fully closed
+    closed_since: Option<Instant>,
+    /// Overtemperature with a settled closed valve, counted as a failure
+    failure: bool,
 }
 
 impl MotorControl {
@@ -43,27 +67,44 @@ impl MotorControl {
             enable_pin,
             status: MotorStatus::Off,
             move_start: None,
-            total_movement_time: 0,
-            heating_status: HeatingStatus::Off,
-            last_move_status: MotorStatus::Off,
-            last_temp: 0.0,
+            travel: Travel::new(MAX_MOVE_TIME),
+            regulator: Regulator::new(),
+            paused: false,
+            manual: false,
+            last_reading: Instant::now(),
+            failsafe: false,
+            closed_since: None,
+            failure: false,
         }
     }
 
     pub async fn move_motor(&mut self, direction: MotorStatus, duration: u64) -> bool {
+        if lockout::latched().is_some() {
+            return false;
+        }
         if !self.can_move(direction) {
-            info!("Max movement time reached, cannot move motor further");
+            log!(
+                Motor,
+                info,
+                "Max movement time reached, cannot move motor further"
+            );
             self.stop();
             return false;
         }
 
+        if direction == MotorStatus::Opening && state::condensation_risk() {
+            log!(Motor, info, "Valve held, pipe close to the dew point");
+            return false;
+        }
+
+        let _running = actuators::start().await;
         match direction {
             MotorStatus::Opening => {
-                info!("Opening motor for {}s", duration);
+                log!(Motor, info, "Opening motor for {}s", duration);
                 self.open();
             }
             MotorStatus::Closing => {
-                info!("Closing motor for {}s", duration);
+                log!(Motor, info, "Closing motor for {}s", duration);
                 self.close();
             }
             _ => return false,
@@ -74,145 +115,281 @@ impl MotorControl {
         true
     }
 
-    pub async fn step_move(&mut self, direction: MotorStatus, temp: f32) -> bool {
-        let action = match direction {
-            MotorStatus::Opening => "Opening",
-            MotorStatus::Closing => "Closing",
+    pub async fn step_move(&mut self, step: Step, temp: f32) -> bool {
+        let (action, direction) = match step {
+            Step::Open => ("Opening", MotorStatus::Opening),
+            Step::Close => ("Closing", MotorStatus::Closing),
             _ => return false,
         };
 
-        info!(
+        log!(
+            Motor,
+            info,
             "{} motor for one step, CUR: {}, LAST: {}",
-            action, temp, self.last_temp
+            action,
+            temp,
+            self.regulator.last_temp()
         );
         let success = self.move_motor(direction, STEP_MOVE_TIME).await;
         if success {
-            self.last_temp = temp;
+            self.regulator.moved(step, temp);
         }
         success
     }
 
     pub fn stop(&mut self) {
-        if self.status != MotorStatus::Off {
-            self.last_move_status = self.status;
-
-            if let Some(elapsed) = self.elapsed_s() {
-                self.total_movement_time += elapsed;
+        if let Some(start) = self.move_start {
+            self.travel.stop(self.status, start.elapsed().as_millis());
+            let position = self.travel.position();
+            state::update(|s| s.valve_position = position);
+            if position > 0 {
+                self.closed_since = None;
+            } else if self.closed_since.is_none() {
+                self.closed_since = Some(Instant::now());
             }
         }
 
         self.move_start = None;
         self.enable_pin.set_low();
         self.direction_pin.set_low();
-        self.status = MotorStatus::Off;
-        SIGNAL_MOTOR_STATUS.signal(MotorStatus::Off);
+        self.set_status(MotorStatus::Off);
     }
 
     pub fn close(&mut self) {
-        if self.last_move_status != MotorStatus::Closing {
-            self.total_movement_time = 0;
-        }
-
+        self.travel.start(MotorStatus::Closing);
         self.move_start = Some(Instant::now());
 
         self.enable_pin.set_high();
         self.direction_pin.set_low();
-        self.status = MotorStatus::Closing;
-        SIGNAL_MOTOR_STATUS.signal(MotorStatus::Closing);
+        self.set_status(MotorStatus::Closing);
     }
 
     pub fn open(&mut self) {
-        if self.last_move_status != MotorStatus::Opening {
-            self.total_movement_time = 0;
-        }
-
+        self.travel.start(MotorStatus::Opening);
         self.move_start = Some(Instant::now());
 
         self.enable_pin.set_high();
         self.direction_pin.set_high();
-        self.status = MotorStatus::Opening;
-        SIGNAL_MOTOR_STATUS.signal(MotorStatus::Opening);
+        self.set_status(MotorStatus::Opening);
     }
 
     pub fn can_move(&self, direction: MotorStatus) -> bool {
-        let total_time = if direction != self.last_move_status {
-            0
-        } else {
-            self.total_movement_time
-        };
+        self.travel.can_move(direction)
+    }
+
+    fn set_status(&mut self, status: MotorStatus) {
+        self.status = status;
+        indicator::motor(status);
+        state::update(|s| s.motor_status = status);
+    }
+
+    /// Close the valve once when pausing, returns whether the heating is paused.
+    pub async fn pause(&mut self, paused: bool) -> bool {
+        if paused && !self.paused {
+            log!(Motor, info, "Closing motor for pause");
+            self.move_motor(MotorStatus::Closing, MAX_MOVE_TIME).await;
+            // Start over from a fully open valve when resuming
+            self.regulator.restart();
+        }
+        self.paused = paused;
+        paused
+    }
+
+    /// Switch between manual and automatic operation.
+    #[cfg(feature = "manual")]
+    fn set_manual(&mut self, manual: bool) {
+        if manual == self.manual {
+            return;
+        }
+
+        log!(
+            Motor,
+            info,
+            "Manual mode {}",
+            if manual { "on" } else { "off" }
+        );
+        self.stop();
+        self.manual = manual;
+        // Restart the regulation from a fully open valve
+        self.regulator.restart();
+        state::update(|s| s.manual = manual);
+    }
+
+    /// Hold the valve at [`FAILSAFE_POSITION`] while no readings arrive.
+    async fn failsafe(&mut self) {
+        if !self.failsafe {
+            warn!(
+                "No temperature for {}s, holding the valve at {}%",
+                self.last_reading.elapsed().as_secs(),
+                FAILSAFE_POSITION
+            );
+            self.failsafe = true;
+            indicator::set(Condition::SensorFault, true);
+        }
+        self.move_to(FAILSAFE_POSITION).await;
+    }
+
+    /// Note a new reading, leaving the failsafe if it was active.
+    fn reading(&mut self) {
+        self.last_reading = Instant::now();
+        if self.failsafe {
+            info!("Temperature readings resumed");
+            self.failsafe = false;
+            // Restart the regulation from a fully open valve
+            self.regulator.restart();
+        }
+    }
 
-        total_time < MAX_MOVE_TIME
+    /// Whether the temperature stayed above the overtemperature margin with
+    /// the valve closed for [`LOCKOUT_SETTLE_S`], so the valve cannot have
+    /// closed. Counted once until the temperature drops or the valve opens.
+    fn actuator_failed(&mut self, overtemperature: bool) -> bool {
+        let settled = self
+            .closed_since
+            .is_some_and(|since| since.elapsed() >= Duration::from_secs(LOCKOUT_SETTLE_S));
+        let failure = overtemperature && settled;
+        let new = failure && !self.failure;
+        self.failure = failure;
+        new
     }
 
-    fn elapsed_s(&self) -> Option<u64> {
-        self.move_start.map(|t| t.elapsed().as_secs())
+    /// Drive the valve to `demand` % using the estimated position.
+    pub async fn move_to(&mut self, demand: u8) {
+        // 32-bit arithmetic is plenty for the few seconds of travel
+        let position_ms = self.travel.position_ms() as u32;
+        let target_ms = u32::from(demand.min(100)) * MAX_MOVE_TIME as u32 * 10;
+        let (direction, distance_ms) = if target_ms > position_ms {
+            (MotorStatus::Opening, target_ms - position_ms)
+        } else {
+            (MotorStatus::Closing, position_ms - target_ms)
+        };
+
+        // Run fully against the end stop once when asked for either end
+        let duration = match demand {
+            0 | 100.. if self.can_move(direction) => MAX_MOVE_TIME,
+            _ => u64::from((distance_ms + 500) / 1000),
+        };
+        if duration >= STEP_MOVE_TIME {
+            log!(Motor, info, "Moving valve to {}%", demand);
+            self.move_motor(direction, duration).await;
+        }
     }
 }
 
 #[task]
 pub async fn motor_control(mut motor_control: MotorControl) {
     loop {
-        if let Some(temp) = SIGNAL_TEMPERATURE.try_take() {
+        if let Some(reason) = lockout::latched() {
+            log!(Motor, warn, "Safe mode, motor off: {}", reason.name());
+            motor_control.stop();
+        } else if motor_control.pause(state::heating_paused()).await {
+            log!(Motor, info, "Heating paused");
+        } else if state::condensation_risk() {
+            log!(
+                Motor,
+                info,
+                "Closing motor one step, pipe close to the dew point"
+            );
+            motor_control
+                .move_motor(MotorStatus::Closing, STEP_MOVE_TIME)
+                .await;
+        } else if motor_control.manual {
+            log!(Motor, info, "Manual mode, regulation suspended");
+        } else if let Some(demand) = state::valve_demand() {
+            motor_control.move_to(demand).await;
+        } else if let Some(temp) = SIGNAL_TEMPERATURE.try_take() {
+            motor_control.reading();
             let temp = (temp * 10.0).round() / 10.0;
-            info!("Temperature: {}", temp);
-            match motor_control.heating_status {
-                HeatingStatus::Off => {
+            let setpoint = state::get().target_setpoint();
+            let hysteresis = CONTROL_SOURCE.hysteresis();
+            log!(Motor, info, "Temperature: {}, setpoint: {}", temp, setpoint);
+            let temp = ACTION.regulated(temp);
+            let setpoint = ACTION.regulated(setpoint);
+            let overtemperature = temp > setpoint + OVERTEMPERATURE_MARGIN;
+            if motor_control.actuator_failed(overtemperature) {
+                lockout::trip(Reason::Overtemperature);
+            }
+            let step = motor_control.regulator.step(temp, setpoint, hysteresis);
+            match step {
+                Step::Calibrate => {
                     // Initial setup - fully open the motor
-                    info!("Opening at beginning");
+                    log!(Motor, info, "Opening at beginning");
+                    indicator::set(Condition::Calibration, true);
                     if motor_control
                         .move_motor(MotorStatus::Opening, MAX_MOVE_TIME)
                         .await
                     {
-                        info!("Motor fully open at beginning");
-                        motor_control.heating_status = HeatingStatus::Heating;
+                        log!(Motor, info, "Motor fully open at beginning");
+                        motor_control.regulator.moved(step, temp);
                     }
+                    indicator::set(Condition::Calibration, false);
                 }
-
-                HeatingStatus::Cooling => {
-                    if temp < MAX_TEMPERATURE - TEMP_HYSTERESIS {
-                        info!("Motor cool enough, starting heating");
-                        motor_control.heating_status = HeatingStatus::Heating;
-                        if motor_control
-                            .move_motor(MotorStatus::Opening, MAX_MOVE_TIME)
-                            .await
-                        {
-                            info!("Motor fully open after cool down");
-                        }
-                    } else {
-                        info!("Cooling ...");
+                Step::Resume => {
+                    log!(Motor, info, "Motor cool enough, starting heating");
+                    if motor_control
+                        .move_motor(MotorStatus::Opening, MAX_MOVE_TIME)
+                        .await
+                    {
+                        log!(Motor, info, "Motor fully open after cool down");
                     }
-
-                    motor_control.last_temp = temp
                 }
-
-                HeatingStatus::Heating => {
-                    if temp > MAX_TEMPERATURE {
-                        // Overheating - fully close motor
-                        info!("Closing motor to overheating");
-                        if motor_control
-                            .move_motor(MotorStatus::Closing, MAX_MOVE_TIME)
-                            .await
-                        {
-                            info!("Motor fully close due to overheating");
-                        }
-                        motor_control.heating_status = HeatingStatus::Cooling;
-                    } else if temp < MAX_TEMPERATURE - TEMP_HYSTERESIS {
-                        info!("Too low temperature during heating, keep open");
-                    } else {
-                        // Fine-tune motor position based on temperature changes
-                        if temp > motor_control.last_temp {
-                            motor_control.step_move(MotorStatus::Closing, temp).await;
-                        } else if temp < motor_control.last_temp {
-                            motor_control.step_move(MotorStatus::Opening, temp).await;
-                        }
+                Step::Cooling => log!(Motor, info, "Cooling ..."),
+                Step::Overheat => {
+                    // Overheating - fully close motor
+                    log!(Motor, info, "Closing motor to overheating");
+                    if motor_control
+                        .move_motor(MotorStatus::Closing, MAX_MOVE_TIME)
+                        .await
+                    {
+                        log!(Motor, info, "Motor fully close due to overheating");
                     }
                 }
+                Step::TooLow => {
+                    log!(Motor, info, "Too low temperature during heating, keep open")
+                }
+                // Fine-tune motor position based on temperature changes
+                Step::Close | Step::Open => {
+                    motor_control.step_move(step, temp).await;
+                }
+                Step::Hold => {}
             }
+        } else if motor_control.last_reading.elapsed() >= Duration::from_secs(STALE_TIMEOUT_S) {
+            motor_control.failsafe().await;
         } else {
             // No new temperature, keep motor closed
             motor_control.stop();
         }
 
+        #[cfg(not(feature = "commands"))]
         Timer::after_secs(WAIT_TIME_S).await;
+
+        #[cfg(feature = "commands")]
+        if let Either::Second(command) =
+            select(Timer::after_secs(WAIT_TIME_S), MOTOR_COMMANDS.receive()).await
+        {
+            match command {
+                #[cfg(feature = "bootloader")]
+                MotorCommand::SafeState => {
+                    log!(Motor, info, "Motor in safe state");
+                    motor_control.stop();
+                    SIGNAL_SAFE_STATE.signal(());
+                    core::future::pending::<()>().await;
+                }
+                #[cfg(feature = "manual")]
+                MotorCommand::Manual(manual) => motor_control.set_manual(manual),
+                #[cfg(feature = "input")]
+                MotorCommand::Nudge(direction) => {
+                    if motor_control.manual {
+                        motor_control.move_motor(direction, STEP_MOVE_TIME).await;
+                    }
+                }
+                #[cfg(feature = "manual")]
+                MotorCommand::Position(position) => {
+                    if motor_control.manual {
+                        motor_control.move_to(position).await;
+                    }
+                }
+            }
+        }
     }
 }
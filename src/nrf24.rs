@@ -0,0 +1,148 @@
+//! Remote room sensor received through an nRF24L01(+) radio.
+//!
+//! The sensor node sends a fixed size payload on pipe 0:
+//! `node id, temperature (0.1 °C, i16 LE), battery level (%)`
+
+use embassy_executor::task;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Output;
+use embassy_stm32::mode::Blocking;
+use embassy_stm32::spi::Spi;
+use embassy_time::Timer;
+
+use crate::fmt::{info, warn};
+use crate::indicator::{self, Condition};
+use crate::temperature::{self, TemperatureSource};
+
+const CHANNEL: u8 = 76;
+const ADDRESS: [u8; 5] = *b"HDRS1";
+const PAYLOAD_SIZE: usize = 4;
+
+// Commands
+const R_REGISTER: u8 = 0x00;
+const W_REGISTER: u8 = 0x20;
+const R_RX_PAYLOAD: u8 = 0x61;
+const FLUSH_RX: u8 = 0xE2;
+
+// Registers
+const REG_CONFIG: u8 = 0x00;
+const REG_EN_AA: u8 = 0x01;
+const REG_EN_RXADDR: u8 = 0x02;
+const REG_SETUP_AW: u8 = 0x03;
+const REG_RF_CH: u8 = 0x05;
+const REG_RF_SETUP: u8 = 0x06;
+const REG_STATUS: u8 = 0x07;
+const REG_RX_ADDR_P0: u8 = 0x0A;
+const REG_RX_PW_P0: u8 = 0x11;
+const REG_FIFO_STATUS: u8 = 0x17;
+
+const CONFIG_EN_CRC: u8 = 0x08;
+const CONFIG_CRCO: u8 = 0x04;
+const CONFIG_PWR_UP: u8 = 0x02;
+const CONFIG_PRIM_RX: u8 = 0x01;
+const RF_SETUP_250KBPS: u8 = 0x20;
+const RF_SETUP_0DBM: u8 = 0x06;
+const STATUS_RX_DR: u8 = 0x40;
+const FIFO_RX_EMPTY: u8 = 0x01;
+
+pub struct Nrf24 {
+    spi: Spi<'static, Blocking>,
+    csn: Output<'static>,
+    ce: Output<'static>,
+    irq: ExtiInput<'static>,
+}
+
+impl Nrf24 {
+    pub fn new(
+        spi: Spi<'static, Blocking>,
+        csn: Output<'static>,
+        ce: Output<'static>,
+        irq: ExtiInput<'static>,
+    ) -> Self {
+        Self { spi, csn, ce, irq }
+    }
+
+    fn command(&mut self, command: u8, data: &mut [u8]) -> u8 {
+        let mut status = [command];
+        self.csn.set_low();
+        let _ = self.spi.blocking_transfer_in_place(&mut status);
+        let _ = self.spi.blocking_transfer_in_place(data);
+        self.csn.set_high();
+        status[0]
+    }
+
+    fn read_register(&mut self, register: u8) -> u8 {
+        let mut value = [0xFF];
+        self.command(R_REGISTER | register, &mut value);
+        value[0]
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) {
+        self.command(W_REGISTER | register, &mut [value]);
+    }
+
+    pub async fn init(&mut self) -> bool {
+        self.ce.set_low();
+        Timer::after_millis(100).await; // Power on reset
+
+        self.write_register(REG_RF_CH, CHANNEL);
+        if self.read_register(REG_RF_CH) != CHANNEL {
+            warn!("nRF24: radio not responding");
+            return false;
+        }
+
+        self.write_register(REG_SETUP_AW, 0x03); // 5 byte addresses
+        let mut address = ADDRESS;
+        self.command(W_REGISTER | REG_RX_ADDR_P0, &mut address);
+        self.write_register(REG_EN_RXADDR, 0x01);
+        self.write_register(REG_EN_AA, 0x01);
+        self.write_register(REG_RX_PW_P0, PAYLOAD_SIZE as u8);
+        self.write_register(REG_RF_SETUP, RF_SETUP_250KBPS | RF_SETUP_0DBM);
+        self.command(FLUSH_RX, &mut []);
+        self.write_register(REG_STATUS, STATUS_RX_DR);
+        self.write_register(
+            REG_CONFIG,
+            CONFIG_EN_CRC | CONFIG_CRCO | CONFIG_PWR_UP | CONFIG_PRIM_RX,
+        );
+        Timer::after_millis(2).await; // Crystal start-up
+
+        self.ce.set_high();
+        true
+    }
+
+    /// Wait for the next payload received on pipe 0.
+    pub async fn receive(&mut self) -> [u8; PAYLOAD_SIZE] {
+        loop {
+            if self.read_register(REG_FIFO_STATUS) & FIFO_RX_EMPTY == 0 {
+                let mut payload = [0xFF; PAYLOAD_SIZE];
+                self.command(R_RX_PAYLOAD, &mut payload);
+                self.write_register(REG_STATUS, STATUS_RX_DR);
+                return payload;
+            }
+
+            self.irq.wait_for_low().await;
+            self.write_register(REG_STATUS, STATUS_RX_DR);
+        }
+    }
+}
+
+#[task]
+pub async fn nrf24(mut radio: Nrf24) {
+    if !radio.init().await {
+        warn!("nRF24: remote sensor disabled");
+        indicator::set(Condition::RadioFault, true);
+        return;
+    }
+
+    info!("Starting nRF24 remote sensor receiver");
+
+    loop {
+        let [node, low, high, battery] = radio.receive().await;
+        let temperature = i16::from_le_bytes([low, high]) as f32 / 10.0;
+        info!(
+            "nRF24: node {} temperature {} battery {}%",
+            node, temperature, battery
+        );
+        temperature::publish(TemperatureSource::Remote, temperature);
+    }
+}
@@ -1,60 +1,56 @@
-use defmt::trace;
 use embassy_executor::task;
 use embassy_stm32::Peri;
-use embassy_stm32::adc::{Adc, SampleTime};
-use embassy_stm32::peripherals::{ADC1, PA0};
+use embassy_stm32::peripherals::ADC1;
 use embassy_time::Timer;
-use micromath::F32Ext;
-
-use crate::SIGNAL_TEMPERATURE;
-
-const ADC_MAX: f32 = 4095.0;
-const R_PULL: f32 = 10_000.0; // pull-down 10k
-const R_NTC_25: f32 = 10_000.0; // 10k @ 25°C
-const BETA: f32 = 5800.0;
-const T0: f32 = 298.15; // 25°C v K
+use heat_control::ntc::Divider;
+
+use crate::board::NtcPin;
+use crate::chip;
+use crate::config::{NTC_BETA, NTC_R_PULL, NTC_R25};
+#[cfg(feature = "factory")]
+use crate::factory;
+use crate::log::log;
+use crate::temperature::{self, TemperatureSource};
+
+const DIVIDER: Divider = Divider {
+    beta: NTC_BETA,
+    r25: NTC_R25,
+    r_pull: NTC_R_PULL,
+};
 
 pub fn adc_to_temperature_c(adc: u16) -> f32 {
-    if adc == 0 || adc as f32 >= ADC_MAX {
-        return f32::NAN;
-    }
-
-    let adc_f = adc as f32;
-
-    // NTC to VCC, pull-down to GND
-    let r_ntc = R_PULL * (ADC_MAX - adc_f) / adc_f;
-
-    let inv_t = (1.0 / T0) + (1.0 / BETA) * (r_ntc / R_NTC_25).ln();
-
-    (1.0 / inv_t) - 273.15
+    DIVIDER.temperature_c(adc)
 }
 
 #[task]
-pub async fn ntc(temp_pin: Peri<'static, PA0>, temp_adc: Peri<'static, ADC1>) {
-    let mut adc = Adc::new(temp_adc);
+pub async fn ntc(temp_pin: Peri<'static, NtcPin>, temp_adc: Peri<'static, ADC1>) {
+    let mut adc = chip::Adc::new(temp_adc, chip::SAMPLE_TIME);
     let mut pin = temp_pin;
 
-    let mut vrefint = adc.enable_vref();
-    adc.set_sample_time(SampleTime::CYCLES13_5);
-
-    let vrefint_sample = adc.read(&mut vrefint).await;
-    let convert_to_millivolts = |sample: u16| {
-        // From http://www.st.com/resource/en/datasheet/CD00161566.pdf
-        // 5.3.4 Embedded reference voltage
-        const VREFINT_MV: u32 = 1200; // mV
-
-        (u32::from(sample) * VREFINT_MV / u32::from(vrefint_sample)) as u16
-    };
+    let vrefint_sample = adc.read_vrefint().await;
+    let convert_to_millivolts =
+        |sample: u16| (u32::from(sample) * chip::VREFINT_MV / u32::from(vrefint_sample)) as u16;
 
     loop {
         let measured = adc.read(&mut pin).await;
-        trace!("--> {} - {} mV", measured, convert_to_millivolts(measured));
+        log!(
+            Ntc,
+            trace,
+            "--> {} - {} mV",
+            measured,
+            convert_to_millivolts(measured)
+        );
+
+        #[cfg(feature = "factory")]
+        factory::record_adc(measured, vrefint_sample);
 
         let temp_c = adc_to_temperature_c(measured);
+        #[cfg(feature = "factory")]
+        let temp_c = temp_c + factory::ntc_offset();
 
         if temp_c.is_normal() {
-            trace!("Temperature: {}", temp_c);
-            SIGNAL_TEMPERATURE.signal(temp_c);
+            log!(Ntc, trace, "Temperature: {}", temp_c);
+            temperature::publish(TemperatureSource::Ntc, temp_c);
         }
 
         Timer::after_millis(1000).await;
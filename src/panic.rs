@@ -0,0 +1,77 @@
+//! Panic handler that leaves the valve alone and signals the failure.
+//!
+//! The executor does not run any more, so everything here goes straight to
+//! the registers: the motor driver is disabled and the status LED (and the
+//! buzzer, if fitted) repeat SOS until the controller is reset.
+
+use core::panic::PanicInfo;
+
+#[cfg(feature = "buzzer")]
+use embassy_stm32::pac;
+use embassy_stm32::peripherals::CRC;
+use embassy_stm32::rcc;
+
+use crate::fmt::{Display2Format, error};
+use crate::{board, chip};
+
+/// Length of a dot in ms
+const UNIT_MS: u32 = 200;
+/// On and off times of S, O, S in units, followed by the pause between words
+const SOS: [(u32, u32); 9] = [
+    (1, 1),
+    (1, 1),
+    (1, 3),
+    (3, 1),
+    (3, 1),
+    (3, 3),
+    (1, 1),
+    (1, 1),
+    (1, 7),
+];
+
+fn delay_units(units: u32) {
+    // The core clock, the same as the AHB clock
+    let cycles_per_ms = rcc::frequency::<CRC>().0 / 1000;
+    cortex_m::asm::delay(units * UNIT_MS * cycles_per_ms);
+}
+
+fn indicate(on: bool) {
+    let (port, pin) = board::STATUS_LED;
+    let high = on != board::STATUS_LED_ACTIVE_LOW;
+    port.bsrr().write(|w| {
+        w.set_bs(pin, high);
+        w.set_br(pin, !high);
+    });
+    // Gate the running tone, does nothing before the buzzer was set up
+    #[cfg(feature = "buzzer")]
+    pac::TIM2.ccer().modify(|w| w.set_cce(2, on));
+}
+
+/// Disable the motor and repeat SOS forever.
+pub fn halt() -> ! {
+    cortex_m::interrupt::disable();
+
+    // The pins may not have been set up yet
+    chip::enable_gpio();
+    let (motor_port, motor_pin) = board::MOTOR_ENABLE;
+    motor_port.bsrr().write(|w| w.set_br(motor_pin, true));
+    indicate(false);
+    chip::push_pull(board::MOTOR_ENABLE);
+    chip::push_pull(board::STATUS_LED);
+
+    loop {
+        for (on, off) in SOS {
+            indicate(true);
+            delay_units(on);
+            indicate(false);
+            delay_units(off);
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    error!("{}", Display2Format(info));
+
+    halt();
+}
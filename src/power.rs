@@ -0,0 +1,102 @@
+//! Peripheral clock gating and an estimate of the supply current.
+//!
+//! The drivers enable the clock of their peripheral when created, but the HAL
+//! initialization leaves a few on that nothing here uses: DMA1, the GPIO ports
+//! missing from the 48 pin package and the CRC unit once the image is
+//! verified. [`init`] turns them off and lets the SRAM and flash interface
+//! clocks stop while the core sleeps between tasks.
+//!
+//! [`estimate`] derives the run mode current from the clock frequencies and
+//! the peripheral clocks enabled at the time, with typical figures from the
+//! STM32F103x8 datasheet. It is meant to compare builds with different
+//! features, a meter in the supply line reads less as the core spends most
+//! of its time sleeping.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_stm32::Peri;
+use embassy_stm32::pac::RCC;
+use embassy_stm32::peripherals;
+use embassy_stm32::rcc;
+
+use crate::fmt::info;
+
+/// Supply current independent of the clock, regulator and oscillators
+const STATIC_UA: u32 = 1_500;
+/// Core, flash and SRAM while running code from flash
+const CORE_UA_PER_MHZ: u32 = 350;
+/// Average of the peripherals, per MHz of their bus clock
+const PERIPHERAL_UA_PER_MHZ: u32 = 6;
+
+/// SRAM and flash interface clocks during sleep, not peripherals
+const AHB_SLEEP_CLOCKS: u32 = 1 << 2 | 1 << 4;
+
+static HCLK: AtomicU32 = AtomicU32::new(0);
+static PCLK1: AtomicU32 = AtomicU32::new(0);
+static PCLK2: AtomicU32 = AtomicU32::new(0);
+
+pub struct Estimate {
+    /// Core clock in Hz
+    pub sysclk: u32,
+    /// Peripherals with their clock enabled
+    pub peripherals: u32,
+    /// Supply current in µA
+    pub current: u32,
+}
+
+/// Gate the clocks nothing uses, after the image was verified with the CRC
+/// unit.
+pub fn init(rcc: &Peri<'_, peripherals::RCC>) {
+    let clocks = rcc::clocks(rcc);
+    let hertz = |clock: embassy_stm32::time::MaybeHertz| clock.to_hertz().map_or(0, |f| f.0);
+    HCLK.store(hertz(clocks.hclk1), Ordering::Relaxed);
+    PCLK1.store(hertz(clocks.pclk1), Ordering::Relaxed);
+    PCLK2.store(hertz(clocks.pclk2), Ordering::Relaxed);
+
+    RCC.ahbenr().modify(|w| {
+        w.set_dma1en(false);
+        w.set_crcen(false);
+        // Only stops them in sleep mode
+        w.set_sramen(false);
+        w.set_flashen(false);
+    });
+    // PD0 and PD1 are the crystal pins, port E is not bonded out
+    RCC.apb2enr().modify(|w| {
+        w.set_gpioden(false);
+        w.set_gpioeen(false);
+    });
+
+    let estimate = estimate();
+    info!(
+        "Power: {} MHz, {} peripherals, about {} mA",
+        estimate.sysclk / 1_000_000,
+        estimate.peripherals,
+        estimate.current / 1000
+    );
+}
+
+/// Current drawn with the clocks enabled now.
+pub fn estimate() -> Estimate {
+    let buses = [
+        (
+            RCC.ahbenr().read().0 & !AHB_SLEEP_CLOCKS,
+            HCLK.load(Ordering::Relaxed),
+        ),
+        (RCC.apb1enr().read().0, PCLK1.load(Ordering::Relaxed)),
+        (RCC.apb2enr().read().0, PCLK2.load(Ordering::Relaxed)),
+    ];
+
+    let sysclk = HCLK.load(Ordering::Relaxed);
+    let mut peripherals = 0;
+    let mut current = STATIC_UA + CORE_UA_PER_MHZ * (sysclk / 1_000_000);
+    for (enabled, clock) in buses {
+        peripherals += enabled.count_ones();
+        current += enabled.count_ones() * PERIPHERAL_UA_PER_MHZ * (clock / 1_000_000);
+    }
+
+    Estimate {
+        sysclk,
+        peripherals,
+        current,
+    }
+}
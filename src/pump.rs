@@ -0,0 +1,145 @@
+//! Circulation pump relay on PA4, active high.
+//!
+//! The pump runs while the valve is open and keeps running for
+//! [`OVERRUN`] after it closed, to carry away the heat left in the circuit.
+//! A pump that stood still for [`KICK_INTERVAL`] is started for
+//! [`KICK_TIME`] so it does not seize over the summer.
+//!
+//! With the flow sensor a pump that delivers less than [`MIN_FLOW`] for
+//! [`DRY_RUN_TIME`] is stopped as running dry and only tried again after
+//! [`DRY_RUN_PAUSE`].
+
+use embassy_executor::task;
+use embassy_stm32::gpio::Output;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::actuators;
+use crate::fmt::info;
+#[cfg(feature = "flow")]
+use crate::fmt::warn;
+#[cfg(feature = "flow")]
+use crate::indicator::{self, Condition};
+use crate::state;
+
+const OVERRUN: Duration = Duration::from_secs(5 * 60);
+const KICK_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const KICK_TIME: Duration = Duration::from_secs(30);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Flow in ml/min
+#[cfg(feature = "flow")]
+const MIN_FLOW: u32 = 500;
+#[cfg(feature = "flow")]
+const DRY_RUN_TIME: Duration = Duration::from_secs(30);
+#[cfg(feature = "flow")]
+const DRY_RUN_PAUSE: Duration = Duration::from_secs(15 * 60);
+
+struct Pump {
+    relay: Output<'static>,
+    running: bool,
+    /// Last time the valve was seen open
+    demand_seen: Option<Instant>,
+    stopped_at: Instant,
+    kick_until: Option<Instant>,
+    #[cfg(feature = "flow")]
+    dry_since: Option<Instant>,
+    #[cfg(feature = "flow")]
+    blocked_until: Option<Instant>,
+}
+
+impl Pump {
+    /// Whether the pump should run now, given the valve opening.
+    fn wanted(&mut self, valve_open: bool) -> bool {
+        let now = Instant::now();
+        if valve_open {
+            self.demand_seen = Some(now);
+            self.kick_until = None;
+            return true;
+        }
+
+        if self
+            .demand_seen
+            .is_some_and(|seen| now.duration_since(seen) < OVERRUN)
+        {
+            return true;
+        }
+
+        if !self.running && now.duration_since(self.stopped_at) >= KICK_INTERVAL {
+            info!("Pump: anti-seize run");
+            self.kick_until = Some(now + KICK_TIME);
+        }
+        self.kick_until.is_some_and(|until| now < until)
+    }
+
+    /// Stop a pump running dry, returns whether it may run.
+    #[cfg(feature = "flow")]
+    fn check_flow(&mut self, flow: u32) -> bool {
+        let now = Instant::now();
+        if let Some(until) = self.blocked_until {
+            if now < until {
+                return false;
+            }
+            info!("Pump: trying again after running dry");
+            self.blocked_until = None;
+            indicator::set(Condition::DryRun, false);
+        }
+
+        if !self.running || flow >= MIN_FLOW {
+            self.dry_since = None;
+            return true;
+        }
+
+        let since = *self.dry_since.get_or_insert(now);
+        if now.duration_since(since) < DRY_RUN_TIME {
+            return true;
+        }
+
+        warn!("Pump: no flow, stopped as running dry");
+        self.dry_since = None;
+        self.blocked_until = Some(now + DRY_RUN_PAUSE);
+        indicator::set(Condition::DryRun, true);
+        false
+    }
+
+    fn set(&mut self, running: bool) {
+        if running == self.running {
+            return;
+        }
+
+        info!("Pump {}", if running { "on" } else { "off" });
+        self.relay.set_level(running.into());
+        self.running = running;
+        if !running {
+            self.stopped_at = Instant::now();
+            self.kick_until = None;
+        }
+        state::update(|s| s.pump_running = running);
+    }
+}
+
+#[task]
+pub async fn pump(relay: Output<'static>) {
+    info!("Starting pump control");
+    let mut pump = Pump {
+        relay,
+        running: false,
+        demand_seen: None,
+        stopped_at: Instant::now(),
+        kick_until: None,
+        #[cfg(feature = "flow")]
+        dry_since: None,
+        #[cfg(feature = "flow")]
+        blocked_until: None,
+    };
+
+    loop {
+        let state = state::get();
+        let running = pump.wanted(state.valve_position > 0);
+        #[cfg(feature = "flow")]
+        let running = pump.check_flow(state.flow) && running;
+        if running && !pump.running {
+            actuators::stagger().await;
+        }
+        pump.set(running);
+        Timer::after(CHECK_INTERVAL).await;
+    }
+}
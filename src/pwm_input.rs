@@ -0,0 +1,69 @@
+//! PWM demand input on PA6 (TIM3 CH1), as output by many heat pump
+//! controllers.
+//!
+//! TIM3 runs in PWM input mode at 1 MHz, so signals from about 20 Hz to
+//! 10 kHz are measured. Without a rising edge for [`SIGNAL_TIMEOUT`] the
+//! signal counts as lost, a constant level included, and control goes back
+//! to the local regulation.
+
+use embassy_executor::task;
+use embassy_stm32::Peri;
+use embassy_stm32::gpio::Pull;
+use embassy_stm32::pac;
+use embassy_stm32::peripherals::TIM3;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::pwm_input::PwmInput;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::board::PwmInputPin;
+use crate::demand::{ExternalDemand, FULL_SCALE};
+use crate::fmt::{info, trace, warn};
+
+const TICK_FREQUENCY: Hertz = Hertz(1_000_000);
+const SIGNAL_TIMEOUT: Duration = Duration::from_secs(2);
+const SAMPLE_INTERVAL_MS: u64 = 100;
+
+/// Whether a rising edge was captured since the last call.
+fn captured() -> bool {
+    let captured = pac::TIM3.sr().read().ccif(0);
+    pac::TIM3.sr().modify(|w| w.set_ccif(0, false));
+    captured
+}
+
+#[task]
+pub async fn pwm_input(pin: Peri<'static, PwmInputPin>, tim: Peri<'static, TIM3>) {
+    let mut pwm = PwmInput::new_ch1(tim, pin, Pull::Down, TICK_FREQUENCY);
+    pwm.enable();
+
+    let mut demand = ExternalDemand::new();
+    let mut last_edge: Option<Instant> = None;
+
+    info!("Starting PWM input");
+    loop {
+        Timer::after_millis(SAMPLE_INTERVAL_MS).await;
+
+        if captured() {
+            if last_edge.is_none() {
+                info!("PWM: signal present");
+            }
+            last_edge = Some(Instant::now());
+        }
+
+        match last_edge {
+            Some(edge) if edge.elapsed() >= SIGNAL_TIMEOUT => {
+                warn!("PWM: signal lost");
+                last_edge = None;
+                demand.release();
+            }
+            Some(_) => {
+                let period = pwm.get_period_ticks();
+                let width = pwm.get_width_ticks();
+                if let Some(duty) = (width.min(period) * FULL_SCALE).checked_div(period) {
+                    trace!("PWM: {}/{} ticks, {} per mille", width, period, duty);
+                    demand.apply(duty);
+                }
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! RGB status LED on TIM1: PA8 red, PA9 green, PA10 blue, common cathode.
+//!
+//! Shows the same status as the single LED as colors, fading between
+//! them: green idle, blue opening, orange closing, purple during a manual
+//! override and red for faults, flashing the fault code.
+
+use embassy_stm32::peripherals::TIM1;
+use embassy_stm32::timer::Channel;
+use embassy_stm32::timer::simple_pwm::SimplePwm;
+use embassy_time::{Duration, Instant};
+
+use crate::indicator::{Pattern, Status, StatusIndicator};
+use crate::led::{FAULT_BLINK_MS, FAULT_PAUSE_MS};
+
+/// Overall brightness in %
+pub const BRIGHTNESS: u16 = 50;
+const STEP: Duration = Duration::from_millis(20);
+/// Share of the remaining difference covered per step, as a power of two
+const FADE_SHIFT: u32 = 3;
+
+type Color = [u16; 3];
+
+const OFF: Color = [0, 0, 0];
+const RED: Color = [255, 0, 0];
+const GREEN: Color = [0, 255, 0];
+const BLUE: Color = [0, 0, 255];
+const ORANGE: Color = [255, 80, 0];
+#[cfg(feature = "manual")]
+const PURPLE: Color = [160, 0, 255];
+
+const CHANNELS: [Channel; 3] = [Channel::Ch1, Channel::Ch2, Channel::Ch3];
+
+/// Color for `pattern` at `ms` since it started.
+fn color(pattern: Pattern, ms: u64) -> Color {
+    match pattern {
+        Pattern::Fault(code) => {
+            let blinks = u64::from(code) * 2 * FAULT_BLINK_MS;
+            let phase = ms % (blinks + FAULT_PAUSE_MS);
+            if phase < blinks && phase % (2 * FAULT_BLINK_MS) < FAULT_BLINK_MS {
+                RED
+            } else {
+                OFF
+            }
+        }
+        Pattern::Calibration | Pattern::Opening => BLUE,
+        Pattern::Closing => ORANGE,
+        #[cfg(feature = "manual")]
+        Pattern::Override => PURPLE,
+        Pattern::Idle => GREEN,
+    }
+}
+
+pub struct RgbLed {
+    pwm: SimplePwm<'static, TIM1>,
+    pattern: Pattern,
+    since: Instant,
+    /// Levels kept scaled up by the fade weight
+    current: [u16; 3],
+}
+
+impl StatusIndicator for RgbLed {
+    const NAME: &'static str = "RGB LED";
+
+    fn update(&mut self, status: Status) {
+        let pattern = status.pattern();
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            self.since = Instant::now();
+        }
+    }
+
+    fn tick(&mut self) -> Instant {
+        let target = color(self.pattern, self.since.elapsed().as_millis());
+        for (index, channel) in CHANNELS.into_iter().enumerate() {
+            let level = &mut self.current[index];
+            let scaled = target[index] << FADE_SHIFT;
+            // Exponential approach, moving at least one step
+            if *level < scaled {
+                *level += ((scaled - *level) >> FADE_SHIFT).max(1);
+            } else if *level > scaled {
+                *level -= ((*level - scaled) >> FADE_SHIFT).max(1);
+            }
+            let brightness = (*level >> FADE_SHIFT) * BRIGHTNESS / 100;
+            self.pwm
+                .channel(channel)
+                .set_duty_cycle_fraction(brightness, 255);
+        }
+        Instant::now() + STEP
+    }
+}
+
+impl RgbLed {
+    pub fn new(mut pwm: SimplePwm<'static, TIM1>) -> Self {
+        for channel in CHANNELS {
+            pwm.channel(channel).enable();
+        }
+
+        Self {
+            pwm,
+            pattern: Pattern::Idle,
+            since: Instant::now(),
+            current: [0; 3],
+        }
+    }
+}
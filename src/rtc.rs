@@ -0,0 +1,345 @@
+//! Real-time clock on the 32.768 kHz LSE crystal (PC14, PC15).
+//!
+//! The RTC counts UTC seconds since the Unix epoch and keeps running from
+//! VBAT while the board is unpowered. A marker in a backup register tells
+//! whether the time was ever set, until then [`now`] returns `None`.
+//!
+//! Time received over the radio links disciplines the clock with [`sync`]:
+//! besides correcting the time, the drift between two syncs sets the
+//! calibration, which slows the RTC down in steps of about 1 ppm.
+//!
+//! With `low-power` the counter is the time base of `low_power` instead and
+//! counts its ticks from the first power up, it is never written. The time
+//! is a base in two more backup registers plus the counter then, and the
+//! base moves on by the full counter range whenever the time driver sees the
+//! counter wrap around.
+
+use core::fmt::{self, Display};
+#[cfg(any(feature = "ble", feature = "lora"))]
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_stm32::pac::rtc::regs::Crl;
+use embassy_stm32::pac::rtc::vals::Rtoff;
+use embassy_stm32::pac::{BKP, PWR, RCC, RTC};
+
+use crate::fmt::{Display2Format, info};
+
+/// Backup register holding [`TIME_SET`] once the clock was set
+const MARKER_REGISTER: usize = 0;
+/// Differs with the counter rate, so a clock set by the other build is not
+/// taken for set
+#[cfg(not(feature = "low-power"))]
+const TIME_SET: u16 = 0xC10C;
+#[cfg(feature = "low-power")]
+const TIME_SET: u16 = 0xC10D;
+/// LSE divided to 1 Hz
+#[cfg(not(feature = "low-power"))]
+const PRESCALER: u32 = 32_768 - 1;
+/// LSE divided to the tick rate of the time driver
+#[cfg(feature = "low-power")]
+const PRESCALER: u32 = 32_768 / TICK_HZ - 1;
+#[cfg(feature = "low-power")]
+const TICK_HZ: u32 = embassy_time::TICK_HZ as u32;
+/// Backup registers holding the time at a counter value of 0, low half first
+#[cfg(feature = "low-power")]
+const BASE_REGISTER: usize = 1;
+
+/// Shortest time between syncs to measure the drift over
+#[cfg(any(feature = "ble", feature = "lora"))]
+const DRIFT_INTERVAL: u32 = 6 * 3600;
+/// Calibration steps, in 2^-20 of the clock
+#[cfg(any(feature = "ble", feature = "lora"))]
+const CALIBRATION_SHIFT: u32 = 20;
+#[cfg(any(feature = "ble", feature = "lora"))]
+const CALIBRATION_MAX: i64 = 127;
+
+/// Reference time of the last sync the drift is measured from, 0 for none
+#[cfg(any(feature = "ble", feature = "lora"))]
+static LAST_SYNC: AtomicU32 = AtomicU32::new(0);
+
+const SECONDS_PER_DAY: u32 = 86_400;
+/// Days from 0000-03-01 to the epoch in the proleptic Gregorian calendar
+const EPOCH_DAYS: i32 = 719_468;
+
+/// UTC date and time.
+#[derive(Clone, Copy)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Civil date from seconds since the epoch, see
+    /// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    pub fn from_seconds(seconds: u32) -> Self {
+        let days = (seconds / SECONDS_PER_DAY) as i32 + EPOCH_DAYS;
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + i32::from(month <= 2);
+
+        let time = seconds % SECONDS_PER_DAY;
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// Seconds since the epoch, `None` for an invalid or unrepresentable date.
+    #[cfg(feature = "shell")]
+    pub fn to_seconds(self) -> Option<u32> {
+        if !(1970..=2105).contains(&self.year)
+            || !(1..=12).contains(&self.month)
+            || !(1..=31).contains(&self.day)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 59
+        {
+            return None;
+        }
+
+        // Inverse of the above, days_from_civil
+        let year = i32::from(self.year) - i32::from(self.month <= 2);
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let month = i32::from(self.month);
+        let month_index = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * month_index + 2) / 5 + i32::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - EPOCH_DAYS;
+
+        let time = u32::from(self.hour) * 3600 + u32::from(self.minute) * 60;
+        (days as u32)
+            .checked_mul(SECONDS_PER_DAY)?
+            .checked_add(time + u32::from(self.second))
+    }
+
+    /// Parse `YYYY-MM-DD` and `HH:MM:SS`.
+    #[cfg(feature = "shell")]
+    pub fn parse(date: &str, time: &str) -> Option<Self> {
+        let mut date = date.splitn(3, '-');
+        let mut time = time.splitn(3, ':');
+        let date_time = Self {
+            year: date.next()?.parse().ok()?,
+            month: date.next()?.parse().ok()?,
+            day: date.next()?.parse().ok()?,
+            hour: time.next()?.parse().ok()?,
+            minute: time.next()?.parse().ok()?,
+            second: time.next()?.parse().ok()?,
+        };
+        // Reject dates rolling over into the next month
+        let seconds = date_time.to_seconds()?;
+        (DateTime::from_seconds(seconds).day == date_time.day).then_some(date_time)
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Wait for the last write to the RTC registers to finish.
+fn wait_write() {
+    while RTC.crl().read().rtoff() == Rtoff::ONGOING {}
+}
+
+/// Write the control register with the flags left alone unless cleared by
+/// `f`. Flags are cleared by writing 0, so a flag set between reading and
+/// writing back the register would be lost otherwise.
+fn write_crl(f: impl FnOnce(&mut Crl)) {
+    wait_write();
+    RTC.crl().write(|w| {
+        w.set_secf(true);
+        w.set_alrf(true);
+        w.set_owf(true);
+        w.set_rsf(true);
+        f(w);
+    });
+}
+
+/// Run `f` in configuration mode, where the counter, prescaler and alarm are
+/// writable.
+fn configure(f: impl FnOnce()) {
+    write_crl(|w| w.set_cnf(true));
+    f();
+    write_crl(|_| {});
+    wait_write();
+}
+
+/// Wait for the APB interface to catch up with the RTC, which it does not
+/// follow during a reset or STOP mode.
+pub fn resync() {
+    write_crl(|w| w.set_rsf(false));
+    while !RTC.crl().read().rsf() {}
+}
+
+/// Give access to the RTC, clocked from the LSE by the RCC setup.
+pub fn init() {
+    RCC.apb1enr().modify(|w| {
+        w.set_pwren(true);
+        w.set_bkpen(true);
+    });
+    PWR.cr().modify(|w| w.set_dbp(true));
+    resync();
+
+    match now() {
+        Some(seconds) => info!("RTC: {}", Display2Format(&DateTime::from_seconds(seconds))),
+        None => {
+            // The backup domain was reset and lost the prescaler as well
+            info!("RTC: time not set");
+            configure(|| {
+                RTC.prlh().write(|w| w.set_prlh((PRESCALER >> 16) as u8));
+                RTC.prll().write(|w| w.set_prll(PRESCALER as u16));
+            });
+        }
+    }
+}
+
+/// Raw counter value, read twice to catch a carry between the halves.
+fn counter() -> u32 {
+    loop {
+        let high = RTC.cnth().read().cnth();
+        let low = RTC.cntl().read().cntl();
+        if RTC.cnth().read().cnth() == high {
+            return u32::from(high) << 16 | u32::from(low);
+        }
+    }
+}
+
+/// Counter value and whether it wrapped around since the last call, moving
+/// the base on by the counter range then.
+#[cfg(feature = "low-power")]
+pub fn ticks() -> (u32, bool) {
+    loop {
+        let wrapped = RTC.crl().read().owf();
+        let counter = counter();
+        // A wrap between the reads leaves the counter unclear
+        if RTC.crl().read().owf() == wrapped {
+            if wrapped {
+                set_base(base().wrapping_add(((1 << 32) / u64::from(TICK_HZ)) as u32));
+                write_crl(|w| w.set_owf(false));
+            }
+            return (counter, wrapped);
+        }
+    }
+}
+
+#[cfg(feature = "low-power")]
+fn base() -> u32 {
+    let low = BKP.dr(BASE_REGISTER).read().d();
+    let high = BKP.dr(BASE_REGISTER + 1).read().d();
+    u32::from(high) << 16 | u32::from(low)
+}
+
+#[cfg(feature = "low-power")]
+fn set_base(base: u32) {
+    BKP.dr(BASE_REGISTER).write(|w| w.set_d(base as u16));
+    BKP.dr(BASE_REGISTER + 1)
+        .write(|w| w.set_d((base >> 16) as u16));
+}
+
+/// Alarm at the counter value `ticks`, raising EXTI17.
+#[cfg(feature = "low-power")]
+pub fn set_alarm(ticks: u32) {
+    configure(|| {
+        RTC.alrh().write(|w| w.set_alrh((ticks >> 16) as u16));
+        RTC.alrl().write(|w| w.set_alrl(ticks as u16));
+    });
+}
+
+#[cfg(feature = "low-power")]
+pub fn enable_alarm() {
+    wait_write();
+    RTC.crh().modify(|w| w.set_alrie(true));
+}
+
+#[cfg(feature = "low-power")]
+pub fn clear_alarm() {
+    write_crl(|w| w.set_alrf(false));
+}
+
+/// Seconds since the epoch if the time was set.
+#[cfg(not(feature = "low-power"))]
+fn seconds() -> u32 {
+    counter()
+}
+
+#[cfg(feature = "low-power")]
+fn seconds() -> u32 {
+    // Through the time driver, which moves the base on a wrap around
+    let ticks = embassy_time::Instant::now().as_ticks() as u32;
+    base().wrapping_add(ticks / TICK_HZ)
+}
+
+/// Current time in seconds since the epoch, `None` until set.
+pub fn now() -> Option<u32> {
+    (BKP.dr(MARKER_REGISTER).read().d() == TIME_SET).then(seconds)
+}
+
+#[cfg(any(feature = "shell", feature = "ble", feature = "lora"))]
+pub fn set(seconds: u32) {
+    #[cfg(not(feature = "low-power"))]
+    configure(|| {
+        RTC.cnth().write(|w| w.set_cnth((seconds >> 16) as u16));
+        RTC.cntl().write(|w| w.set_cntl(seconds as u16));
+    });
+    #[cfg(feature = "low-power")]
+    {
+        let ticks = embassy_time::Instant::now().as_ticks() as u32;
+        set_base(seconds.wrapping_sub(ticks / TICK_HZ));
+    }
+    BKP.dr(MARKER_REGISTER).write(|w| w.set_d(TIME_SET));
+    info!(
+        "RTC: time set to {}",
+        Display2Format(&DateTime::from_seconds(seconds))
+    );
+}
+
+/// Discipline the clock with the `reference` time from a time source.
+#[cfg(any(feature = "ble", feature = "lora"))]
+pub fn sync(reference: u32) {
+    let last = LAST_SYNC.load(Ordering::Relaxed);
+    match now() {
+        Some(now) if last != 0 && reference.saturating_sub(last) >= DRIFT_INTERVAL => {
+            // Positive when running fast, the calibration can only slow down
+            let error = i64::from(now) - i64::from(reference);
+            let steps = (error << CALIBRATION_SHIFT) / i64::from(reference - last);
+            let calibration = BKP.rtccr().read().cal();
+            let calibration = (i64::from(calibration) + steps).clamp(0, CALIBRATION_MAX) as u8;
+            BKP.rtccr().modify(|w| w.set_cal(calibration));
+            info!("RTC: off by {} s, calibration {}", error, calibration);
+        }
+        // Too close to the last sync to tell drift from jitter
+        Some(now) if last != 0 && now.abs_diff(reference) <= 1 => return,
+        _ => {}
+    }
+
+    set(reference);
+    LAST_SYNC.store(reference, Ordering::Relaxed);
+}
+
+// Log with the wall clock time instead of the uptime
+#[cfg(feature = "defmt")]
+defmt::timestamp!("{=u64:iso8601s}", u64::from(seconds()));
@@ -0,0 +1,74 @@
+//! Power-on self-test before the regulation starts.
+//!
+//! Checks what the board can check on its own: the supply voltage through
+//! the internal reference, an NTC reading within the plausible range and,
+//! with `rtc`, a set clock. The motor is pulsed open and closed again so a
+//! dead actuator can be noticed on site, the driver has no current sense or
+//! limit switches to verify it. The image CRC was checked before already.
+//!
+//! A failed check raises [`Condition::SelfTest`] until the controller is
+//! reset, the regulation runs anyway and handles a bad reading on its own.
+
+use embassy_stm32::Peri;
+use embassy_stm32::peripherals::ADC1;
+use embassy_time::{Duration, Timer};
+
+use crate::board::NtcPin;
+use crate::chip;
+use crate::fmt::{info, warn};
+use crate::indicator::{self, Condition};
+use crate::motor_control::MotorControl;
+use crate::ntc::adc_to_temperature_c;
+#[cfg(feature = "rtc")]
+use crate::rtc;
+
+/// Supply voltage range of the board in mV
+const SUPPLY_MV: (u32, u32) = (3000, 3600);
+/// Plausible NTC readings in °C, outside a wire is open or shorted
+const NTC_RANGE: (f32, f32) = (-20.0, 110.0);
+/// Motor pulse in each direction, short enough to leave the valve in place
+const MOTOR_PULSE: Duration = Duration::from_millis(300);
+
+fn report(name: &str, passed: bool) -> bool {
+    if passed {
+        info!("Self-test: {} ok", name);
+    } else {
+        warn!("Self-test: {} FAILED", name);
+    }
+    passed
+}
+
+/// Run the checks and report the result.
+pub async fn run(ntc: Peri<'_, NtcPin>, adc: Peri<'_, ADC1>, motor: &mut MotorControl) {
+    let mut adc = chip::Adc::new(adc, chip::SAMPLE_TIME);
+    let mut ntc = ntc;
+
+    let reference = u32::from(adc.read_vrefint().await);
+    let supply = chip::VREFINT_MV * 4095 / reference.max(1);
+    info!("Self-test: supply {} mV", supply);
+    let mut passed = report("supply", (SUPPLY_MV.0..=SUPPLY_MV.1).contains(&supply));
+
+    let temperature = adc_to_temperature_c(adc.read(&mut ntc).await);
+    info!("Self-test: NTC {} C", temperature);
+    passed &= report("NTC", (NTC_RANGE.0..=NTC_RANGE.1).contains(&temperature));
+
+    #[cfg(feature = "rtc")]
+    {
+        passed &= report("clock", rtc::now().is_some());
+    }
+
+    info!("Self-test: pulsing the motor");
+    motor.open();
+    Timer::after(MOTOR_PULSE).await;
+    motor.stop();
+    motor.close();
+    Timer::after(MOTOR_PULSE).await;
+    motor.stop();
+
+    if passed {
+        info!("Self-test passed");
+    } else {
+        warn!("Self-test failed");
+        indicator::set(Condition::SelfTest, true);
+    }
+}
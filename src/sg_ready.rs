@@ -0,0 +1,82 @@
+//! SG-ready style demand-response input from the utility.
+//!
+//! Two potential-free contacts to ground on PB3 and PB4 (freed from JTAG,
+//! SWD keeps working) select the grid mode through [`MODES`]. The mode
+//! shifts whatever setpoint is active, local, remote or from an external
+//! demand, so it takes priority over all of them. Only the safety stops and
+//! an external demand driving the valve position directly rank higher.
+
+use embassy_executor::task;
+use embassy_stm32::gpio::Input;
+use embassy_time::Timer;
+
+use crate::fmt::info;
+use crate::state;
+
+/// Operating mode requested by the utility.
+#[derive(PartialEq, Clone, Copy)]
+pub enum GridMode {
+    /// Lower the setpoint while energy is scarce or expensive
+    Eco,
+    Normal,
+    /// Raise the setpoint to use cheap or surplus energy
+    Boost,
+}
+
+/// Grid mode for the contacts `[open/open, 1 closed, 2 closed, both closed]`,
+/// following the SG-ready states block, normal, recommend on and force on
+pub const MODES: [GridMode; 4] = [
+    GridMode::Normal,
+    GridMode::Eco,
+    GridMode::Boost,
+    GridMode::Boost,
+];
+const ECO_OFFSET: f32 = -5.0;
+const BOOST_OFFSET: f32 = 5.0;
+const POLL_INTERVAL_MS: u64 = 250;
+/// Consecutive equal readings before a new mode is accepted
+const DEBOUNCE_COUNT: u8 = 4;
+
+impl GridMode {
+    /// Setpoint shift applied in this mode.
+    pub const fn offset(self) -> f32 {
+        match self {
+            GridMode::Eco => ECO_OFFSET,
+            GridMode::Normal => 0.0,
+            GridMode::Boost => BOOST_OFFSET,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            GridMode::Eco => "eco",
+            GridMode::Normal => "normal",
+            GridMode::Boost => "boost",
+        }
+    }
+}
+
+#[task]
+pub async fn sg_ready(input_1: Input<'static>, input_2: Input<'static>) {
+    let read = || MODES[usize::from(input_1.is_low()) | usize::from(input_2.is_low()) << 1];
+
+    let mut candidate = read();
+    let mut count = 0;
+
+    info!("Starting SG-ready input");
+    loop {
+        let mode = read();
+        if mode != candidate {
+            candidate = mode;
+            count = 0;
+        } else if count < DEBOUNCE_COUNT {
+            count += 1;
+            if count == DEBOUNCE_COUNT && state::get().grid_mode != mode {
+                info!("Grid mode {}", mode.name());
+                state::update(|s| s.grid_mode = mode);
+            }
+        }
+
+        Timer::after_millis(POLL_INTERVAL_MS).await;
+    }
+}
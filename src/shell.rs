@@ -0,0 +1,535 @@
+//! Line based command shell shared by the text interfaces.
+//!
+//! Transports feed received bytes into a [`Shell`] and forward everything it
+//! writes back to the terminal.
+
+use core::fmt::{self, Write};
+
+use embassy_time::{Duration, Instant};
+use heapless::String;
+
+#[cfg(feature = "auth")]
+use crate::auth;
+#[cfg(feature = "buzzer")]
+use crate::buzzer;
+#[cfg(feature = "factory")]
+use crate::factory;
+use crate::lockout;
+use crate::log::{self, Level, Module};
+use crate::motor_control::MotorStatus;
+#[cfg(feature = "power")]
+use crate::power;
+#[cfg(feature = "rtc")]
+use crate::rtc::{self, DateTime};
+#[cfg(feature = "stack")]
+use crate::stack;
+use crate::temperature::Celsius;
+use crate::{identity, manual, state, version};
+
+const LINE_LEN: usize = 96;
+pub const PROMPT: &str = "> ";
+
+/// Parse a decimal number with at most one fractional digit, e.g. `55` or `-2.5`.
+fn parse_tenths(value: &str) -> Option<f32> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, "0"));
+    if fraction.len() != 1 {
+        return None;
+    }
+
+    let tenths = integer.parse::<i32>().ok()? * 10 + fraction.parse::<i32>().ok()?;
+    let value = tenths as f32 / 10.0;
+    Some(if negative { -value } else { value })
+}
+
+/// Follow-up the transport has to run after the command output is sent.
+pub enum Action {
+    EnterBootloader,
+    /// Park the motor and enter the factory test mode
+    #[cfg(feature = "factory")]
+    EnterFactory,
+}
+
+pub struct Shell {
+    line: String<LINE_LEN>,
+    last_byte: u8,
+    telemetry: bool,
+}
+
+impl Shell {
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            last_byte: 0,
+            telemetry: false,
+        }
+    }
+
+    /// Periodic status output requested with the `telemetry` command.
+    pub fn telemetry(&self) -> bool {
+        self.telemetry
+    }
+
+    /// Feed a received byte, writing the echo and any command output to `out`.
+    pub fn feed(&mut self, byte: u8, out: &mut impl Write) -> Option<Action> {
+        let last_byte = core::mem::replace(&mut self.last_byte, byte);
+        match byte {
+            b'\n' if last_byte == b'\r' => {}
+            b'\r' | b'\n' => {
+                let _ = out.write_str("\r\n");
+                let line = core::mem::take(&mut self.line);
+                let action = self.execute(&line, out);
+                if action.is_none() {
+                    let _ = out.write_str(PROMPT);
+                }
+                return action;
+            }
+            0x08 | 0x7F if !self.line.is_empty() => {
+                self.line.pop();
+                let _ = out.write_str("\x08 \x08");
+            }
+            0x20..=0x7E if self.line.len() < LINE_LEN => {
+                let _ = self.line.push(byte as char);
+                let _ = out.write_char(byte as char);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn execute(&mut self, line: &str, out: &mut impl Write) -> Option<Action> {
+        // State changing commands end with a counter and tag
+        #[cfg(feature = "auth")]
+        let line = {
+            let mut args = line.split_whitespace();
+            let changes_state = match args.next() {
+                Some("setpoint") | Some("override") | Some("time") => args.next().is_some(),
+                Some("dfu") => true,
+                Some("lockout") => args.next().is_some(),
+                #[cfg(feature = "factory")]
+                Some("factory") | Some("out") => true,
+                #[cfg(feature = "factory")]
+                Some("cal") => args.next().is_some(),
+                _ => false,
+            };
+            if !changes_state {
+                line
+            } else if let Some(command) = auth::verify_text(line) {
+                command
+            } else {
+                let _ = out.write_str("authentication failed\r\n");
+                return None;
+            }
+        };
+
+        let mut args = line.split_whitespace();
+        let _ = match args.next() {
+            None => Ok(()),
+            Some("help") => {
+                let help = out.write_str(
+                    "help                 this help\r\n\
+                     status               show controller state\r\n\
+                     setpoint [value]     show or change the setpoint\r\n\
+                     override [valve <%>|setpoint <value>] [min]\r\n\
+                     \x20                    fix the valve or setpoint for a while\r\n\
+                     override off         return to automatic operation\r\n\
+                     telemetry [on|off]   periodic status output\r\n\
+                     lockout [clear]      show or release the safe mode\r\n\
+                     log [module|all] [level]\r\n\
+                     \x20                    show or set the log levels\r\n\
+                     version              show firmware build information\r\n\
+                     dfu                  restart into the system bootloader\r\n",
+                );
+                #[cfg(feature = "buzzer")]
+                let help = help.and_then(|()| {
+                    out.write_str("mute                 silence the sounding alarms\r\n")
+                });
+                #[cfg(feature = "rtc")]
+                let help = help.and_then(|()| {
+                    out.write_str(
+                        "time [YYYY-MM-DD HH:MM:SS]\r\n\
+                         \x20                    show or set the UTC time\r\n",
+                    )
+                });
+                help
+            }
+            #[cfg(feature = "buzzer")]
+            Some("mute") => {
+                buzzer::mute();
+                out.write_str("alarms muted\r\n")
+            }
+            Some("status") => status(out),
+            #[cfg(feature = "rtc")]
+            Some("time") => match (args.next(), args.next()) {
+                (None, _) => time(out),
+                (Some(date), Some(time)) => {
+                    match DateTime::parse(date, time).and_then(DateTime::to_seconds) {
+                        Some(seconds) => {
+                            rtc::set(seconds);
+                            write!(
+                                out,
+                                "time set to {} UTC\r\n",
+                                DateTime::from_seconds(seconds)
+                            )
+                        }
+                        None => out.write_str("invalid time, expected YYYY-MM-DD HH:MM:SS\r\n"),
+                    }
+                }
+                _ => out.write_str("usage: time [YYYY-MM-DD HH:MM:SS]\r\n"),
+            },
+            Some("setpoint") => match args.next() {
+                None => write!(out, "setpoint: {}\r\n", Celsius(state::get().setpoint)),
+                Some(value) => match parse_tenths(value) {
+                    Some(setpoint) if state::set_setpoint(setpoint) => {
+                        write!(out, "setpoint set to {}\r\n", Celsius(setpoint))
+                    }
+                    _ => write!(
+                        out,
+                        "invalid setpoint, expected {} - {}\r\n",
+                        Celsius(state::SETPOINT_MIN),
+                        Celsius(state::SETPOINT_MAX)
+                    ),
+                },
+            },
+            Some("override") => {
+                let target = args.next();
+                let value = args.next();
+                let duration = match args.next().map(str::parse::<u64>) {
+                    None => Some(manual::DEFAULT_DURATION),
+                    Some(Ok(minutes)) if minutes > 0 => Some(Duration::from_secs(minutes * 60)),
+                    Some(_) => None,
+                };
+                match (target, value, duration) {
+                    (None, ..) => override_status(out),
+                    (Some("off"), None, _) => {
+                        manual::end();
+                        out.write_str("override ended\r\n")
+                    }
+                    (Some("valve"), Some(value), Some(duration)) => match value.parse::<u8>() {
+                        Ok(position) if position <= 100 => {
+                            manual::fix_valve(Some(position), duration);
+                            write!(out, "valve fixed at {} %\r\n", position)
+                        }
+                        _ => out.write_str("invalid position, expected 0 - 100\r\n"),
+                    },
+                    (Some("setpoint"), Some(value), Some(duration)) => match parse_tenths(value) {
+                        Some(setpoint) if manual::fix_setpoint(setpoint, duration) => {
+                            write!(out, "setpoint fixed at {}\r\n", Celsius(setpoint))
+                        }
+                        _ => write!(
+                            out,
+                            "invalid setpoint, expected {} - {}\r\n",
+                            Celsius(state::SETPOINT_MIN),
+                            Celsius(state::SETPOINT_MAX)
+                        ),
+                    },
+                    _ => {
+                        out.write_str("usage: override [valve <%>|setpoint <value>|off] [min]\r\n")
+                    }
+                }
+            }
+            Some("lockout") => match (args.next(), lockout::latched()) {
+                (None, Some(reason)) => write!(out, "lockout: {}\r\n", reason.name()),
+                (None, None) => out.write_str("lockout: none\r\n"),
+                (Some("clear"), _) => {
+                    lockout::clear();
+                    out.write_str("lockout cleared\r\n")
+                }
+                _ => out.write_str("usage: lockout [clear]\r\n"),
+            },
+            Some("log") => log_levels(out, args.next(), args.next()),
+            Some("telemetry") => {
+                match args.next() {
+                    Some("on") => self.telemetry = true,
+                    Some("off") => self.telemetry = false,
+                    _ => {}
+                }
+                write!(
+                    out,
+                    "telemetry {}\r\n",
+                    if self.telemetry { "on" } else { "off" }
+                )
+            }
+            Some("version") => write!(
+                out,
+                "version: {}\r\ncommit: {}\r\nbuilt: {} UTC\r\nfeatures: {}\r\n",
+                version::VERSION,
+                version::GIT_HASH,
+                version::BUILD_TIME,
+                version::FEATURES
+            ),
+            Some("dfu") => {
+                let _ = out.write_str("motor to safe state, restarting into bootloader\r\n");
+                return Some(Action::EnterBootloader);
+            }
+            #[cfg(feature = "factory")]
+            Some("factory") if factory::active() => out.write_str(FACTORY_HELP),
+            #[cfg(feature = "factory")]
+            Some("factory") => {
+                let _ = out.write_str("motor to safe state, entering factory test mode\r\n");
+                return Some(Action::EnterFactory);
+            }
+            #[cfg(feature = "factory")]
+            Some(command) if factory::active() && FACTORY_COMMANDS.contains(&command) => {
+                factory_command(out, command, args.next(), args.next())
+            }
+            Some(command) => write!(out, "unknown command '{}', try 'help'\r\n", command),
+        };
+        None
+    }
+}
+
+#[cfg(feature = "factory")]
+const FACTORY_COMMANDS: [&str; 4] = ["out", "adc", "uid", "cal"];
+
+#[cfg(feature = "factory")]
+const FACTORY_HELP: &str = "factory test mode, reset to leave\r\n\
+                            out <name> <high|low>\r\n\
+                            \x20                    set an output\r\n\
+                            adc [on|off]         show or stream the raw ADC counts\r\n\
+                            uid                  show the 96-bit unique ID\r\n\
+                            cal [ntc <K>]        show or store the NTC offset\r\n";
+
+/// Run a command of the factory test mode.
+#[cfg(feature = "factory")]
+fn factory_command(
+    out: &mut impl Write,
+    command: &str,
+    first: Option<&str>,
+    second: Option<&str>,
+) -> fmt::Result {
+    match (command, first, second) {
+        ("out", Some(name), Some(level @ ("high" | "low"))) => {
+            if factory::set_output(name, level == "high") {
+                write!(out, "{} {}\r\n", name, level)
+            } else {
+                out.write_str("unknown output, expected")?;
+                for (name, _) in factory::OUTPUTS {
+                    write!(out, " {}", name)?;
+                }
+                out.write_str("\r\n")
+            }
+        }
+        ("adc", stream, None) => {
+            match stream {
+                Some("on") => factory::set_streaming(true),
+                Some("off") => factory::set_streaming(false),
+                _ => {}
+            }
+            factory_adc(out)
+        }
+        ("uid", None, None) => {
+            out.write_str("uid: ")?;
+            for byte in embassy_stm32::uid::uid() {
+                write!(out, "{:02x}", byte)?;
+            }
+            #[cfg(feature = "ble")]
+            write!(out, "\r\npairing: {:06}", identity::pairing_code())?;
+            out.write_str("\r\n")
+        }
+        ("cal", None, None) => {
+            out.write_str("ntc offset: ")?;
+            kelvin(out, factory::ntc_offset())
+        }
+        ("cal", Some("ntc"), Some(value)) => match parse_tenths(value) {
+            Some(offset) if factory::set_ntc_offset(offset) => {
+                out.write_str("ntc offset set to ")?;
+                kelvin(out, offset)
+            }
+            _ => write!(
+                out,
+                "invalid offset, expected -{0} - {0} K\r\n",
+                factory::NTC_OFFSET_MAX as u8
+            ),
+        },
+        _ => out.write_str(FACTORY_HELP),
+    }
+}
+
+/// Write a temperature difference with one decimal, like [`Celsius`].
+#[cfg(feature = "factory")]
+fn kelvin(out: &mut impl Write, value: f32) -> fmt::Result {
+    let tenths = (value * 10.0) as i32;
+    let sign = if tenths < 0 { "-" } else { "" };
+    write!(
+        out,
+        "{}{}.{} K\r\n",
+        sign,
+        tenths.abs() / 10,
+        tenths.abs() % 10
+    )
+}
+
+/// Write the latest raw ADC conversions.
+#[cfg(feature = "factory")]
+pub fn factory_adc(out: &mut impl Write) -> fmt::Result {
+    let (ntc, vrefint) = factory::adc();
+    write!(out, "adc: ntc {} vrefint {}\r\n", ntc, vrefint)
+}
+
+/// Show the log levels, or set those of one or `all` modules.
+fn log_levels(out: &mut impl Write, module: Option<&str>, level: Option<&str>) -> fmt::Result {
+    let modules = match module {
+        None | Some("all") => &Module::ALL[..],
+        Some(name) => match Module::from_name(name) {
+            Some(module) => &[module][..],
+            None => {
+                return out.write_str("unknown module, expected ntc, motor, sensors or all\r\n");
+            }
+        },
+    };
+    if let Some(level) = level {
+        let Some(level) = Level::from_name(level) else {
+            return out
+                .write_str("unknown level, expected off, error, warn, info, debug or trace\r\n");
+        };
+        for module in modules {
+            log::set_level(*module, level);
+        }
+    }
+    for module in modules {
+        write!(out, "{}: {}\r\n", module.name(), log::level(*module).name())?;
+    }
+    Ok(())
+}
+
+/// Write the current time, if the clock was set.
+#[cfg(feature = "rtc")]
+fn time(out: &mut impl Write) -> fmt::Result {
+    match rtc::now() {
+        Some(seconds) => write!(out, "time: {} UTC\r\n", DateTime::from_seconds(seconds)),
+        None => out.write_str("time: not set\r\n"),
+    }
+}
+
+/// Write the remaining override time, if an override is running.
+fn override_status(out: &mut impl Write) -> fmt::Result {
+    match state::get().override_until {
+        Some(until) => write!(
+            out,
+            "override: {} min left\r\n",
+            until
+                .saturating_duration_since(Instant::now())
+                .as_secs()
+                .div_ceil(60)
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Write the controller state as one `key: value` line per item.
+pub fn status(out: &mut impl Write) -> fmt::Result {
+    let state = state::get();
+    let motor = match state.motor_status {
+        MotorStatus::Off => "off",
+        MotorStatus::Opening => "opening",
+        MotorStatus::Closing => "closing",
+    };
+
+    #[cfg(feature = "rtc")]
+    time(out)?;
+    write!(out, "temperature: {}\r\n", Celsius(state.temperature))?;
+    write!(out, "setpoint: {}\r\n", Celsius(state.setpoint))?;
+    write!(out, "valve: {} %\r\n", state.valve_position)?;
+    #[cfg(feature = "i2c-sensor")]
+    if !state.humidity.is_nan() {
+        write!(out, "humidity: {} %\r\n", state.humidity as u8)?;
+        write!(out, "dew point: {}\r\n", Celsius(state.dew_point))?;
+    }
+    #[cfg(feature = "i2c-sensor")]
+    write!(out, "pipe: {}\r\n", Celsius(state.pipe_temperature))?;
+    #[cfg(feature = "flow")]
+    write!(
+        out,
+        "flow: {}.{:02} l/min\r\n",
+        state.flow / 1000,
+        state.flow % 1000 / 10
+    )?;
+    #[cfg(feature = "energy")]
+    {
+        write!(out, "return: {}\r\n", Celsius(state.return_temperature))?;
+        write!(out, "power: {} W\r\n", state.heat_power)?;
+        write!(
+            out,
+            "energy: {}.{:03} kWh\r\n",
+            state.heat_energy / 1000,
+            state.heat_energy % 1000
+        )?;
+    }
+    write!(out, "motor: {}\r\n", motor)?;
+    #[cfg(feature = "sg-ready")]
+    write!(out, "grid: {}\r\n", state.grid_mode.name())?;
+    write!(
+        out,
+        "mode: {}\r\n",
+        if state.manual { "manual" } else { "auto" }
+    )?;
+    override_status(out)?;
+    if let Some(reason) = lockout::latched() {
+        write!(out, "lockout: {}\r\n", reason.name())?;
+    }
+    #[cfg(feature = "window")]
+    write!(
+        out,
+        "window: {}\r\n",
+        if state.window_open { "open" } else { "closed" }
+    )?;
+    #[cfg(feature = "pump")]
+    write!(
+        out,
+        "pump: {}\r\n",
+        if state.pump_running { "on" } else { "off" }
+    )?;
+    #[cfg(feature = "boiler")]
+    write!(
+        out,
+        "boiler: {}\r\n",
+        if state.boiler_request { "on" } else { "off" }
+    )?;
+    #[cfg(feature = "fan")]
+    write!(out, "fan: {} %\r\n", state.fan_duty)?;
+    #[cfg(feature = "fan-tach")]
+    write!(out, "fan speed: {} rpm\r\n", state.fan_rpm)?;
+    #[cfg(feature = "stages")]
+    for (number, on) in state.stages.iter().enumerate() {
+        write!(
+            out,
+            "stage {}: {}\r\n",
+            number + 1,
+            if *on { "on" } else { "off" }
+        )?;
+    }
+    #[cfg(feature = "power")]
+    {
+        let power = power::estimate();
+        write!(
+            out,
+            "clock: {} MHz, {} peripherals\r\n",
+            power.sysclk / 1_000_000,
+            power.peripherals
+        )?;
+        write!(
+            out,
+            "current: ~{}.{} mA\r\n",
+            power.current / 1000,
+            power.current % 1000 / 100
+        )?;
+    }
+    #[cfg(feature = "stack")]
+    write!(
+        out,
+        "stack: {} of {} bytes\r\n",
+        stack::peak(),
+        stack::size()
+    )?;
+    write!(
+        out,
+        "firmware: {}+{}\r\n",
+        version::VERSION,
+        version::GIT_HASH
+    )?;
+    write!(out, "serial: {:08x}\r\n", identity::serial())
+}
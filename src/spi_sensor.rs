@@ -0,0 +1,270 @@
+//! PT100 or thermocouple frontend on SPI2: PB13 SCK, PB14 MISO, PB15 MOSI,
+//! PB12 CS.
+//!
+//! Either a MAX31865 RTD converter (`max31865`) for a PT100 or a MAX31855
+//! (`max31855`) for a K-type thermocouple, replacing the on-board NTC where
+//! it is out of its range. A fault reported by the converter is decoded and
+//! raises the sensor fault instead of publishing a reading.
+
+use embassy_executor::task;
+use embassy_stm32::gpio::Output;
+use embassy_stm32::mode::Blocking;
+use embassy_stm32::spi::{self, Spi};
+use embassy_time::{Duration, Timer};
+#[cfg(feature = "max31865")]
+use micromath::F32Ext;
+
+use crate::fmt::{info, warn};
+use crate::indicator::{self, Condition};
+use crate::log::log;
+use crate::temperature::{self, TemperatureSource};
+
+const MEASURE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Problems reported by the converters.
+#[derive(Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum Fault {
+    /// The converter does not answer
+    NotResponding,
+    /// Thermocouple or RTD wire broken
+    OpenCircuit,
+    ShortToGround,
+    ShortToSupply,
+    /// RTD resistance above or below the thresholds, also a broken RTD
+    OutOfRange,
+    /// Reference voltage too high
+    Reference,
+    /// Input over or under voltage
+    Voltage,
+}
+
+impl Fault {
+    pub fn name(self) -> &'static str {
+        match self {
+            Fault::NotResponding => "not responding",
+            Fault::OpenCircuit => "open circuit",
+            Fault::ShortToGround => "short to ground",
+            Fault::ShortToSupply => "short to supply",
+            Fault::OutOfRange => "out of range",
+            Fault::Reference => "reference fault",
+            Fault::Voltage => "over or under voltage",
+        }
+    }
+}
+
+/// Temperature converter on the bus.
+pub trait Sensor {
+    const NAME: &'static str;
+    /// Clock polarity and phase the converter expects
+    const MODE: spi::Mode;
+
+    /// Configure the converter, false on error.
+    fn init(&mut self) -> bool;
+    /// Latest temperature or the fault preventing it.
+    fn read(&mut self) -> Result<f32, Fault>;
+}
+
+#[cfg(feature = "max31865")]
+pub struct Max31865 {
+    spi: Spi<'static, Blocking>,
+    cs: Output<'static>,
+}
+
+#[cfg(feature = "max31865")]
+impl Max31865 {
+    /// Reference resistor of the common PT100 boards
+    const R_REF: f32 = 430.0;
+    const R_0: f32 = 100.0;
+    // Callendar-Van Dusen coefficients of IEC 60751
+    const A: f32 = 3.9083e-3;
+    const B: f32 = -5.775e-7;
+    /// The RTD is connected with 3 wires instead of 2 or 4
+    const THREE_WIRE: bool = false;
+
+    const WRITE: u8 = 0x80;
+    // Registers
+    const REG_CONFIG: u8 = 0x00;
+    const REG_RTD: u8 = 0x01;
+    const REG_FAULT_STATUS: u8 = 0x07;
+
+    const FAULT_REFIN_HIGH: u8 = 0x20;
+    /// REFIN- or RTDIN- below 0.85 VBIAS with FORCE- open
+    const FAULT_FORCE_OPEN: u8 = 0x18;
+    const FAULT_VOLTAGE: u8 = 0x04;
+
+    const CONFIG_BIAS: u8 = 0x80;
+    const CONFIG_AUTO: u8 = 0x40;
+    const CONFIG_3WIRE: u8 = 0x10;
+    const CONFIG_FAULT_CLEAR: u8 = 0x02;
+    const CONFIG_50HZ: u8 = 0x01;
+    const CONFIG: u8 = Self::CONFIG_BIAS
+        | Self::CONFIG_AUTO
+        | Self::CONFIG_50HZ
+        | if Self::THREE_WIRE {
+            Self::CONFIG_3WIRE
+        } else {
+            0
+        };
+
+    pub fn new(spi: Spi<'static, Blocking>, cs: Output<'static>) -> Self {
+        Self { spi, cs }
+    }
+
+    fn read_registers(&mut self, register: u8, data: &mut [u8]) -> bool {
+        self.cs.set_low();
+        let ok = self.spi.blocking_write(&[register]).is_ok()
+            && self.spi.blocking_transfer_in_place(data).is_ok();
+        self.cs.set_high();
+        ok
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> bool {
+        self.cs.set_low();
+        let ok = self
+            .spi
+            .blocking_write(&[Self::WRITE | register, value])
+            .is_ok();
+        self.cs.set_high();
+        ok
+    }
+
+    /// Decode the fault status register, most specific first.
+    fn fault(status: u8) -> Fault {
+        if status & Self::FAULT_FORCE_OPEN != 0 {
+            Fault::OpenCircuit
+        } else if status & Self::FAULT_REFIN_HIGH != 0 {
+            Fault::Reference
+        } else if status & Self::FAULT_VOLTAGE != 0 {
+            Fault::Voltage
+        } else {
+            Fault::OutOfRange
+        }
+    }
+
+    /// Temperature of a PT100 with resistance `r`, exact above 0 °C.
+    fn temperature(r: f32) -> f32 {
+        let discriminant = Self::A * Self::A - 4.0 * Self::B * (1.0 - r / Self::R_0);
+        (-Self::A + discriminant.sqrt()) / (2.0 * Self::B)
+    }
+}
+
+#[cfg(feature = "max31865")]
+impl Sensor for Max31865 {
+    const NAME: &'static str = "MAX31865";
+    const MODE: spi::Mode = spi::MODE_1;
+
+    fn init(&mut self) -> bool {
+        let mut config = [0u8];
+        self.write_register(Self::REG_CONFIG, Self::CONFIG | Self::CONFIG_FAULT_CLEAR)
+            && self.read_registers(Self::REG_CONFIG, &mut config)
+            && config[0] == Self::CONFIG
+    }
+
+    fn read(&mut self) -> Result<f32, Fault> {
+        let mut rtd = [0u8; 2];
+        if !self.read_registers(Self::REG_RTD, &mut rtd) {
+            return Err(Fault::NotResponding);
+        }
+
+        let rtd = u16::from_be_bytes(rtd);
+        // Fault flag in the lowest bit, the status tells which one
+        if rtd & 1 != 0 {
+            let mut status = [0u8];
+            self.read_registers(Self::REG_FAULT_STATUS, &mut status);
+            self.write_register(Self::REG_CONFIG, Self::CONFIG | Self::CONFIG_FAULT_CLEAR);
+            return Err(Self::fault(status[0]));
+        }
+
+        let r = f32::from(rtd >> 1) * Self::R_REF / 32768.0;
+        Ok(Self::temperature(r))
+    }
+}
+
+#[cfg(feature = "max31855")]
+pub struct Max31855 {
+    spi: Spi<'static, Blocking>,
+    cs: Output<'static>,
+}
+
+#[cfg(feature = "max31855")]
+impl Max31855 {
+    const FAULT: u32 = 1 << 16;
+    const OPEN_CIRCUIT: u32 = 1 << 0;
+    const SHORT_TO_GROUND: u32 = 1 << 1;
+
+    pub fn new(spi: Spi<'static, Blocking>, cs: Output<'static>) -> Self {
+        Self { spi, cs }
+    }
+}
+
+#[cfg(feature = "max31855")]
+impl Sensor for Max31855 {
+    const NAME: &'static str = "MAX31855";
+    const MODE: spi::Mode = spi::MODE_0;
+
+    fn init(&mut self) -> bool {
+        // Read only, converting continuously from power up
+        true
+    }
+
+    fn read(&mut self) -> Result<f32, Fault> {
+        let mut data = [0u8; 4];
+        self.cs.set_low();
+        let ok = self.spi.blocking_transfer_in_place(&mut data).is_ok();
+        self.cs.set_high();
+
+        let data = u32::from_be_bytes(data);
+        // A missing converter reads as all zeros or all ones
+        if !ok || data == 0 || data == u32::MAX {
+            return Err(Fault::NotResponding);
+        }
+        if data & Self::FAULT != 0 {
+            return Err(if data & Self::OPEN_CIRCUIT != 0 {
+                Fault::OpenCircuit
+            } else if data & Self::SHORT_TO_GROUND != 0 {
+                Fault::ShortToGround
+            } else {
+                // The remaining fault bit
+                Fault::ShortToSupply
+            });
+        }
+
+        // Signed 14 bits in 0.25 °C at the top
+        Ok((data as i32 >> 18) as f32 * 0.25)
+    }
+}
+
+#[cfg(feature = "max31865")]
+pub type Device = Max31865;
+#[cfg(feature = "max31855")]
+pub type Device = Max31855;
+
+#[task]
+pub async fn spi_sensor(mut sensor: Device) {
+    while !sensor.init() {
+        warn!("{}: not responding", Device::NAME);
+        Timer::after(MEASURE_INTERVAL).await;
+    }
+
+    info!("Starting {} temperature sensor", Device::NAME);
+    let mut fault = None;
+    loop {
+        match sensor.read() {
+            Ok(temperature) => {
+                log!(Sensors, trace, "{}: {} C", Device::NAME, temperature);
+                fault = None;
+                temperature::publish(TemperatureSource::Spi, temperature);
+            }
+            Err(error) => {
+                if fault != Some(error) {
+                    warn!("{}: {}", Device::NAME, error.name());
+                    indicator::set(Condition::SensorFault, true);
+                }
+                fault = Some(error);
+            }
+        }
+
+        Timer::after(MEASURE_INTERVAL).await;
+    }
+}
@@ -0,0 +1,272 @@
+//! 128x64 SSD1306 OLED status display on I2C1: PB6 SCL, PB7 SDA.
+//!
+//! The frame is drawn into a RAM buffer and sent as a whole. It shows the
+//! temperature, setpoint, a valve position bar, the operating mode and fault
+//! icons, or the menu when the buttons or encoder are present.
+
+use core::fmt::Write;
+
+use embassy_stm32::i2c::{I2c, Master};
+use embassy_stm32::mode::Blocking;
+use heapless::String;
+
+use crate::display::{self, Backend};
+use crate::indicator::{Condition, Status};
+#[cfg(feature = "input")]
+use crate::menu::ITEMS;
+use crate::state;
+use crate::temperature::Celsius;
+
+const ADDRESS: u8 = 0x3C;
+const WIDTH: usize = 128;
+const PAGES: usize = 8;
+
+// Control bytes
+const CONTROL_COMMAND: u8 = 0x00;
+const CONTROL_DATA: u8 = 0x40;
+
+const INIT: [u8; 25] = [
+    0xAE, // Display off
+    0xD5, 0x80, // Clock divider
+    0xA8, 0x3F, // Multiplex ratio, 64 lines
+    0xD3, 0x00, // No display offset
+    0x40, // Start line 0
+    0x8D, 0x14, // Charge pump on
+    0x20, 0x00, // Horizontal addressing
+    0xA1, // Segment remap
+    0xC8, // COM scan direction remapped
+    0xDA, 0x12, // COM pins configuration
+    0x81, 0xCF, // Contrast
+    0xD9, 0xF1, // Precharge period
+    0xDB, 0x40, // VCOMH deselect level
+    0xA4, // Display from RAM
+    0xA6, // Normal, not inverted
+    0xAF, // Display on
+];
+const SET_WINDOW: [u8; 6] = [0x21, 0, WIDTH as u8 - 1, 0x22, 0, PAGES as u8 - 1];
+
+/// 5x7 font from ' ' to 'Z', one byte per column with the top row in bit 0
+const FONT: [[u8; 5]; 59] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // #
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // )
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], // *
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // 0
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x00, 0x08, 0x14, 0x22, 0x41], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x41, 0x22, 0x14, 0x08, 0x00], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // @
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // A
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // D
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7F, 0x09, 0x09, 0x01, 0x01], // F
+    [0x3E, 0x41, 0x41, 0x51, 0x32], // G
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // H
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // J
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7F, 0x02, 0x04, 0x02, 0x7F], // M
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // N
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // Q
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // T
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // V
+    [0x7F, 0x20, 0x18, 0x20, 0x7F], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x03, 0x04, 0x78, 0x04, 0x03], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+];
+
+// 8x8 fault icons
+const ICON_SENSOR_FAULT: [u8; 8] = [0x60, 0x58, 0x46, 0x5D, 0x46, 0x58, 0x60, 0x00];
+#[cfg(feature = "window")]
+const ICON_WINDOW_OPEN: [u8; 8] = [0x7F, 0x49, 0x49, 0x7F, 0x49, 0x49, 0x7F, 0x00];
+
+pub struct Ssd1306 {
+    i2c: I2c<'static, Blocking, Master>,
+    buffer: [[u8; WIDTH]; PAGES],
+}
+
+impl Ssd1306 {
+    pub fn new(i2c: I2c<'static, Blocking, Master>) -> Self {
+        Self {
+            i2c,
+            buffer: [[0; WIDTH]; PAGES],
+        }
+    }
+
+    fn commands(&mut self, commands: &[u8]) -> bool {
+        let mut frame = [CONTROL_COMMAND; INIT.len() + 1];
+        frame[1..=commands.len()].copy_from_slice(commands);
+        self.i2c
+            .blocking_write(ADDRESS, &frame[..=commands.len()])
+            .is_ok()
+    }
+
+    fn clear(&mut self) {
+        self.buffer = [[0; WIDTH]; PAGES];
+    }
+
+    fn glyph(c: char) -> &'static [u8; 5] {
+        let index = (c.to_ascii_uppercase() as usize).wrapping_sub(' ' as usize);
+        FONT.get(index).unwrap_or(&FONT[0])
+    }
+
+    /// Draw `text` starting at column `x` of `page`, clipped at the edge.
+    fn text(&mut self, x: usize, page: usize, text: &str) {
+        let mut x = x;
+        for c in text.chars() {
+            for column in Self::glyph(c).iter().chain(&[0]) {
+                if x < WIDTH {
+                    self.buffer[page][x] = *column;
+                }
+                x += 1;
+            }
+        }
+    }
+
+    /// Draw `text` at double size over `page` and the one below.
+    fn large_text(&mut self, x: usize, page: usize, text: &str) {
+        let mut x = x;
+        for c in text.chars() {
+            for column in Self::glyph(c).iter().chain(&[0]) {
+                // Double every bit of the column into the two pages
+                let mut wide = 0u16;
+                for bit in 0..8 {
+                    if column & (1 << bit) != 0 {
+                        wide |= 0b11 << (2 * bit);
+                    }
+                }
+                for _ in 0..2 {
+                    if x < WIDTH {
+                        self.buffer[page][x] = wide as u8;
+                        self.buffer[page + 1][x] = (wide >> 8) as u8;
+                    }
+                    x += 1;
+                }
+            }
+        }
+    }
+
+    fn icon(&mut self, x: usize, page: usize, icon: &[u8; 8]) {
+        self.buffer[page][x..x + 8].copy_from_slice(icon);
+    }
+
+    /// Horizontal bar over the full width, filled to `percent`.
+    fn bar(&mut self, page: usize, percent: u8) {
+        let filled = usize::from(percent.min(100)) * (WIDTH - 2) / 100;
+        for (x, column) in self.buffer[page].iter_mut().enumerate() {
+            *column = match x {
+                0 | 127 => 0x7E,
+                _ if x <= filled => 0x7E,
+                _ => 0x42,
+            };
+        }
+    }
+}
+
+impl Backend for Ssd1306 {
+    const NAME: &'static str = "SSD1306";
+
+    fn init(&mut self) -> bool {
+        self.commands(&INIT)
+    }
+
+    fn draw_status(&mut self, status: Status) {
+        let state = state::get();
+        let mut line: String<24> = String::new();
+
+        self.clear();
+        match status.fault_code() {
+            Some(code) => {
+                let _ = write!(line, "FAULT {}", code);
+                self.text(0, 0, &line);
+                line.clear();
+            }
+            None => self.text(0, 0, display::mode()),
+        }
+        #[cfg(feature = "manual")]
+        if state.override_until.is_some() {
+            self.text(56, 0, "OVR");
+        }
+        if status.is(Condition::SensorFault) {
+            self.icon(WIDTH - 8, 0, &ICON_SENSOR_FAULT);
+        }
+        #[cfg(feature = "window")]
+        if state.window_open {
+            self.icon(WIDTH - 18, 0, &ICON_WINDOW_OPEN);
+        }
+
+        let _ = write!(line, "{}", Celsius(state.temperature));
+        self.large_text(0, 2, &line);
+
+        line.clear();
+        let _ = write!(line, "SET {}", Celsius(state.target_setpoint()));
+        self.text(0, 5, &line);
+
+        line.clear();
+        let _ = write!(line, "VALVE {} %", state.valve_position);
+        self.text(0, 6, &line);
+        self.bar(7, state.valve_position);
+    }
+
+    #[cfg(feature = "input")]
+    fn draw_menu(&mut self, selected: usize) {
+        self.clear();
+        self.text(0, 0, "MENU");
+        for (index, item) in ITEMS.iter().enumerate() {
+            let page = 2 + index;
+            if index == selected {
+                self.text(0, page, ">");
+            }
+            self.text(12, page, item.label());
+        }
+    }
+
+    /// Send the whole buffer to the display.
+    fn flush(&mut self) -> bool {
+        if !self.commands(&SET_WINDOW) {
+            return false;
+        }
+
+        let mut frame = [CONTROL_DATA; 33];
+        for chunk in self.buffer.as_flattened().chunks(32) {
+            frame[1..].copy_from_slice(chunk);
+            if self.i2c.blocking_write(ADDRESS, &frame).is_err() {
+                return false;
+            }
+        }
+        true
+    }
+}
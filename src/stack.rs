@@ -0,0 +1,83 @@
+//! Stack high-water mark.
+//!
+//! The tasks keep their state in statically allocated futures, so they all
+//! run on the one main stack between the end of the static data and the top
+//! of RAM. [`paint`] fills the unused part with a pattern at boot, the lowest
+//! overwritten word is the deepest the stack ever got. The monitor task logs
+//! each new peak and warns once less than [`WARN_FREE`] bytes are left.
+
+use core::ptr::{addr_of, read_volatile, write_volatile};
+
+use embassy_executor::task;
+use embassy_time::{Duration, Timer};
+
+use crate::fmt::{info, warn};
+
+const PAINT: u32 = 0xCAFE_F00D;
+/// Left unpainted below the stack pointer for the painting itself
+const MARGIN: usize = 64;
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const WARN_FREE: u32 = 1024;
+
+unsafe extern "C" {
+    /// Top of the stack, the end of RAM
+    static _stack_start: u32;
+    /// Lowest address the stack may reach, the end of the static data
+    static _stack_end: u32;
+}
+
+fn bottom() -> *mut u32 {
+    addr_of!(_stack_end) as *mut u32
+}
+
+fn top() -> *mut u32 {
+    addr_of!(_stack_start) as *mut u32
+}
+
+/// Stack size in bytes.
+pub fn size() -> u32 {
+    top() as u32 - bottom() as u32
+}
+
+/// Fill the stack below the current stack pointer with the pattern, first
+/// thing at boot.
+pub fn paint() {
+    let end = cortex_m::register::msp::read() as usize - MARGIN;
+    let mut word = bottom();
+    while (word as usize) < end {
+        // Safety: unused stack between the static data and the stack pointer
+        unsafe {
+            write_volatile(word, PAINT);
+            word = word.add(1);
+        }
+    }
+}
+
+/// Most stack used so far in bytes.
+pub fn peak() -> u32 {
+    let mut word = bottom();
+    // Safety: reads within the stack, stopping at the stack pointer at worst
+    while word < top() && unsafe { read_volatile(word) } == PAINT {
+        word = unsafe { word.add(1) };
+    }
+    top() as u32 - word as u32
+}
+
+#[task]
+pub async fn monitor() {
+    let size = size();
+    let mut reported = 0;
+    loop {
+        let peak = peak();
+        if peak > reported {
+            reported = peak;
+            if size - peak < WARN_FREE {
+                warn!("Stack: {} of {} bytes used", peak, size);
+            } else {
+                info!("Stack: {} of {} bytes used", peak, size);
+            }
+        }
+
+        Timer::after(CHECK_INTERVAL).await;
+    }
+}
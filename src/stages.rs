@@ -0,0 +1,89 @@
+//! Two-stage heat demand outputs on PB11 (stage 1) and PB12 (stage 2),
+//! active high, for systems with a second heat source.
+//!
+//! Stage 1 comes on when the temperature falls [`ON_ERROR`] below the
+//! setpoint and goes off when the setpoint is reached. Stage 2 only follows
+//! when stage 1 alone could not close the gap within [`STAGE_2_DELAY`], and
+//! drops out again at half the error. Each output keeps its state for at
+//! least [`MIN_RUN`] or [`MIN_REST`] to spare the burners and contactors.
+
+use embassy_executor::task;
+use embassy_stm32::gpio::Output;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::fmt::info;
+use crate::state;
+
+const ON_ERROR: f32 = 1.0;
+const STAGE_2_DELAY: Duration = Duration::from_secs(20 * 60);
+const MIN_RUN: Duration = Duration::from_secs(5 * 60);
+const MIN_REST: Duration = Duration::from_secs(5 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Stage {
+    number: u8,
+    output: Output<'static>,
+    on: bool,
+    changed_at: Instant,
+}
+
+impl Stage {
+    fn new(number: u8, output: Output<'static>) -> Self {
+        Self {
+            number,
+            output,
+            on: false,
+            // Free to start right away
+            changed_at: Instant::MIN,
+        }
+    }
+
+    /// Time since the last switch.
+    fn since(&self) -> Duration {
+        Instant::now().duration_since(self.changed_at)
+    }
+
+    /// Switch the output unless it has to keep its state a bit longer.
+    fn switch(&mut self, on: bool) {
+        let minimum = if self.on { MIN_RUN } else { MIN_REST };
+        if on == self.on || self.since() < minimum {
+            return;
+        }
+
+        info!("Stage {} {}", self.number, if on { "on" } else { "off" });
+        self.output.set_level(on.into());
+        self.on = on;
+        self.changed_at = Instant::now();
+    }
+}
+
+#[task]
+pub async fn stages(output_1: Output<'static>, output_2: Output<'static>) {
+    info!("Starting two-stage heat demand");
+    let mut stage_1 = Stage::new(1, output_1);
+    let mut stage_2 = Stage::new(2, output_2);
+
+    loop {
+        let state = state::get();
+        // NaN without a reading, making every comparison below false
+        let error = state.target_setpoint() - state.temperature;
+        let blocked = state::heating_paused() || error.is_nan();
+
+        let want_1 = if blocked || error <= 0.0 {
+            false
+        } else {
+            stage_1.on || error >= ON_ERROR
+        };
+        stage_1.switch(want_1);
+
+        let want_2 = if !stage_1.on || error <= ON_ERROR / 2.0 {
+            false
+        } else {
+            stage_2.on || (error >= ON_ERROR && stage_1.since() >= STAGE_2_DELAY)
+        };
+        stage_2.switch(want_2);
+
+        state::update(|s| s.stages = [stage_1.on, stage_2.on]);
+        Timer::after(CHECK_INTERVAL).await;
+    }
+}
@@ -0,0 +1,181 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+#[cfg(feature = "manual")]
+use embassy_time::Instant;
+
+use crate::motor_control::MotorStatus;
+#[cfg(feature = "sg-ready")]
+use crate::sg_ready::GridMode;
+use crate::temperature::CONTROL_SOURCE;
+
+#[cfg(feature = "remote")]
+pub const SETPOINT_MIN: f32 = 5.0;
+#[cfg(feature = "remote")]
+pub const SETPOINT_MAX: f32 = 80.0;
+
+/// Snapshot of the controller state shared with the communication interfaces.
+#[derive(Clone, Copy)]
+pub struct SystemState {
+    pub temperature: f32,
+    pub setpoint: f32,
+    pub valve_position: u8, // Estimated opening in %
+    /// Relative room humidity in %
+    #[cfg(feature = "i2c-sensor")]
+    pub humidity: f32,
+    /// Dew point of the room air
+    #[cfg(feature = "i2c-sensor")]
+    pub dew_point: f32,
+    /// On-board NTC reading while the room sensor regulates
+    #[cfg(feature = "i2c-sensor")]
+    pub pipe_temperature: f32,
+    /// Flow through the circuit in ml/min
+    #[cfg(feature = "flow")]
+    pub flow: u32,
+    #[cfg(feature = "energy")]
+    pub return_temperature: f32,
+    /// Delivered heat power in W
+    #[cfg(feature = "energy")]
+    pub heat_power: u32,
+    /// Delivered heat energy in Wh
+    #[cfg(feature = "energy")]
+    pub heat_energy: u32,
+    /// Opening in % requested by an external controller, overrides regulation
+    #[cfg(feature = "demand")]
+    pub valve_demand: Option<u8>,
+    pub motor_status: MotorStatus,
+    #[cfg(feature = "sg-ready")]
+    pub grid_mode: GridMode,
+    /// Window open long enough to pause the heating
+    #[cfg(feature = "window")]
+    pub window_open: bool,
+    #[cfg(feature = "pump")]
+    pub pump_running: bool,
+    #[cfg(feature = "boiler")]
+    pub boiler_request: bool,
+    /// Fan duty cycle in %
+    #[cfg(feature = "fan")]
+    pub fan_duty: u8,
+    #[cfg(feature = "fan-tach")]
+    pub fan_rpm: u16,
+    /// Heat demand outputs of the two stages
+    #[cfg(feature = "stages")]
+    pub stages: [bool; 2],
+    /// Regulation suspended, the valve is moved by the user
+    #[cfg(feature = "manual")]
+    pub manual: bool,
+    /// End of the running manual override
+    #[cfg(feature = "manual")]
+    pub override_until: Option<Instant>,
+    /// Setpoint to restore when a setpoint override ends
+    #[cfg(feature = "manual")]
+    pub saved_setpoint: Option<f32>,
+}
+
+impl SystemState {
+    /// Setpoint the regulation works towards, after the grid mode shift.
+    pub fn target_setpoint(&self) -> f32 {
+        #[cfg(feature = "sg-ready")]
+        return self.setpoint + self.grid_mode.offset();
+        #[cfg(not(feature = "sg-ready"))]
+        self.setpoint
+    }
+}
+
+static STATE: Mutex<CriticalSectionRawMutex, RefCell<SystemState>> =
+    Mutex::new(RefCell::new(SystemState {
+        temperature: f32::NAN,
+        setpoint: CONTROL_SOURCE.default_setpoint(),
+        valve_position: 0,
+        #[cfg(feature = "i2c-sensor")]
+        humidity: f32::NAN,
+        #[cfg(feature = "i2c-sensor")]
+        dew_point: f32::NAN,
+        #[cfg(feature = "i2c-sensor")]
+        pipe_temperature: f32::NAN,
+        #[cfg(feature = "flow")]
+        flow: 0,
+        #[cfg(feature = "energy")]
+        return_temperature: f32::NAN,
+        #[cfg(feature = "energy")]
+        heat_power: 0,
+        #[cfg(feature = "energy")]
+        heat_energy: 0,
+        #[cfg(feature = "demand")]
+        valve_demand: None,
+        motor_status: MotorStatus::Off,
+        #[cfg(feature = "sg-ready")]
+        grid_mode: GridMode::Normal,
+        #[cfg(feature = "window")]
+        window_open: false,
+        #[cfg(feature = "pump")]
+        pump_running: false,
+        #[cfg(feature = "boiler")]
+        boiler_request: false,
+        #[cfg(feature = "fan")]
+        fan_duty: 0,
+        #[cfg(feature = "fan-tach")]
+        fan_rpm: 0,
+        #[cfg(feature = "stages")]
+        stages: [false; 2],
+        #[cfg(feature = "manual")]
+        manual: false,
+        #[cfg(feature = "manual")]
+        override_until: None,
+        #[cfg(feature = "manual")]
+        saved_setpoint: None,
+    }));
+
+pub fn get() -> SystemState {
+    STATE.lock(|state| *state.borrow())
+}
+
+pub fn update(f: impl FnOnce(&mut SystemState)) {
+    STATE.lock(|state| f(&mut state.borrow_mut()));
+}
+
+/// Valve opening requested by an external controller, if any.
+pub fn valve_demand() -> Option<u8> {
+    #[cfg(feature = "demand")]
+    return get().valve_demand;
+    #[cfg(not(feature = "demand"))]
+    None
+}
+
+/// Whether the heating is paused with the valve closed.
+pub fn heating_paused() -> bool {
+    #[cfg(feature = "window")]
+    return get().window_open;
+    #[cfg(not(feature = "window"))]
+    false
+}
+
+/// Whether the valve must not open further to avoid condensation.
+pub fn condensation_risk() -> bool {
+    #[cfg(feature = "i2c-sensor")]
+    return crate::dew_point::risk(&get());
+    #[cfg(not(feature = "i2c-sensor"))]
+    false
+}
+
+/// Change the regulation setpoint, rejecting values outside the allowed range.
+#[cfg(feature = "remote")]
+pub fn set_setpoint(setpoint: f32) -> bool {
+    if !(SETPOINT_MIN..=SETPOINT_MAX).contains(&setpoint) {
+        return false;
+    }
+
+    update(|state| state.setpoint = setpoint);
+    true
+}
+
+/// Encode a temperature in tenths of a degree, NaN maps to `i16::MIN`.
+#[cfg(any(feature = "ble", feature = "lora"))]
+pub fn deci_degrees(value: f32) -> i16 {
+    if value.is_nan() {
+        i16::MIN
+    } else {
+        (value * 10.0) as i16
+    }
+}
@@ -0,0 +1,98 @@
+#[cfg(any(feature = "shell", feature = "display"))]
+use core::fmt::{self, Display};
+
+use crate::SIGNAL_TEMPERATURE;
+use crate::config::{MAX_TEMPERATURE, OVERTEMPERATURE_MARGIN, TEMP_HYSTERESIS};
+#[cfg(any(feature = "nrf24", feature = "i2c-sensor"))]
+use crate::config::{ROOM_HYSTERESIS, ROOM_SETPOINT};
+use crate::indicator::{self, Condition};
+use crate::state;
+
+/// Sensors able to publish a temperature reading.
+#[derive(PartialEq, Clone, Copy)]
+pub enum TemperatureSource {
+    /// On-board NTC measuring the pipe temperature
+    Ntc,
+    /// Battery powered room sensor received over nRF24L01
+    #[cfg(feature = "nrf24")]
+    Remote,
+    /// SHT3x or BME280 room sensor on I2C
+    #[cfg(feature = "i2c-sensor")]
+    I2c,
+    /// PT100 or thermocouple on SPI measuring the pipe temperature
+    #[cfg(feature = "spi-sensor")]
+    Spi,
+}
+
+/// Source the motor control regulates on.
+#[cfg(not(any(feature = "nrf24", feature = "i2c-sensor", feature = "spi-sensor")))]
+pub const CONTROL_SOURCE: TemperatureSource = TemperatureSource::Ntc;
+#[cfg(feature = "nrf24")]
+pub const CONTROL_SOURCE: TemperatureSource = TemperatureSource::Remote;
+#[cfg(feature = "i2c-sensor")]
+pub const CONTROL_SOURCE: TemperatureSource = TemperatureSource::I2c;
+#[cfg(feature = "spi-sensor")]
+pub const CONTROL_SOURCE: TemperatureSource = TemperatureSource::Spi;
+
+impl TemperatureSource {
+    pub const fn default_setpoint(self) -> f32 {
+        match self {
+            TemperatureSource::Ntc => MAX_TEMPERATURE,
+            #[cfg(feature = "nrf24")]
+            TemperatureSource::Remote => ROOM_SETPOINT,
+            #[cfg(feature = "i2c-sensor")]
+            TemperatureSource::I2c => ROOM_SETPOINT,
+            #[cfg(feature = "spi-sensor")]
+            TemperatureSource::Spi => MAX_TEMPERATURE,
+        }
+    }
+
+    pub const fn hysteresis(self) -> f32 {
+        match self {
+            TemperatureSource::Ntc => TEMP_HYSTERESIS,
+            #[cfg(feature = "nrf24")]
+            TemperatureSource::Remote => ROOM_HYSTERESIS,
+            #[cfg(feature = "i2c-sensor")]
+            TemperatureSource::I2c => ROOM_HYSTERESIS,
+            #[cfg(feature = "spi-sensor")]
+            TemperatureSource::Spi => TEMP_HYSTERESIS,
+        }
+    }
+}
+
+/// Publish a new reading, forwarding it to the motor control if it comes
+/// from the regulation source.
+pub fn publish(source: TemperatureSource, temperature: f32) {
+    #[cfg(feature = "i2c-sensor")]
+    if source == TemperatureSource::Ntc {
+        state::update(|s| s.pipe_temperature = temperature);
+    }
+    if source != CONTROL_SOURCE {
+        return;
+    }
+
+    SIGNAL_TEMPERATURE.signal(temperature);
+    indicator::set(Condition::SensorFault, false);
+    indicator::set(
+        Condition::Overtemperature,
+        temperature > state::get().target_setpoint() + OVERTEMPERATURE_MARGIN,
+    );
+    state::update(|s| s.temperature = temperature);
+}
+
+/// Temperature printed with one decimal without pulling in float formatting.
+#[cfg(any(feature = "shell", feature = "display"))]
+pub struct Celsius(pub f32);
+
+#[cfg(any(feature = "shell", feature = "display"))]
+impl Display for Celsius {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_nan() {
+            return f.write_str("-- C");
+        }
+
+        let tenths = (self.0 * 10.0) as i32;
+        let sign = if tenths < 0 { "-" } else { "" };
+        write!(f, "{}{}.{} C", sign, tenths.abs() / 10, tenths.abs() % 10)
+    }
+}
@@ -0,0 +1,174 @@
+//! Four digit TM1637 seven-segment display on PB6 CLK and PB7 DIO.
+//!
+//! Shows the temperature with one decimal, or `Er` and the fault code. After
+//! a setpoint change the setpoint blinks for [`SETPOINT_TIME`] instead, so it
+//! can be adjusted with the buttons or encoder without a full display.
+
+use embassy_stm32::gpio::OutputOpenDrain;
+use embassy_time::{Duration, Instant, block_for};
+
+use crate::fmt::warn;
+use crate::indicator::{Status, StatusIndicator};
+use crate::state;
+
+const SETPOINT_TIME: Duration = Duration::from_secs(3);
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+const BIT_DELAY: Duration = Duration::from_micros(5);
+/// Brightness 0 - 7
+const BRIGHTNESS: u8 = 4;
+
+// Commands
+const DATA_AUTO_INCREMENT: u8 = 0x40;
+const ADDRESS_FIRST: u8 = 0xC0;
+const DISPLAY_ON: u8 = 0x88;
+
+const DIGITS: [u8; 10] = [0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F];
+const MINUS: u8 = 0x40;
+const DECIMAL_POINT: u8 = 0x80;
+const LETTER_E: u8 = 0x79;
+const LETTER_R: u8 = 0x50;
+
+pub struct Tm1637 {
+    clk: OutputOpenDrain<'static>,
+    dio: OutputOpenDrain<'static>,
+}
+
+impl Tm1637 {
+    pub fn new(clk: OutputOpenDrain<'static>, dio: OutputOpenDrain<'static>) -> Self {
+        Self { clk, dio }
+    }
+
+    fn start(&mut self) {
+        self.dio.set_low();
+        block_for(BIT_DELAY);
+    }
+
+    fn stop(&mut self) {
+        self.clk.set_low();
+        self.dio.set_low();
+        block_for(BIT_DELAY);
+        self.clk.set_high();
+        block_for(BIT_DELAY);
+        self.dio.set_high();
+        block_for(BIT_DELAY);
+    }
+
+    /// Send a byte LSB first, returns whether the TM1637 acknowledged it.
+    fn write_byte(&mut self, byte: u8) -> bool {
+        for bit in 0..8 {
+            self.clk.set_low();
+            self.dio.set_level((byte & (1 << bit) != 0).into());
+            block_for(BIT_DELAY);
+            self.clk.set_high();
+            block_for(BIT_DELAY);
+        }
+
+        // The TM1637 pulls DIO low during the ninth clock
+        self.clk.set_low();
+        self.dio.set_high();
+        block_for(BIT_DELAY);
+        self.clk.set_high();
+        block_for(BIT_DELAY);
+        let ack = self.dio.is_low();
+        self.clk.set_low();
+        ack
+    }
+
+    fn command(&mut self, bytes: &[u8]) -> bool {
+        self.start();
+        let ack = bytes.iter().all(|byte| self.write_byte(*byte));
+        self.stop();
+        ack
+    }
+
+    /// Show the segment patterns, leftmost digit first.
+    pub fn show(&mut self, segments: [u8; 4]) -> bool {
+        let mut data = [ADDRESS_FIRST, 0, 0, 0, 0];
+        data[1..].copy_from_slice(&segments);
+        self.command(&[DATA_AUTO_INCREMENT])
+            && self.command(&data)
+            && self.command(&[DISPLAY_ON | BRIGHTNESS])
+    }
+}
+
+/// Segments for a temperature with one decimal, dashes if it is not known.
+fn temperature_segments(value: f32) -> [u8; 4] {
+    let tenths = (value * 10.0) as i32;
+    if value.is_nan() || !(-99..=999).contains(&tenths) {
+        return [MINUS; 4];
+    }
+
+    let digits = tenths.unsigned_abs() as usize;
+    let mut segments = [
+        0,
+        DIGITS[digits / 100 % 10],
+        DIGITS[digits / 10 % 10] | DECIMAL_POINT,
+        DIGITS[digits % 10],
+    ];
+    if digits < 100 {
+        segments[1] = 0;
+    }
+    if tenths < 0 {
+        segments[if digits < 100 { 1 } else { 0 }] = MINUS;
+    }
+    segments
+}
+
+pub struct Readout {
+    display: Tm1637,
+    status: Status,
+    last_setpoint: f32,
+    setpoint_since: Instant,
+    blink: bool,
+    responding: bool,
+}
+
+impl Readout {
+    pub fn new(display: Tm1637) -> Self {
+        Self {
+            display,
+            status: Status::default(),
+            last_setpoint: state::get().setpoint,
+            setpoint_since: Instant::MIN,
+            blink: false,
+            responding: true,
+        }
+    }
+}
+
+impl StatusIndicator for Readout {
+    const NAME: &'static str = "TM1637";
+
+    fn update(&mut self, status: Status) {
+        self.status = status;
+    }
+
+    fn tick(&mut self) -> Instant {
+        let state = state::get();
+        if state.setpoint != self.last_setpoint {
+            self.last_setpoint = state.setpoint;
+            self.setpoint_since = Instant::now();
+        }
+
+        self.blink = !self.blink;
+        let segments = if self.setpoint_since.elapsed() < SETPOINT_TIME {
+            if self.blink {
+                temperature_segments(state.setpoint)
+            } else {
+                [0; 4]
+            }
+        } else if let Some(code) = self.status.fault_code() {
+            [LETTER_E, LETTER_R, 0, DIGITS[usize::from(code % 10)]]
+        } else {
+            temperature_segments(state.temperature)
+        };
+
+        let ack = self.display.show(segments);
+        if self.responding && !ack {
+            warn!("TM1637: no response");
+        }
+        self.responding = ack;
+
+        Instant::now() + REFRESH_INTERVAL
+    }
+}
@@ -0,0 +1,147 @@
+//! Shell and telemetry over a USB CDC-ACM virtual serial port.
+
+use core::fmt::Write;
+
+use embassy_executor::task;
+use embassy_futures::join::join;
+use embassy_futures::select::{Either, select};
+use embassy_stm32::Peri;
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::peripherals::USB;
+use embassy_stm32::usb::Driver;
+use embassy_time::{Duration, Timer};
+use embassy_usb::Builder;
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_usb::driver::EndpointError;
+use heapless::String;
+
+use crate::board::{UsbDmPin, UsbDpPin};
+#[cfg(feature = "factory")]
+use crate::factory;
+use crate::fmt::info;
+use crate::shell::{self, Action, Shell};
+use crate::{Irqs, bootloader, identity};
+
+const VID: u16 = 0x1209; // pid.codes
+const PID: u16 = 0x0001; // pid.codes test PID
+const MAX_PACKET_SIZE: usize = 64;
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+type Class<'d> = CdcAcmClass<'d, Driver<'d, USB>>;
+
+async fn write_all(class: &mut Class<'_>, data: &[u8]) -> Result<(), EndpointError> {
+    for chunk in data.chunks(MAX_PACKET_SIZE) {
+        class.write_packet(chunk).await?;
+    }
+
+    // Terminate transfers that end on a packet boundary
+    if data.len().is_multiple_of(MAX_PACKET_SIZE) && !data.is_empty() {
+        class.write_packet(&[]).await?;
+    }
+    Ok(())
+}
+
+async fn session(class: &mut Class<'_>) -> Result<(), EndpointError> {
+    let mut shell = Shell::new();
+    let mut packet = [0u8; MAX_PACKET_SIZE];
+    let mut out: String<512> = String::new();
+
+    write_all(class, shell::PROMPT.as_bytes()).await?;
+
+    loop {
+        match select(
+            class.read_packet(&mut packet),
+            Timer::after(TELEMETRY_INTERVAL),
+        )
+        .await
+        {
+            Either::First(len) => {
+                for byte in &packet[..len?] {
+                    match shell.feed(*byte, &mut out) {
+                        Some(Action::EnterBootloader) => {
+                            write_all(class, out.as_bytes()).await?;
+                            bootloader::enter().await;
+                        }
+                        #[cfg(feature = "factory")]
+                        Some(Action::EnterFactory) => {
+                            write_all(class, out.as_bytes()).await?;
+                            out.clear();
+                            bootloader::safe_state().await;
+                            factory::activate();
+                            let _ = out.push_str(shell::PROMPT);
+                        }
+                        None => {}
+                    }
+                }
+            }
+            Either::Second(()) => {
+                if shell.telemetry() {
+                    let _ = shell::status(&mut out);
+                }
+                #[cfg(feature = "factory")]
+                if factory::streaming() {
+                    let _ = shell::factory_adc(&mut out);
+                }
+            }
+        }
+
+        write_all(class, out.as_bytes()).await?;
+        out.clear();
+    }
+}
+
+#[task]
+pub async fn usb(
+    usb: Peri<'static, USB>,
+    mut dp: Peri<'static, UsbDpPin>,
+    dm: Peri<'static, UsbDmPin>,
+) {
+    // The D+ pull-up is fixed on most boards, pull D+ low to force the host
+    // to enumerate the device again after a reset
+    {
+        let _dp = Output::new(dp.reborrow(), Level::Low, Speed::Low);
+        Timer::after_millis(10).await;
+    }
+
+    let driver = Driver::new(usb, Irqs, dp, dm);
+
+    // Same serial as in the shell status, the host tells units apart by it
+    let mut serial = String::<8>::new();
+    write!(serial, "{:08x}", identity::serial()).unwrap();
+
+    let mut config = embassy_usb::Config::new(VID, PID);
+    config.manufacturer = Some("heat-dooRS");
+    config.product = Some("heat-dooRS controller");
+    config.serial_number = Some(&serial);
+    config.max_power = 100;
+    config.max_packet_size_0 = MAX_PACKET_SIZE as u8;
+
+    let mut config_descriptor = [0u8; 256];
+    let mut bos_descriptor = [0u8; 256];
+    let mut control_buf = [0u8; 64];
+    let mut state = State::new();
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut [],
+        &mut control_buf,
+    );
+    let mut class = CdcAcmClass::new(&mut builder, &mut state, MAX_PACKET_SIZE as u16);
+    let mut device = builder.build();
+
+    info!("Starting USB CDC-ACM shell");
+
+    let shell_loop = async {
+        loop {
+            class.wait_connection().await;
+            info!("USB: terminal connected");
+            let _ = session(&mut class).await;
+            info!("USB: terminal disconnected");
+        }
+    };
+
+    join(device.run(), shell_loop).await;
+}
@@ -0,0 +1,8 @@
+//! Build information embedded by build.rs.
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("GIT_HASH");
+/// UTC build time as `YYYY-MM-DD HH:MM`
+pub const BUILD_TIME: &str = env!("BUILD_TIME");
+/// Enabled cargo features, space separated
+pub const FEATURES: &str = env!("FEATURES");
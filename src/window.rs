@@ -0,0 +1,37 @@
+//! Door/window reed contact on PB5, closed to ground while the window is shut.
+//!
+//! When the window stays open for [`OPEN_DELAY`] the motor control closes the
+//! valve and pauses the regulation, it resumes as soon as the window closes.
+
+use embassy_executor::task;
+use embassy_stm32::exti::ExtiInput;
+use embassy_time::{Duration, Timer, with_timeout};
+
+use crate::fmt::info;
+use crate::state;
+
+const OPEN_DELAY: Duration = Duration::from_secs(60);
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+#[task]
+pub async fn window(mut contact: ExtiInput<'static>) {
+    info!("Starting window contact");
+    loop {
+        contact.wait_for_high().await;
+        info!("Window opened");
+
+        if with_timeout(OPEN_DELAY, contact.wait_for_low())
+            .await
+            .is_err()
+        {
+            info!("Window still open, pausing heating");
+            state::update(|s| s.window_open = true);
+            contact.wait_for_low().await;
+            info!("Window closed, resuming heating");
+            state::update(|s| s.window_open = false);
+        }
+
+        // Ignore the contact bouncing when the window closes
+        Timer::after(DEBOUNCE).await;
+    }
+}
@@ -0,0 +1,142 @@
+"""Send a firmware image to a controller built with the `iap` feature.
+
+The update request and its responses are link layer frames (see
+src/link.rs), the image itself is sent with XMODEM-1K.
+
+Usage: python3 tools/iap_update.py /dev/ttyUSB0 heat-dooRS.bin
+Requires pyserial.
+"""
+
+import struct
+import sys
+import zlib
+
+import serial
+
+BAUDRATE = 115200
+
+START = 0x7E
+ESCAPE = 0x7D
+ESCAPE_XOR = 0x20
+
+REQUEST_UPDATE = 0x01
+RESPONSE_READY = 0x81
+RESPONSE_DONE = 0x82
+RESULTS = {
+    0: "ok",
+    1: "invalid request",
+    2: "staging erase failed",
+    3: "transfer failed",
+    4: "image incomplete",
+    5: "CRC mismatch",
+    6: "record write failed",
+}
+
+STX = 0x02
+EOT = 0x04
+ACK = 0x06
+NAK = 0x15
+CAN = 0x18
+CRC_MODE = ord("C")
+BLOCK_SIZE = 1024
+MAX_RETRIES = 10
+
+
+def crc16(data: bytes) -> int:
+    crc = 0
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x1021) if crc & 0x8000 else crc << 1
+            crc &= 0xFFFF
+    return crc
+
+
+def frame(payload: bytes) -> bytes:
+    body = bytes([len(payload)]) + payload
+    body += struct.pack(">H", crc16(body))
+    out = bytearray([START])
+    for byte in body:
+        if byte in (START, ESCAPE):
+            out += bytes([ESCAPE, byte ^ ESCAPE_XOR])
+        else:
+            out.append(byte)
+    return bytes(out)
+
+
+def read_frame(port: serial.Serial) -> bytes:
+    body = None
+    escaped = False
+    while True:
+        data = port.read(1)
+        if not data:
+            sys.exit("timeout waiting for the controller")
+        byte = data[0]
+        if byte == START:
+            body = bytearray()
+            escaped = False
+        elif body is None:
+            continue
+        elif byte == ESCAPE:
+            escaped = True
+        else:
+            body.append(byte ^ ESCAPE_XOR if escaped else byte)
+            escaped = False
+            if len(body) >= 3 and len(body) == body[0] + 3:
+                if crc16(body[:-2]) == struct.unpack(">H", body[-2:])[0]:
+                    return bytes(body[1:-2])
+                body = None
+
+
+def send_xmodem(port: serial.Serial, image: bytes) -> None:
+    while port.read(1) != bytes([CRC_MODE]):
+        pass
+
+    for number, offset in enumerate(range(0, len(image), BLOCK_SIZE), start=1):
+        data = image[offset : offset + BLOCK_SIZE].ljust(BLOCK_SIZE, b"\xff")
+        block = bytes([STX, number & 0xFF, ~number & 0xFF]) + data
+        block += struct.pack(">H", crc16(data))
+        for _ in range(MAX_RETRIES):
+            port.write(block)
+            reply = port.read(1)
+            if reply == bytes([ACK]):
+                break
+            if reply == bytes([CAN]):
+                sys.exit("transfer cancelled by the controller")
+        else:
+            sys.exit(f"block {number} not acknowledged")
+        print(f"\r{min(offset + BLOCK_SIZE, len(image))}/{len(image)} bytes", end="")
+
+    print()
+    port.write(bytes([EOT]))
+    if port.read(1) != bytes([ACK]):
+        sys.exit("end of transfer not acknowledged")
+
+
+def main() -> None:
+    if len(sys.argv) != 3:
+        sys.exit(__doc__)
+
+    with open(sys.argv[2], "rb") as file:
+        image = file.read()
+    if len(image) % 2:
+        image += b"\xff"
+
+    with serial.Serial(sys.argv[1], BAUDRATE, timeout=10) as port:
+        request = struct.pack("<BII", REQUEST_UPDATE, len(image), zlib.crc32(image))
+        port.write(frame(request))
+
+        response = read_frame(port)
+        if response != bytes([RESPONSE_READY, 0]):
+            sys.exit(f"update refused: {RESULTS.get(response[-1], response.hex())}")
+
+        send_xmodem(port, image)
+
+        response = read_frame(port)
+        if response[0] != RESPONSE_DONE or response[1] != 0:
+            sys.exit(f"update failed: {RESULTS.get(response[-1], response.hex())}")
+        print("image staged, the controller restarts to install it")
+
+
+if __name__ == "__main__":
+    main()
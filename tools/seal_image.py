@@ -0,0 +1,48 @@
+"""Fill in the CRC of the footer the linker appends to the firmware image.
+
+The CRC is the one computed by the STM32 CRC unit over the image words:
+polynomial 0x04C11DB7, initial value 0xFFFFFFFF, no reflection.
+
+Usage: python3 tools/seal_image.py heat-dooRS.bin
+"""
+
+import struct
+import sys
+
+POLYNOMIAL = 0x04C11DB7
+
+
+def stm32_crc(data: bytes) -> int:
+    crc = 0xFFFFFFFF
+    for (word,) in struct.iter_unpack("<I", data):
+        crc ^= word
+        for _ in range(32):
+            if crc & 0x80000000:
+                crc = ((crc << 1) ^ POLYNOMIAL) & 0xFFFFFFFF
+            else:
+                crc = (crc << 1) & 0xFFFFFFFF
+    return crc
+
+
+def main() -> None:
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+
+    path = sys.argv[1]
+    with open(path, "rb") as file:
+        image = bytearray(file.read())
+
+    length, _ = struct.unpack_from("<II", image, len(image) - 8)
+    if length != len(image) - 8:
+        sys.exit(f"{path}: no image footer at the end of the binary")
+
+    crc = stm32_crc(image[:length])
+    struct.pack_into("<I", image, length + 4, crc)
+    with open(path, "wb") as file:
+        file.write(image)
+
+    print(f"{path}: {length} bytes, CRC {crc:08x}")
+
+
+if __name__ == "__main__":
+    main()
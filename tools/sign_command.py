@@ -0,0 +1,43 @@
+"""Authenticate a state-changing command for firmware built with `auth`.
+
+Prints the shell line for a text command, or the counter and tag to append
+to a binary BLE/LoRa payload when the command is given as hex with --hex.
+The counter has to be higher than the last one the device accepted.
+
+Usage: python3 tools/sign_command.py KEY COUNTER "setpoint 55"
+       python3 tools/sign_command.py KEY COUNTER --hex 03b801
+"""
+
+import hashlib
+import hmac
+import struct
+import sys
+
+TAG_LEN = 16
+
+
+def tag(key: bytes, counter: int, command: bytes) -> bytes:
+    message = struct.pack("<I", counter) + command
+    return hmac.new(key, message, hashlib.sha256).digest()[:TAG_LEN]
+
+
+def main() -> None:
+    args = sys.argv[1:]
+    binary = "--hex" in args
+    if binary:
+        args.remove("--hex")
+    if len(args) != 3:
+        sys.exit(__doc__)
+
+    key = bytes.fromhex(args[0])
+    counter = int(args[1])
+    if binary:
+        command = bytes.fromhex(args[2])
+        print((struct.pack("<I", counter) + tag(key, counter, command)).hex())
+    else:
+        command = args[2].encode()
+        print(f"{args[2]} {counter} {tag(key, counter, command).hex()}")
+
+
+if __name__ == "__main__":
+    main()